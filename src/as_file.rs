@@ -0,0 +1,128 @@
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+
+use serde::de::{self, Deserialize, Deserializer, Visitor};
+use serde::ser::{Serialize, Serializer};
+
+mod private {
+    pub trait Sealed {}
+}
+
+/// An encoding for values stored by [`AsFile`]
+pub trait LeafFormat: private::Sealed {
+    /// The newtype struct name [`AsFile`] serializes with, which the serializer and deserializer
+    /// recognize to switch to this format
+    #[doc(hidden)]
+    const NEWTYPE_NAME: &'static str;
+}
+
+/// Stores [`AsFile`] values as JSON documents
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Json;
+
+impl private::Sealed for Json {}
+
+impl LeafFormat for Json {
+    const NEWTYPE_NAME: &'static str = "$serde_fs::AsFile<Json>";
+}
+
+/// Stores the wrapped value as a single leaf file encoded with `F`, instead of as a directory
+/// tree.
+///
+/// This is the explicit form of naming a field `json...`: it works for any field name, at any
+/// depth, and reads the same in the type as it does on disk. With other serde formats the wrapper
+/// is transparent and `T` is (de)serialized as usual.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct AsFile<F, T> {
+    value: T,
+    format: PhantomData<F>,
+}
+
+impl<F, T> AsFile<F, T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            value,
+            format: PhantomData,
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+impl<F, T> From<T> for AsFile<F, T> {
+    fn from(value: T) -> Self {
+        Self::new(value)
+    }
+}
+
+impl<F, T> Deref for AsFile<F, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<F, T> DerefMut for AsFile<F, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+impl<F, T> Serialize for AsFile<F, T>
+where
+    F: LeafFormat,
+    T: Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_newtype_struct(F::NEWTYPE_NAME, &self.value)
+    }
+}
+
+impl<'de, F, T> Deserialize<'de> for AsFile<F, T>
+where
+    F: LeafFormat,
+    T: Deserialize<'de>,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct AsFileVisitor<F, T>(PhantomData<(F, T)>);
+
+        impl<'de, F, T> Visitor<'de> for AsFileVisitor<F, T>
+        where
+            T: Deserialize<'de>,
+        {
+            type Value = AsFile<F, T>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a value stored as a file")
+            }
+
+            fn visit_newtype_struct<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+            where
+                D: Deserializer<'de>,
+            {
+                T::deserialize(deserializer).map(AsFile::new)
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: de::SeqAccess<'de>,
+            {
+                seq.next_element()?
+                    .map(AsFile::new)
+                    .ok_or_else(|| de::Error::invalid_length(0, &self))
+            }
+        }
+
+        deserializer.deserialize_newtype_struct(F::NEWTYPE_NAME, AsFileVisitor(PhantomData))
+    }
+}
@@ -0,0 +1,167 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use serde::Deserialize;
+
+use crate::de::{Deserializer, Result};
+use crate::vfs::{EntryKind, FileSystem, StdFs};
+
+/// Leaf contents remembered across reads of the same tree, so that reloading a tree which has
+/// not changed costs one stat per leaf instead of one read.
+///
+/// A leaf is read again once its modification time changes. Writes landing within the
+/// resolution of the file system's timestamps can go unnoticed, so this suits trees edited by
+/// hand or deployed as a whole, not ones rewritten many times a second
+#[derive(Debug, Default)]
+pub struct LeafCache {
+    leaves: RefCell<HashMap<PathBuf, (SystemTime, Vec<u8>)>>,
+}
+
+impl LeafCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Deserializes the tree at `path` like [`from_fs`](crate::from_fs), reading only the leaves
+    /// that changed since this cache last saw them
+    pub fn load<T>(&self, path: impl AsRef<Path>) -> Result<T>
+    where
+        T: for<'de> Deserialize<'de>,
+    {
+        let mut deserializer = Deserializer::with_fs(self.fs(StdFs), path);
+        T::deserialize(&mut deserializer)
+    }
+
+    /// Wraps `fs` so that the files read through it go through this cache
+    pub fn fs<F: FileSystem>(&self, fs: F) -> CachedFs<'_, F> {
+        CachedFs { fs, cache: self }
+    }
+
+    /// Forgets every remembered leaf
+    pub fn clear(&self) {
+        self.leaves.borrow_mut().clear();
+    }
+}
+
+/// A [`FileSystem`] reading files through a [`LeafCache`]. Files whose modification time is
+/// unknown are always read
+#[derive(Debug, Clone)]
+pub struct CachedFs<'a, F> {
+    fs: F,
+    cache: &'a LeafCache,
+}
+
+impl<F: FileSystem> FileSystem for CachedFs<'_, F> {
+    fn metadata(&self, path: &Path) -> io::Result<EntryKind> {
+        self.fs.metadata(path)
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let Ok(modified) = self.fs.modified(path) else {
+            return self.fs.read(path);
+        };
+        if let Some((cached_at, data)) = self.cache.leaves.borrow().get(path) {
+            if *cached_at == modified {
+                return Ok(data.clone());
+            }
+        }
+        let data = self.fs.read(path)?;
+        self.cache
+            .leaves
+            .borrow_mut()
+            .insert(path.to_owned(), (modified, data.clone()));
+        Ok(data)
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<OsString>> {
+        self.fs.read_dir(path)
+    }
+
+    fn modified(&self, path: &Path) -> io::Result<SystemTime> {
+        self.fs.modified(path)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::time::Duration;
+
+    use super::*;
+
+    /// Counts the files successfully read from the real file system
+    struct CountingFs<'a>(&'a Cell<usize>);
+
+    impl FileSystem for CountingFs<'_> {
+        fn metadata(&self, path: &Path) -> io::Result<EntryKind> {
+            StdFs.metadata(path)
+        }
+
+        fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+            let data = StdFs.read(path)?;
+            self.0.set(self.0.get() + 1);
+            Ok(data)
+        }
+
+        fn read_dir(&self, path: &Path) -> io::Result<Vec<OsString>> {
+            StdFs.read_dir(path)
+        }
+
+        fn modified(&self, path: &Path) -> io::Result<SystemTime> {
+            StdFs.modified(path)
+        }
+    }
+
+    #[test]
+    fn test_leaf_cache() {
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Config {
+            name: String,
+            port: u16,
+            hosts: Vec<String>,
+        }
+
+        let test_dir = "./.test-leaf-cache";
+        let _ = std::fs::remove_dir_all(test_dir);
+        for (leaf, data) in [("name", "app"), ("port", "80"), ("hosts/0", "a")] {
+            let path = Path::new(test_dir).join(leaf);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, data).unwrap();
+        }
+
+        let cache = LeafCache::new();
+        let reads = Cell::new(0);
+        let load = || {
+            let fs = cache.fs(CountingFs(&reads));
+            Config::deserialize(&mut Deserializer::with_fs(fs, test_dir)).unwrap()
+        };
+
+        let first = load();
+        assert_eq!(reads.get(), 3);
+        let second = load();
+        assert_eq!(reads.get(), 3);
+        assert_eq!(first, second);
+
+        let port = format!("{}/port", test_dir);
+        std::fs::write(&port, "8080").unwrap();
+        let later = std::fs::metadata(&port).unwrap().modified().unwrap() + Duration::from_secs(1);
+        std::fs::File::options()
+            .write(true)
+            .open(&port)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        let third = load();
+        assert_eq!(reads.get(), 4);
+        assert_eq!(third.port, 8080);
+        assert_eq!(third.name, "app");
+
+        let _ = std::fs::remove_dir_all(test_dir);
+    }
+}
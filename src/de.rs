@@ -1,6 +1,8 @@
-use std::fs;
-use std::num::{ParseFloatError, ParseIntError};
-use std::path::{Path, PathBuf};
+use std::borrow::Cow;
+use std::ffi::OsString;
+use std::fmt;
+use std::io::Cursor;
+use std::path::{Component, Path, PathBuf};
 use std::str::FromStr;
 
 use serde::de::value::StringDeserializer;
@@ -10,16 +12,194 @@ use serde::de::{
 };
 use serde::Deserialize;
 
+use crate::as_file::{Json, LeafFormat};
 use crate::error::DeError;
+use crate::ser::{
+    BytesRepr, EnumRepr, LeafType, OptionRepr, UnitRepr, GIT_ATTRIBUTES, GIT_IGNORE, INDEX_FILE,
+    OBJECTS_DIR,
+};
+use crate::share::{load_manifest, SHARED_MANIFEST};
+use crate::temp::is_temp_name;
+use crate::version::{FormatVersion, VERSION_MARKER};
+use crate::vfs::{EntryKind, FileSystem, StdFs};
 
 type Error = DeError;
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// A file naming another tree to overlay onto the directory holding it, read when
+/// [`Deserializer::follow_includes`] is set
+pub const INCLUDE_FILE: &str = ".include";
+
+/// Starts text files saved by some editors, see [`Deserializer::strip_bom`]
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// How many includes deep a path may be before it is taken for a cycle
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+type JsonDeserializer = serde_json::Deserializer<serde_json::de::IoRead<Cursor<Vec<u8>>>>;
+
+/// The integer types leaves are parsed into, with the range named by
+/// [`DeError::IntOutOfRange`]
+trait Integer: FromStr + fmt::Display {
+    const MIN: Self;
+    const MAX: Self;
+}
+
+macro_rules! impl_integer {
+    ($($ty:ty)*) => {
+        $(
+            impl Integer for $ty {
+                const MIN: Self = <$ty>::MIN;
+                const MAX: Self = <$ty>::MAX;
+            }
+        )*
+    };
+}
+
+impl_integer!(i8 i16 i32 i64 u8 u16 u32 u64);
+
+/// The float types leaves are parsed into, which can also be read from the bit patterns written
+/// by [`FloatPolicy::EncodeBits`](crate::FloatPolicy::EncodeBits)
+trait Float: FromStr {
+    /// Parses `hex`, without its `0x` prefix, as the bits of `Self`
+    fn from_hex_bits(hex: &str) -> Option<Self>;
+}
+
+macro_rules! impl_float {
+    ($($ty:ty => $bits:ty),*) => {
+        $(
+            impl Float for $ty {
+                fn from_hex_bits(hex: &str) -> Option<Self> {
+                    let digits = 2 * std::mem::size_of::<$bits>();
+                    if hex.len() != digits || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+                        return None;
+                    }
+                    <$bits>::from_str_radix(hex, 16).ok().map(<$ty>::from_bits)
+                }
+            }
+        )*
+    };
+}
+
+impl_float!(f32 => u32, f64 => u64);
+
+/// The words read as bools by [`Deserializer::lenient_bools`], compared ignoring ASCII case
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BoolTokens {
+    truthy: Vec<String>,
+    falsy: Vec<String>,
+}
+
+impl BoolTokens {
+    pub fn new<S: Into<String>>(
+        truthy: impl IntoIterator<Item = S>,
+        falsy: impl IntoIterator<Item = S>,
+    ) -> Self {
+        Self {
+            truthy: truthy.into_iter().map(Into::into).collect(),
+            falsy: falsy.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    fn parse(&self, s: &str) -> Option<bool> {
+        let matches = |tokens: &[String]| tokens.iter().any(|t| t.eq_ignore_ascii_case(s));
+        if matches(&self.truthy) {
+            Some(true)
+        } else if matches(&self.falsy) {
+            Some(false)
+        } else {
+            None
+        }
+    }
+}
+
+impl Default for BoolTokens {
+    /// `true`, `yes`, `on`, `1` and `false`, `no`, `off`, `0`
+    fn default() -> Self {
+        Self::new(["true", "yes", "on", "1"], ["false", "no", "off", "0"])
+    }
+}
+
+/// The options of a [`Deserializer`], as set by its builder methods. See the method of the same
+/// name for what each does
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeConfig {
+    pub allow_underscores: bool,
+    pub lenient_ints: bool,
+    pub ordered_maps: bool,
+    /// Extra spellings accepted for bools, see [`Deserializer::lenient_bools`]
+    pub bool_tokens: Option<BoolTokens>,
+    pub infer_types: bool,
+    pub option_repr: OptionRepr,
+    pub unit_repr: UnitRepr,
+    pub enum_repr: EnumRepr,
+    pub bytes_repr: BytesRepr,
+    pub typed_leaves: bool,
+    pub ignore_temp_entries: bool,
+    pub ignore_index_files: bool,
+    pub padded_int_keys: bool,
+    pub seq_base: usize,
+    pub relative_error_paths: bool,
+    /// Fill char trimmed from number leaves, see [`Deserializer::padded_numbers`]
+    pub number_fill: Option<char>,
+    pub max_map_entries: Option<usize>,
+    pub max_seq_len: Option<usize>,
+    /// Where included trees must live, see [`Deserializer::follow_includes`]
+    pub include_root: Option<PathBuf>,
+    /// Prefix of the variables overriding leaves, see [`Deserializer::env_overrides`]
+    pub env_prefix: Option<String>,
+    /// See [`Deserializer::collapse_below_depth`]
+    pub collapse_below_depth: Option<usize>,
+    pub strip_bom: bool,
+    pub lowercase_keys: bool,
+}
+
+impl Default for DeConfig {
+    fn default() -> Self {
+        Self {
+            allow_underscores: false,
+            lenient_ints: false,
+            ordered_maps: false,
+            bool_tokens: None,
+            infer_types: false,
+            option_repr: OptionRepr::default(),
+            unit_repr: UnitRepr::default(),
+            enum_repr: EnumRepr::default(),
+            bytes_repr: BytesRepr::default(),
+            typed_leaves: false,
+            ignore_temp_entries: true,
+            ignore_index_files: true,
+            padded_int_keys: false,
+            seq_base: 0,
+            relative_error_paths: false,
+            number_fill: None,
+            max_map_entries: None,
+            max_seq_len: None,
+            include_root: None,
+            env_prefix: None,
+            collapse_below_depth: None,
+            strip_bom: true,
+            lowercase_keys: false,
+        }
+    }
+}
+
 #[derive(Debug)]
-pub struct Deserializer {
+pub struct Deserializer<F = StdFs> {
+    /// Where the tree is read from
+    fs: F,
     /// The current path this serializer is at
     path: PathBuf,
+    /// The path deserialization started at
+    root: PathBuf,
     expect_json: bool,
+    /// Layout version of the tree being read
+    version: FormatVersion,
+    config: DeConfig,
+    /// Leaf errors recovered from, when validating with [`validate_fs`]
+    errors: Option<Vec<DeError>>,
+    /// Directories written by `Serializer::share_subtrees`, with the directory to read instead
+    shared: Vec<(PathBuf, PathBuf)>,
 }
 
 // By convention, the public API of a Serde deserializer is one or more
@@ -32,17 +212,467 @@ where
     T: Deserialize<'a>,
 {
     let mut deserializer = Deserializer::from_fs(s);
-    Ok(T::deserialize(&mut deserializer)?)
+    T::deserialize(&mut deserializer)
+}
+
+/// Checks that the tree at `path` can be read as a `T`, reporting every leaf that cannot be
+/// parsed rather than only the first.
+///
+/// Leaves that fail to parse are read as their type's default, so that the rest of the tree is
+/// still checked. Errors that leave nothing to continue with, like a missing field or a file
+/// where a directory is expected, end the validation and are reported last
+pub fn validate_fs<T>(path: impl AsRef<Path>) -> std::result::Result<(), Vec<DeError>>
+where
+    T: for<'de> Deserialize<'de>,
+{
+    let mut deserializer = Deserializer::from_fs(path);
+    deserializer.errors = Some(Vec::new());
+    let result = T::deserialize(&mut deserializer);
+    let mut errors = deserializer.errors.take().unwrap_or_default();
+    errors.extend(result.err());
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// The shape of the tree at a path, see [`probe_fs`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeKind {
+    /// A file holding a single value
+    Leaf,
+    /// A directory whose entries are keys. Empty directories are maps, since an empty sequence
+    /// looks the same
+    Map,
+    /// A directory holding exactly the entries `0..n`
+    Seq,
+}
+
+/// Returns whether the tree at `path` is a leaf, a map or a sequence, so callers reading
+/// dynamically can pick a target type before deserializing
+pub fn probe_fs(path: impl AsRef<Path>) -> Result<NodeKind> {
+    let deserializer = Deserializer::from_fs(path);
+    if deserializer.points_to_file()? {
+        Ok(NodeKind::Leaf)
+    } else if deserializer.is_sequence_dir()? {
+        Ok(NodeKind::Seq)
+    } else {
+        Ok(NodeKind::Map)
+    }
+}
+
+/// Clones the options and the current position, so the clone reads independently from here.
+/// Errors recovered while validating are not copied, a clone collects its own
+impl<F: Clone> Clone for Deserializer<F> {
+    fn clone(&self) -> Self {
+        Deserializer {
+            fs: self.fs.clone(),
+            path: self.path.clone(),
+            root: self.root.clone(),
+            expect_json: self.expect_json,
+            version: self.version,
+            config: self.config.clone(),
+            errors: self.errors.as_ref().map(|_| Vec::new()),
+            shared: self.shared.clone(),
+        }
+    }
 }
 
 impl Deserializer {
     pub fn from_fs(path: impl AsRef<Path>) -> Self {
+        Deserializer::with_fs(StdFs, path)
+    }
+}
+
+impl<F: FileSystem> Deserializer<F> {
+    /// Creates a deserializer reading the tree at `path` from `fs`
+    pub fn with_fs(fs: F, path: impl AsRef<Path>) -> Self {
+        Self::with_config(fs, path, DeConfig::default())
+    }
+
+    /// Creates a deserializer with all options given at once, the same as calling each builder
+    /// method on a new deserializer
+    pub fn with_config(fs: F, path: impl AsRef<Path>, config: DeConfig) -> Self {
+        let version = FormatVersion::detect_in(&fs, path.as_ref());
+        let shared = load_manifest(&fs, path.as_ref());
         Deserializer {
+            fs,
             path: PathBuf::from(path.as_ref()),
+            root: PathBuf::from(path.as_ref()),
             expect_json: false,
+            version,
+            config,
+            errors: None,
+            shared,
+        }
+    }
+
+    /// Returns the options this deserializer reads with
+    pub fn config(&self) -> &DeConfig {
+        &self.config
+    }
+
+    /// Returns the path deserialization started at
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Returns the path this deserializer is currently reading from
+    pub fn at_path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Re-roots this deserializer at `path`, so that it can be reused to read another tree
+    pub fn reset(&mut self, path: impl AsRef<Path>) {
+        self.path = PathBuf::from(path.as_ref());
+        self.root = self.path.clone();
+        self.expect_json = false;
+        self.version = FormatVersion::detect_in(&self.fs, path.as_ref());
+        self.shared = load_manifest(&self.fs, path.as_ref());
+    }
+
+    /// Overrides the layout version detected from the tree's version marker
+    pub fn format_version(mut self, version: FormatVersion) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Accepts `_` digit separators in numeric leaves and keys, like in Rust literals, so that
+    /// hand edited values such as `1_000_000` can be read.
+    ///
+    /// Off by default, since a custom format may give `_` a meaning of its own
+    pub fn allow_underscores(mut self, allow: bool) -> Self {
+        self.config.allow_underscores = allow;
+        self
+    }
+
+    /// Accepts integers written by other tools: surrounding whitespace, a zero fraction like
+    /// `42.0`, and `0x`, `0o` and `0b` radix prefixes.
+    ///
+    /// Off by default, so that a float landing in an integer field is an error
+    pub fn lenient_ints(mut self, lenient: bool) -> Self {
+        self.config.lenient_ints = lenient;
+        self
+    }
+
+    /// Guesses the type of leaves read without a type hint, like the fields of
+    /// `#[serde(tag = "...")]` enums and `#[serde(flatten)]` structs: canonical bools and numbers
+    /// are read as such instead of as strings.
+    ///
+    /// Off by default, since a string field holding something like `42` can then no longer be
+    /// read through those paths
+    pub fn infer_types(mut self, infer: bool) -> Self {
+        self.config.infer_types = infer;
+        self
+    }
+
+    /// Reads maps written with [`Serializer::ordered_maps`](crate::Serializer::ordered_maps),
+    /// yielding their entries in the order they were written, with the index prefix removed
+    pub fn ordered_maps(mut self, ordered: bool) -> Self {
+        self.config.ordered_maps = ordered;
+        self
+    }
+
+    /// Reads bools written by other tools or by hand, like `yes` or `True`, using `tokens`.
+    ///
+    /// By default only the exact `true` and `false` the serializer writes are accepted
+    pub fn lenient_bools(mut self, tokens: BoolTokens) -> Self {
+        self.config.bool_tokens = Some(tokens);
+        self
+    }
+
+    /// Sets how `None` was written, see [`Serializer::option_repr`](crate::Serializer::option_repr).
+    ///
+    /// A missing file is read as `None` whatever the repr
+    pub fn option_repr(mut self, repr: OptionRepr) -> Self {
+        self.config.option_repr = repr;
+        self
+    }
+
+    /// Sets how units were written, see [`Serializer::unit_repr`](crate::Serializer::unit_repr).
+    ///
+    /// A missing file is read as a unit whatever the repr
+    pub fn unit_repr(mut self, repr: UnitRepr) -> Self {
+        self.config.unit_repr = repr;
+        self
+    }
+
+    /// Sets how enum variants were written, see
+    /// [`Serializer::enum_repr`](crate::Serializer::enum_repr)
+    pub fn enum_repr(mut self, repr: EnumRepr) -> Self {
+        self.config.enum_repr = repr;
+        self
+    }
+
+    /// Sets how byte buffers were written, see
+    /// [`Serializer::bytes_repr`](crate::Serializer::bytes_repr)
+    pub fn bytes_repr(mut self, repr: BytesRepr) -> Self {
+        self.config.bytes_repr = repr;
+        self
+    }
+
+    /// Reads trees written with
+    /// [`Serializer::typed_leaves`](crate::Serializer::typed_leaves): the type prefix is
+    /// stripped from leaves, and reads without a type hint use it to pick the type
+    pub fn typed_leaves(mut self, typed: bool) -> Self {
+        self.config.typed_leaves = typed;
+        self
+    }
+
+    /// Skips the temporary entries interrupted writes leave behind, named `.<name>.tmp.<suffix>`.
+    /// They can be removed with [`cleanup_fs`](crate::cleanup_fs).
+    ///
+    /// On by default. Turn it off to read maps with keys that happen to look like temporary
+    /// entries
+    pub fn ignore_temp_entries(mut self, ignore: bool) -> Self {
+        self.config.ignore_temp_entries = ignore;
+        self
+    }
+
+    /// Reads sequences whose first element is `base` instead of 0, like trees written with
+    /// [`Serializer::seq_base`](crate::Serializer::seq_base) or by scripts counting from 1
+    pub fn seq_base(mut self, base: usize) -> Self {
+        self.config.seq_base = base;
+        self
+    }
+
+    /// Skips the index files written by
+    /// [`Serializer::write_indexes`](crate::Serializer::write_indexes).
+    ///
+    /// On by default. Turn it off to read maps with a key named like the index
+    pub fn ignore_index_files(mut self, ignore: bool) -> Self {
+        self.config.ignore_index_files = ignore;
+        self
+    }
+
+    /// Reads integer map keys written with
+    /// [`Serializer::pad_int_keys`](crate::Serializer::pad_int_keys), by ignoring leading zeros
+    /// after the sign
+    pub fn padded_int_keys(mut self, padded: bool) -> Self {
+        self.config.padded_int_keys = padded;
+        self
+    }
+
+    /// Reads number leaves written with
+    /// [`Serializer::pad_numbers`](crate::Serializer::pad_numbers), by trimming `fill` from
+    /// both ends before parsing
+    pub fn padded_numbers(mut self, fill: char) -> Self {
+        self.config.number_fill = Some(fill);
+        self
+    }
+
+    /// Fails with [`DeError::TooManyEntries`] when a map directory holds more than `limit`
+    /// entries, before any of them is read. Unlimited by default; services reading untrusted
+    /// trees should set it
+    pub fn max_map_entries(mut self, limit: usize) -> Self {
+        self.config.max_map_entries = Some(limit);
+        self
+    }
+
+    /// Fails with [`DeError::TooManyEntries`] when a sequence holds more than `limit` elements.
+    /// Unlimited by default
+    pub fn max_seq_len(mut self, limit: usize) -> Self {
+        self.config.max_seq_len = Some(limit);
+        self
+    }
+
+    /// Makes errors report paths relative to the root this deserializer was created with, so
+    /// that messages do not reveal where the tree lives and are the same on every machine
+    pub fn relative_error_paths(mut self, relative: bool) -> Self {
+        self.config.relative_error_paths = relative;
+        self
+    }
+
+    /// Reads leaves from environment variables when they are set, so that a deployment can
+    /// override single values without editing the tree. The variable for a leaf is `prefix`
+    /// followed by each component of the leaf's path below the root, joined by `_` and
+    /// uppercased, with any char other than an ASCII letter or digit replaced by `_`: with a
+    /// prefix of `APP`, `database/url` is overridden by `APP_DATABASE_URL`.
+    ///
+    /// Only leaves present in the tree are looked up, and the variable is used as the whole
+    /// contents of the leaf, without a type prefix
+    pub fn env_overrides(mut self, prefix: impl Into<String>) -> Self {
+        self.config.env_prefix = Some(prefix.into());
+        self
+    }
+
+    /// Reads trees written with
+    /// [`Serializer::lowercase_keys`](crate::Serializer::lowercase_keys), matching enum map keys
+    /// to their variant ignoring case
+    pub fn lowercase_keys(mut self, lowercase: bool) -> Self {
+        self.config.lowercase_keys = lowercase;
+        self
+    }
+
+    /// Ignores the UTF-8 byte order mark that editors on Windows may start a file with, so that
+    /// hand edited leaves like `\u{FEFF}42` still parse. Raw byte buffers are read as they are.
+    ///
+    /// On by default
+    pub fn strip_bom(mut self, strip: bool) -> Self {
+        self.config.strip_bom = strip;
+        self
+    }
+
+    /// Reads values more than `depth` levels below the root from single JSON leaves, as written
+    /// by [`Serializer::collapse_below_depth`](crate::Serializer::collapse_below_depth)
+    pub fn collapse_below_depth(mut self, depth: usize) -> Self {
+        self.config.collapse_below_depth = Some(depth);
+        self
+    }
+
+    /// Overlays the tree named by a directory's [`INCLUDE_FILE`] onto that directory. Entries
+    /// of the directory itself take precedence, and the included tree is read only for what it
+    /// lacks, so a tree can override a few values of a shared base. Includes are resolved
+    /// relative to the directory holding them and may themselves include further trees.
+    ///
+    /// Every included path must lie within `allowed_root`, or reading fails with
+    /// [`DeError::IncludeOutsideRoot`], as does an `allowed_root` that cannot be resolved. The
+    /// check is done on the paths as written, made absolute against the current directory and with
+    /// `.` and `..` resolved, so symlinks inside `allowed_root` are trusted
+    pub fn follow_includes(mut self, allowed_root: impl AsRef<Path>) -> Self {
+        self.config.include_root = Some(allowed_root.as_ref().to_owned());
+        self
+    }
+
+    /// Returns where the current path is read from: itself, or the copy it shares its contents
+    /// with, see [`Serializer::share_subtrees`](crate::Serializer::share_subtrees)
+    fn source(&self) -> Cow<'_, Path> {
+        let mut source = Cow::Borrowed(self.path.as_path());
+        // the copy can itself hold shared directories. Copies always come before the directories
+        // sharing them in path order, so this ends
+        while let Some((dup, original)) =
+            self.shared.iter().find(|(dup, _)| source.starts_with(dup))
+        {
+            let rest = source.strip_prefix(dup).unwrap();
+            source = Cow::Owned(original.join(rest));
+        }
+        source
+    }
+
+    /// Returns the paths the current path is read from, highest precedence first. Without
+    /// [`Deserializer::follow_includes`] this is only [`Deserializer::source`]. With it, a file
+    /// shadows everything below it, while directories are merged
+    fn layers(&self) -> Result<Vec<PathBuf>> {
+        let source = self.source();
+        let (Some(allowed_root), Ok(relative)) =
+            (&self.config.include_root, source.strip_prefix(&self.root))
+        else {
+            return Ok(vec![source.into_owned()]);
+        };
+        let Some(allowed_root) = absolute(allowed_root) else {
+            return Err(Error::IncludeOutsideRoot(allowed_root.clone()));
+        };
+        let mut layers = self.with_includes(vec![self.root.clone()], &allowed_root)?;
+        for component in relative.components() {
+            let mut children = Vec::new();
+            for layer in &layers {
+                let child = layer.join(component);
+                match self.fs.metadata(&child) {
+                    Ok(EntryKind::File) if children.is_empty() => return Ok(vec![child]),
+                    Ok(EntryKind::Dir) => children.push(child),
+                    _ => {}
+                }
+            }
+            if children.is_empty() {
+                // nothing to merge, let the caller report the local path as missing
+                return Ok(vec![source.into_owned()]);
+            }
+            layers = self.with_includes(children, &allowed_root)?;
+        }
+        Ok(layers)
+    }
+
+    /// Follows the includes of each directory in `dirs`, placing the included trees right after
+    /// the directory including them
+    fn with_includes(&self, dirs: Vec<PathBuf>, allowed_root: &Path) -> Result<Vec<PathBuf>> {
+        let mut layers = Vec::new();
+        for dir in dirs {
+            let mut dir = Some(dir);
+            let mut depth = 0;
+            while let Some(current) = dir.take() {
+                let include = current.join(INCLUDE_FILE);
+                if self.fs.metadata(&include).ok() == Some(EntryKind::File) {
+                    if depth == MAX_INCLUDE_DEPTH {
+                        return Err(Error::IncludeCycle(include));
+                    }
+                    let target = String::from_utf8(self.fs.read(&include)?)
+                        .map_err(|_| Error::InvalidUnicode)?;
+                    let target = current.join(target.trim());
+                    match absolute(&target) {
+                        Some(absolute) if absolute.starts_with(allowed_root) => {
+                            dir = Some(normalize(&target).unwrap_or(absolute))
+                        }
+                        _ => return Err(Error::IncludeOutsideRoot(include)),
+                    }
+                    depth += 1;
+                }
+                layers.push(current);
+            }
+        }
+        Ok(layers)
+    }
+
+    /// Reads the current file
+    fn read_file(&self) -> Result<Vec<u8>> {
+        Ok(self.fs.read(&self.layers()?[0])?)
+    }
+
+    /// Returns what the current path points at
+    fn kind(&self) -> Result<EntryKind> {
+        Ok(self.fs.metadata(&self.layers()?[0])?)
+    }
+
+    /// Lists the current directory, merged with any directories it includes
+    fn list_dir(&self) -> Result<Vec<OsString>> {
+        let mut layers = self.layers()?.into_iter();
+        let mut names = self.fs.read_dir(&layers.next().unwrap())?;
+        for layer in layers {
+            for name in self.fs.read_dir(&layer)? {
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+        }
+        Ok(names)
+    }
+
+    /// Returns the current path as reported in errors, see
+    /// [`Deserializer::relative_error_paths`]
+    fn error_path(&self) -> PathBuf {
+        match self.path.strip_prefix(&self.root) {
+            Ok(relative) if self.config.relative_error_paths => relative.to_owned(),
+            _ => self.path.clone(),
+        }
+    }
+
+    /// Records `result`'s error when validating and carries on with a placeholder value, so that
+    /// the leaves after it are checked too
+    fn recover<T: Default>(&mut self, result: Result<T>) -> Result<T> {
+        match (result, &mut self.errors) {
+            (Err(err), Some(errors)) => {
+                errors.push(err);
+                Ok(T::default())
+            }
+            (result, _) => result,
         }
     }
 
+    /// Returns true if `name` is an entry of the current directory that holds no user data
+    fn is_internal_entry(&self, name: &str) -> bool {
+        let internal = [
+            VERSION_MARKER,
+            OBJECTS_DIR,
+            GIT_ATTRIBUTES,
+            GIT_IGNORE,
+            SHARED_MANIFEST,
+        ];
+        (internal.contains(&name) && self.path == self.root)
+            || (self.config.include_root.is_some() && name == INCLUDE_FILE)
+            || (self.config.ignore_temp_entries && is_temp_name(name))
+            || (self.config.ignore_index_files && name == INDEX_FILE)
+    }
+
     fn push(&mut self, path: impl AsRef<Path>) {
         self.path.push(path);
     }
@@ -51,69 +681,327 @@ impl Deserializer {
         self.path.pop();
     }
 
-    fn read_bytes(&mut self) -> Result<Vec<u8>> {
-        Ok(fs::read(&self.path)?)
+    /// Reads the current leaf, without its type prefix if [`Deserializer::typed_leaves`] is set.
+    /// `text` leaves also lose a leading byte order mark, see [`Deserializer::strip_bom`]
+    fn read_bytes(&mut self, text: bool) -> Result<Vec<u8>> {
+        self.read_leaf(text).map(|(_, bytes)| bytes)
+    }
+
+    /// Like [`Deserializer::read_bytes`], but also returns the type the prefix named, if any
+    fn read_leaf(&mut self, text: bool) -> Result<(Option<LeafType>, Vec<u8>)> {
+        if let Some(value) = self.env_override() {
+            return Ok((None, value.into_bytes()));
+        }
+        let mut bytes = self.read_file()?;
+        if text {
+            bytes = self.without_bom(bytes);
+        }
+        let ty = self.leaf_type(&bytes);
+        if ty.is_some() {
+            bytes.remove(0);
+        }
+        Ok((ty, bytes))
+    }
+
+    /// Removes the UTF-8 byte order mark some editors start text files with, unless
+    /// [`Deserializer::strip_bom`] is off
+    fn without_bom(&self, mut bytes: Vec<u8>) -> Vec<u8> {
+        if self.config.strip_bom && bytes.starts_with(UTF8_BOM) {
+            bytes.drain(..UTF8_BOM.len());
+        }
+        bytes
+    }
+
+    /// Returns the environment variable overriding the current leaf, see
+    /// [`Deserializer::env_overrides`]
+    fn env_override(&self) -> Option<String> {
+        let prefix = self.config.env_prefix.as_ref()?;
+        let relative = self.path.strip_prefix(&self.root).ok()?;
+        let mut name = prefix.clone();
+        for component in relative.components() {
+            name.push('_');
+            let component = component.as_os_str().to_string_lossy();
+            name.extend(component.chars().map(|c| match c {
+                'a'..='z' | 'A'..='Z' | '0'..='9' => c.to_ascii_uppercase(),
+                _ => '_',
+            }));
+        }
+        std::env::var(name).ok()
+    }
+
+    /// Returns the type prefix of `leaf`, if [`Deserializer::typed_leaves`] is set
+    fn leaf_type(&self, leaf: &[u8]) -> Option<LeafType> {
+        leaf.first()
+            .filter(|_| self.config.typed_leaves)
+            .and_then(|&tag| LeafType::from_tag(tag))
+    }
+
+    /// Maps a variant read from the tree back to its name, see [`Deserializer::enum_repr`]
+    fn variant_name(&self, variant: String, variants: &'static [&'static str]) -> Result<String> {
+        match self.config.enum_repr {
+            EnumRepr::ByName => Ok(variant),
+            EnumRepr::ByIndex => variant
+                .parse::<usize>()
+                .ok()
+                .filter(|_| is_canonical_int(&variant))
+                .and_then(|index| variants.get(index))
+                .map(|name| name.to_string())
+                .ok_or_else(|| Error::UnknownVariantIndex(variant, self.error_path())),
+        }
+    }
+
+    /// Parses the file at the current path as an embedded JSON document.
+    ///
+    /// `f` forwards the deserialize call serde made on us to the JSON deserializer, so the JSON
+    /// is read with the same shape (seq, map, struct, ...) the caller expects
+    fn visit_json<T, J>(&self, f: J) -> Result<T>
+    where
+        J: FnOnce(&mut JsonDeserializer) -> serde_json::Result<T>,
+    {
+        if !self.points_to_file()? {
+            return Err(Error::ExpectedFile(self.error_path()));
+        }
+        let bytes = Cursor::new(self.without_bom(self.read_file()?));
+        let mut json_de = serde_json::Deserializer::from_reader(bytes);
+        let value = f(&mut json_de)?;
+        json_de.end()?;
+        Ok(value)
+    }
+
+    /// Returns true if the current path holds JSON: a `json*` struct field, or a value deeper
+    /// than [`Deserializer::collapse_below_depth`]
+    fn reads_json(&self) -> bool {
+        let depth = self
+            .path
+            .strip_prefix(&self.root)
+            .map_or(0, |p| p.components().count());
+        self.expect_json
+            || self
+                .config
+                .collapse_below_depth
+                .is_some_and(|max| depth > max)
+    }
+
+    /// Returns true if the file at the current path starts with a json object
+    fn holds_json_object(&self) -> Result<bool> {
+        let bytes = self.without_bom(self.read_file()?);
+        Ok(bytes.iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'{'))
     }
 
     /// Returns true if the current path points at a file
     fn points_to_file(&self) -> Result<bool> {
-        let metadata = fs::metadata(&self.path)?;
-        if metadata.is_symlink() {
-            Err(Error::EncounteredSymlink(self.path.clone()))
-        } else {
-            Ok(metadata.is_file())
-        }
+        Ok(self.kind()? == EntryKind::File)
+    }
+
+    /// Reads a byte buffer leaf, decoding it according to [`Deserializer::bytes_repr`]
+    fn read_byte_buf(&mut self) -> Result<Vec<u8>> {
+        let text = self.config.bytes_repr == BytesRepr::Hex;
+        let bytes = self.read_bytes(text)?;
+        self.decode_bytes(bytes)
     }
 
-    fn current_path_exists(&self) -> bool {
-        fs::metadata(&self.path).is_ok()
+    /// Decodes the contents of a bytes leaf, see [`Deserializer::bytes_repr`]
+    fn decode_bytes(&self, bytes: Vec<u8>) -> Result<Vec<u8>> {
+        match self.config.bytes_repr {
+            BytesRepr::Raw => Ok(bytes),
+            BytesRepr::Hex => decode_hex(&bytes).ok_or_else(|| {
+                Error::InvalidHex(String::from_utf8_lossy(&bytes).into(), self.error_path())
+            }),
+        }
     }
 
     fn read_string(&mut self) -> Result<String> {
-        Ok(String::from_utf8(self.read_bytes()?).map_err(|_| Error::InvalidUnicode)?)
+        String::from_utf8(self.read_bytes(true)?).map_err(|_| Error::InvalidUnicode)
+    }
+
+    /// Parses a char leaf, which must hold exactly one char
+    fn parse_char(&self, mut string: String) -> Result<char> {
+        if self.version == FormatVersion::V1 {
+            // V1 wrote chars padded with NUL bytes
+            string.truncate(string.trim_end_matches('\0').len());
+        }
+        let mut it = string.chars();
+        let c = it
+            .next()
+            .ok_or_else(|| Error::EmptyFile(self.error_path()))?;
+        if it.next().is_some() {
+            return Err(Error::InvalidChar(string, self.error_path()));
+        }
+        Ok(c)
+    }
+
+    /// Parses a bool leaf or key, see [`Deserializer::lenient_bools`]
+    fn parse_bool(&self, s: String) -> Result<bool> {
+        let parsed = match (&self.config.bool_tokens, s.as_str()) {
+            (_, "true") => Some(true),
+            (_, "false") => Some(false),
+            (Some(tokens), s) => tokens.parse(s),
+            (None, _) => None,
+        };
+        parsed.ok_or_else(|| Error::InvalidBool(s, self.error_path()))
     }
 
-    fn parse<T>(&mut self) -> Result<T>
+    /// Reads an integer leaf. See [`is_canonical_int`] for the accepted format
+    fn parse_int<T>(&mut self) -> Result<T>
     where
-        T: FromStr,
+        T: Integer,
+    {
+        let string = self.read_string()?;
+        self.parse_int_str(string)
+    }
+
+    /// Parses an integer from a leaf or key, coercing it first if [`Deserializer::lenient_ints`]
+    /// is set
+    fn parse_int_str<T>(&self, string: String) -> Result<T>
+    where
+        T: Integer,
+    {
+        let number = match coerce_int(&string).filter(|_| self.config.lenient_ints) {
+            Some(int) => self
+                .clean_number(int, is_canonical_int)
+                .map_err(|_| Error::ParseError(string.clone()))?,
+            None => self.clean_number(string.clone(), is_canonical_int)?,
+        };
+        // canonical integers only fail to parse when they do not fit in `T`
+        number.parse().map_err(|_| Error::IntOutOfRange {
+            value: string,
+            ty: std::any::type_name::<T>(),
+            min: T::MIN.to_string(),
+            max: T::MAX.to_string(),
+        })
+    }
+
+    /// Reads a float leaf. See [`is_canonical_float`] for the accepted format
+    fn parse_float<T>(&mut self) -> Result<T>
+    where
+        T: Float,
     {
         let string = self.read_string()?;
-        Ok(string.parse().map_err(|_| Error::ParseError(string))?)
+        self.parse_float_str(string)
+    }
+
+    /// Parses a float from a leaf, which may hold the hex bits of a non-finite value
+    fn parse_float_str<T>(&self, string: String) -> Result<T>
+    where
+        T: Float,
+    {
+        if let Some(hex) = string.strip_prefix("0x") {
+            return T::from_hex_bits(hex).ok_or(Error::ParseError(string));
+        }
+        self.parse_number(string, is_canonical_float)
+    }
+
+    /// Parses `string` if it is in the format `is_canonical` checks for, see
+    /// [`Deserializer::clean_number`]
+    fn parse_number<T>(&self, string: String, is_canonical: fn(&str) -> bool) -> Result<T>
+    where
+        T: FromStr,
+    {
+        let number = self.clean_number(string.clone(), is_canonical)?;
+        number.parse().map_err(|_| Error::ParseError(string))
+    }
+
+    /// Removes padding when [`Deserializer::padded_numbers`] is set and digit separators when
+    /// [`Deserializer::allow_underscores`] is set, and checks that what is left is in the format
+    /// `is_canonical` checks for
+    fn clean_number(&self, string: String, is_canonical: fn(&str) -> bool) -> Result<String> {
+        let string = match self.config.number_fill {
+            Some(fill) => string.trim_matches(fill).to_owned(),
+            None => string,
+        };
+        let number = if self.config.allow_underscores && string.contains('_') {
+            // Like in Rust literals, a separator cannot start the number or its fraction
+            if string.starts_with('_') || string.contains("-_") || string.contains("._") {
+                return Err(Error::ParseError(string));
+            }
+            string.replace('_', "")
+        } else {
+            string.clone()
+        };
+        if !is_canonical(&number) {
+            return Err(Error::ParseError(string));
+        }
+        Ok(number)
+    }
+
+    /// Returns true if the current directory is non empty and its entries are exactly `0..n`,
+    /// which is how sequences are written, counting from [`Deserializer::seq_base`]
+    fn is_sequence_dir(&self) -> Result<bool> {
+        self.is_sequence(&self.list_dir()?)
+    }
+
+    /// Like [`Deserializer::is_sequence_dir`], for entries that have already been listed
+    fn is_sequence(&self, names: &[OsString]) -> Result<bool> {
+        let mut indices = Vec::new();
+        for name in names {
+            let name = name.to_str().ok_or(Error::InvalidUnicode)?;
+            if self.is_internal_entry(name) {
+                continue;
+            }
+            match name.parse::<usize>() {
+                Ok(index) if is_canonical_int(name) => indices.push(index),
+                _ => return Ok(false),
+            }
+        }
+        indices.sort_unstable();
+        let base = self.config.seq_base;
+        Ok(!indices.is_empty() && (base..).zip(&indices).all(|(i, &index)| i == index))
+    }
+
+    /// Checks that a unit leaf, if there is one, holds the [`Deserializer::unit_repr`] sentinel
+    fn check_unit(&self) -> Result<()> {
+        let UnitRepr::SentinelFile(sentinel) = &self.config.unit_repr else {
+            return Ok(());
+        };
+        if !self.path_exists() {
+            return Ok(());
+        }
+        if !self.points_to_file()? {
+            return Err(Error::ExpectedFile(self.error_path()));
+        }
+        let contents = self.read_file()?;
+        if contents != sentinel.as_bytes() {
+            return Err(Error::ParseError(
+                String::from_utf8_lossy(&contents).into_owned(),
+            ));
+        }
+        Ok(())
     }
 
+    /// Returns true if the current path exists. Like [`Deserializer::points_to_file`] this
+    /// follows symlinks, so a dangling symlink counts as missing
     fn path_exists(&self) -> bool {
-        fs::metadata(&self.path).is_ok()
+        self.kind().is_ok()
     }
 
     /// Pushes the first dir entry found in `self.path` to path, and returs the name of the entry
     /// that was pushed
     fn push_first_dir_entry(&mut self) -> Result<String> {
-        for path in std::fs::read_dir(&self.path).unwrap() {
-            if let Ok(path) = path {
-                let name = path.file_name();
-                let name = name.to_str().ok_or_else(|| Error::InvalidUnicode)?;
-                self.push(name);
-                return Ok(name.to_owned());
+        for name in self.list_dir()? {
+            let name = name.to_str().ok_or(Error::InvalidUnicode)?;
+            if self.is_internal_entry(name) {
+                continue;
             }
+            self.push(name);
+            return Ok(name.to_owned());
         }
-        Err(Error::EmptyDirectory(self.path.clone()))
+        Err(Error::EmptyDirectory(self.error_path()))
     }
 }
 
-impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer {
+impl<'de, F: FileSystem> de::Deserializer<'de> for &mut Deserializer<F> {
     type Error = Error;
 
     fn deserialize_bool<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        let bytes = self.read_string()?;
-        let val = match bytes.as_str() {
-            "true" => true,
-            "false" => false,
-            a => return Err(Error::InvalidBool(a.to_owned(), self.path.clone()).into()),
-        };
-        visitor.visit_bool(val)
+        if self.reads_json() {
+            return self.visit_json(|de| de.deserialize_bool(visitor));
+        }
+        let string = self.read_string()?;
+        let value = self.parse_bool(string);
+        visitor.visit_bool(self.recover(value)?)
     }
 
     // The `parse_signed` function is generic over the integer type `T` so here
@@ -122,56 +1010,88 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer {
     where
         V: Visitor<'de>,
     {
-        visitor.visit_i8(self.parse()?)
+        if self.reads_json() {
+            return self.visit_json(|de| de.deserialize_i8(visitor));
+        }
+        let value = self.parse_int();
+        visitor.visit_i8(self.recover(value)?)
     }
 
     fn deserialize_i16<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        visitor.visit_i16(self.parse()?)
+        if self.reads_json() {
+            return self.visit_json(|de| de.deserialize_i16(visitor));
+        }
+        let value = self.parse_int();
+        visitor.visit_i16(self.recover(value)?)
     }
 
     fn deserialize_i32<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        visitor.visit_i32(self.parse()?)
+        if self.reads_json() {
+            return self.visit_json(|de| de.deserialize_i32(visitor));
+        }
+        let value = self.parse_int();
+        visitor.visit_i32(self.recover(value)?)
     }
 
     fn deserialize_i64<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        visitor.visit_i64(self.parse()?)
+        if self.reads_json() {
+            return self.visit_json(|de| de.deserialize_i64(visitor));
+        }
+        let value = self.parse_int();
+        visitor.visit_i64(self.recover(value)?)
     }
 
     fn deserialize_u8<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        visitor.visit_u8(self.parse()?)
+        if self.reads_json() {
+            return self.visit_json(|de| de.deserialize_u8(visitor));
+        }
+        let value = self.parse_int();
+        visitor.visit_u8(self.recover(value)?)
     }
 
     fn deserialize_u16<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        visitor.visit_u16(self.parse()?)
+        if self.reads_json() {
+            return self.visit_json(|de| de.deserialize_u16(visitor));
+        }
+        let value = self.parse_int();
+        visitor.visit_u16(self.recover(value)?)
     }
 
     fn deserialize_u32<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        visitor.visit_u32(self.parse()?)
+        if self.reads_json() {
+            return self.visit_json(|de| de.deserialize_u32(visitor));
+        }
+        let value = self.parse_int();
+        visitor.visit_u32(self.recover(value)?)
     }
 
     fn deserialize_u64<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        visitor.visit_u64(self.parse()?)
+        if self.reads_json() {
+            return self.visit_json(|de| de.deserialize_u64(visitor));
+        }
+        let value = self.parse_int();
+        visitor.visit_u64(self.recover(value)?)
     }
 
     // Float parsing is stupidly hard.
@@ -179,7 +1099,11 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer {
     where
         V: Visitor<'de>,
     {
-        visitor.visit_f32(self.parse()?)
+        if self.reads_json() {
+            return self.visit_json(|de| de.deserialize_f32(visitor));
+        }
+        let value = self.parse_float();
+        visitor.visit_f32(self.recover(value)?)
     }
 
     // Float parsing is stupidly hard.
@@ -187,7 +1111,11 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer {
     where
         V: Visitor<'de>,
     {
-        visitor.visit_f32(self.parse()?)
+        if self.reads_json() {
+            return self.visit_json(|de| de.deserialize_f64(visitor));
+        }
+        let value = self.parse_float();
+        visitor.visit_f64(self.recover(value)?)
     }
 
     // The `Serializer` implementation on the previous page serialized chars as
@@ -196,14 +1124,11 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer {
     where
         V: Visitor<'de>,
     {
+        if self.reads_json() {
+            return self.visit_json(|de| de.deserialize_char(visitor));
+        }
         let string = self.read_string()?;
-        let mut it = string.chars();
-        let c = it
-            .next()
-            .ok_or_else(|| Error::EmptyFile(self.path.clone()))?;
-
-        //XXX: We could be picky and return an error about trailing characters here
-        visitor.visit_char(c)
+        visitor.visit_char(self.parse_char(string)?)
     }
 
     // Refer to the "Understanding deserializer lifetimes" page for information
@@ -219,6 +1144,9 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer {
     where
         V: Visitor<'de>,
     {
+        if self.reads_json() {
+            return self.visit_json(|de| de.deserialize_string(visitor));
+        }
         visitor.visit_string(self.read_string()?)
     }
 
@@ -228,14 +1156,20 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer {
     where
         V: Visitor<'de>,
     {
-        visitor.visit_bytes(self.read_bytes()?.as_slice())
+        if self.reads_json() {
+            return self.visit_json(|de| de.deserialize_bytes(visitor));
+        }
+        visitor.visit_bytes(self.read_byte_buf()?.as_slice())
     }
 
     fn deserialize_byte_buf<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        visitor.visit_byte_buf(self.read_bytes()?)
+        if self.reads_json() {
+            return self.visit_json(|de| de.deserialize_byte_buf(visitor));
+        }
+        visitor.visit_byte_buf(self.read_byte_buf()?)
     }
 
     // An empty file us used to represen None
@@ -246,12 +1180,19 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer {
     where
         V: Visitor<'de>,
     {
-        if self.current_path_exists() {
-            visitor.visit_some(self)
-        } else {
-            // Serializing options is a nop, so there will be no file
-            visitor.visit_none()
+        if self.reads_json() {
+            return self.visit_json(|de| de.deserialize_option(visitor));
+        }
+        if !self.path_exists() {
+            // Serializing options is a nop by default, so there will be no file
+            return visitor.visit_none();
         }
+        if let Some(none) = self.config.option_repr.none_contents() {
+            if self.points_to_file()? && self.read_file()? == none {
+                return visitor.visit_none();
+            }
+        }
+        visitor.visit_some(self)
     }
 
     // In Serde, unit means an anonymous value containing no data.
@@ -259,21 +1200,32 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer {
     where
         V: Visitor<'de>,
     {
+        if self.reads_json() {
+            return self.visit_json(|de| de.deserialize_unit(visitor));
+        }
+        self.check_unit()?;
         visitor.visit_unit()
     }
 
     // Unit struct means a named value containing no data.
-    fn deserialize_unit_struct<V>(self, _name: &'static str, visitor: V) -> Result<V::Value>
+    fn deserialize_unit_struct<V>(self, name: &'static str, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
+        if self.reads_json() {
+            return self.visit_json(|de| de.deserialize_unit_struct(name, visitor));
+        }
+        self.check_unit()?;
         visitor.visit_unit()
     }
 
-    fn deserialize_newtype_struct<V>(self, _name: &'static str, visitor: V) -> Result<V::Value>
+    fn deserialize_newtype_struct<V>(self, name: &'static str, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
+        if name == Json::NEWTYPE_NAME {
+            return self.visit_json(|de| visitor.visit_newtype_struct(de));
+        }
         visitor.visit_newtype_struct(self)
     }
 
@@ -284,7 +1236,10 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer {
     where
         V: Visitor<'de>,
     {
-        visitor.visit_seq(SequentialDeserializer::new(self))
+        if self.reads_json() {
+            return self.visit_json(|de| de.deserialize_seq(visitor));
+        }
+        visitor.visit_seq(SequentialDeserializer::new(self)?)
     }
 
     // Tuples look just like sequences in JSON. Some formats may be able to
@@ -293,24 +1248,36 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer {
     // As indicated by the length parameter, the `Deserialize` implementation
     // for a tuple in the Serde data model is required to know the length of the
     // tuple before even looking at the input data.
-    fn deserialize_tuple<V>(self, _len: usize, visitor: V) -> Result<V::Value>
+    fn deserialize_tuple<V>(self, len: usize, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        self.deserialize_seq(visitor)
+        if self.reads_json() {
+            return self.visit_json(|de| de.deserialize_tuple(len, visitor));
+        }
+        let path = self.error_path();
+        let seq = SequentialDeserializer::new(self)?;
+        match seq.entries.len() {
+            found if found != len => Err(Error::ArrayLengthMismatch {
+                expected: len,
+                found,
+                path,
+            }),
+            _ => visitor.visit_seq(seq),
+        }
     }
 
     // Tuple structs look just like sequences in JSON.
     fn deserialize_tuple_struct<V>(
         self,
         _name: &'static str,
-        _len: usize,
+        len: usize,
         visitor: V,
     ) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        self.deserialize_seq(visitor)
+        self.deserialize_tuple(len, visitor)
     }
 
     // Much like `deserialize_seq` but calls the visitors `visit_map` method
@@ -320,7 +1287,15 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer {
     where
         V: Visitor<'de>,
     {
-        Ok(visitor.visit_map(MapDeserializer::new(self)?)?)
+        if self.reads_json() {
+            return self.visit_json(|de| de.deserialize_map(visitor));
+        }
+        let ordered = self.config.ordered_maps;
+        // flattened structs are read as maps, and report their missing fields here
+        let dir = self.error_path();
+        visitor
+            .visit_map(MapDeserializer::new(self, ordered)?)
+            .map_err(|err| locate_missing_field(err, &dir))
     }
 
     // Structs look just like maps in JSON.
@@ -338,28 +1313,37 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer {
     where
         V: Visitor<'de>,
     {
-        if self.points_to_file()? {
-            assert!(self.expect_json);
+        if self.reads_json() {
             // structs cannot be written as files, so this must be a json sub-object
-            let file = std::fs::File::open(&self.path)?;
-            let mut json_de = serde_json::de::Deserializer::from_reader(file);
-            Ok(json_de.deserialize_struct(name, fields, visitor)?)
-        } else {
-            assert!(!self.expect_json);
-            // normal struct
-            self.deserialize_map(visitor)
+            return self.visit_json(|de| de.deserialize_struct(name, fields, visitor));
+        }
+        if self.points_to_file()? {
+            // a whole struct saved as a json file, e.g. a root written by hand or by another tool
+            if self.holds_json_object()? {
+                return self.visit_json(|de| de.deserialize_struct(name, fields, visitor));
+            }
+            return Err(Error::ExpectedDirectory(self.error_path()));
         }
+        // normal struct, whose fields are never prefixed
+        let dir = self.error_path();
+        let map = MapDeserializer::new(self, false)?.not_a_sequence(fields)?;
+        visitor
+            .visit_map(map.struct_fields(fields))
+            .map_err(|err| locate_missing_field(err, &dir))
     }
 
     fn deserialize_enum<V>(
         self,
-        _name: &'static str,
-        _variants: &'static [&'static str],
+        name: &'static str,
+        variants: &'static [&'static str],
         visitor: V,
     ) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
+        if self.reads_json() {
+            return self.visit_json(|de| de.deserialize_enum(name, variants, visitor));
+        }
         // Take the enum below:
         // enum E {
         //     Unit,
@@ -371,14 +1355,16 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer {
         // E::Advanced will be serialized as `./Complex`: "(u8 value as base 10 string)"
 
         if self.points_to_file()? {
-            // handles the basic unit case (E::Unit), our variant is the content of the current path
-            let s = self.read_string().unwrap().into_deserializer();
-            let v = visitor.visit_enum(Enum::new(s, self)).unwrap();
-            Ok(v)
+            // handles the basic unit case (E::Unit), our variant is the content of the current path.
+            // Variant names never hold whitespace, so a trailing newline from editing is ignored
+            let found = self.read_string()?;
+            let variant = self.variant_name(found.trim().to_owned(), variants)?;
+            visitor.visit_enum(Enum::new(variant, found, self))
         } else {
             // handles other advanced enums, the name of the variant is the last path
-            let s = self.push_first_dir_entry()?.into_deserializer();
-            let v = visitor.visit_enum(Enum::new(s, self)).unwrap();
+            let found = self.push_first_dir_entry()?;
+            let variant = self.variant_name(found.clone(), variants)?;
+            let v = visitor.visit_enum(Enum::new(variant, found, self))?;
             self.pop();
             Ok(v)
         }
@@ -409,42 +1395,139 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer {
     where
         V: Visitor<'de>,
     {
-        self.deserialize_unit(visitor)
+        // Never read: whatever the entry holds, nothing asked for it
+        visitor.visit_unit()
     }
 
-    fn deserialize_any<V>(self, _visitor: V) -> Result<V::Value>
+    // The tree only tells files from directories, so files are read as strings, unless they have
+    // a `typed_leaves` prefix or `infer_types` is set. Directories holding exactly the entries
+    // `0..n` are sequences, other directories are maps. This is what `#[serde(flatten)]` and
+    // `#[serde(tag = "...")]` enums go through
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        unimplemented!()
+        if self.reads_json() {
+            return self.visit_json(|de| de.deserialize_any(visitor));
+        }
+        if !self.points_to_file()? {
+            // `#[serde(untagged)]` enums buffer the value read here and then try each variant,
+            // so restore the path if reading fails part way, to leave the deserializer usable
+            let path = self.path.clone();
+            let result = if self.is_sequence_dir()? {
+                self.deserialize_seq(visitor)
+            } else {
+                self.deserialize_map(visitor)
+            };
+            if result.is_err() {
+                self.path = path;
+                self.expect_json = false;
+            }
+            return result;
+        }
+        // read once, the type prefix says how to parse what was read
+        let (ty, bytes) = self.read_leaf(true)?;
+        let string = match ty {
+            Some(LeafType::Bytes) => return visitor.visit_byte_buf(self.decode_bytes(bytes)?),
+            _ => String::from_utf8(bytes).map_err(|_| Error::InvalidUnicode)?,
+        };
+        match ty {
+            Some(LeafType::Bool) => return visitor.visit_bool(self.parse_bool(string)?),
+            Some(LeafType::Signed) => return visitor.visit_i64(self.parse_int_str(string)?),
+            Some(LeafType::Unsigned) => return visitor.visit_u64(self.parse_int_str(string)?),
+            Some(LeafType::Float) => return visitor.visit_f64(self.parse_float_str(string)?),
+            Some(LeafType::Char) => return visitor.visit_char(self.parse_char(string)?),
+            Some(LeafType::Str) => return visitor.visit_string(string),
+            Some(LeafType::Bytes) | None => {}
+        }
+        if !self.config.infer_types {
+            return visitor.visit_string(string);
+        }
+        match string.as_str() {
+            "true" => return visitor.visit_bool(true),
+            "false" => return visitor.visit_bool(false),
+            s if is_canonical_int(s) => {
+                if let Ok(v) = s.parse() {
+                    return visitor.visit_u64(v);
+                }
+                if let Ok(v) = s.parse() {
+                    return visitor.visit_i64(v);
+                }
+            }
+            s if s.contains('.') && is_canonical_float(s) => {
+                if let Ok(v) = s.parse() {
+                    return visitor.visit_f64(v);
+                }
+            }
+            _ => {}
+        }
+        visitor.visit_string(string)
     }
 }
 
-pub struct SequentialDeserializer<'a> {
+pub struct SequentialDeserializer<'a, F> {
     index: usize,
-    de: &'a mut Deserializer,
+    /// The entries holding the elements still to read, in order
+    entries: std::vec::IntoIter<OsString>,
+    de: &'a mut Deserializer<F>,
 }
 
-impl<'a> SequentialDeserializer<'a> {
-    fn new(de: &'a mut Deserializer) -> Self {
-        Self { index: 0, de }
+impl<'a, F: FileSystem> SequentialDeserializer<'a, F> {
+    /// Lists the directory once, ordering its entries by the index they name. Indices are read
+    /// whatever their zero padding, so `07` is the element after `6`, and the sequence ends at the
+    /// first index missing from the base on. A missing directory is an empty sequence
+    fn new(de: &'a mut Deserializer<F>) -> Result<Self> {
+        let names = match de.list_dir() {
+            Err(Error::IoError(err)) if err.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            names => names?,
+        };
+        let mut indices: Vec<(usize, OsString)> = names
+            .into_iter()
+            .filter_map(|name| {
+                let index = unpad_int(name.to_str()?)?.parse().ok()?;
+                Some((index, name))
+            })
+            .collect();
+        // an unpadded name sorts first, and wins over padded spellings of the same index
+        indices.sort_by(|(a, a_name), (b, b_name)| a.cmp(b).then(a_name.len().cmp(&b_name.len())));
+        indices.dedup_by_key(|(index, _)| *index);
+        let entries: Vec<OsString> = indices
+            .into_iter()
+            .skip_while(|(index, _)| *index < de.config.seq_base)
+            .zip(de.config.seq_base..)
+            .take_while(|((index, _), expected)| index == expected)
+            .map(|((_, name), _)| name)
+            .collect();
+        Ok(Self {
+            index: de.config.seq_base,
+            entries: entries.into_iter(),
+            de,
+        })
+    }
+
+    /// Returns how many elements have been read
+    fn read(&self) -> usize {
+        self.index - self.de.config.seq_base
     }
 
     fn deserialize_next<'de, T>(&mut self, seed: T) -> Result<Option<T::Value>>
     where
         T: DeserializeSeed<'de>,
     {
-        let mut bytes = [0u8; 32];
-        let len = itoa::write(&mut bytes[..], self.index)?;
-        let num = std::str::from_utf8(&bytes[..len]).unwrap();
-
-        self.de.push(num);
-
-        if !self.de.path_exists() {
-            self.de.pop();
+        let Some(name) = self.entries.next() else {
             return Ok(None);
+        };
+        if let Some(limit) = self
+            .de
+            .config
+            .max_seq_len
+            .filter(|&limit| self.read() >= limit)
+        {
+            let path = self.de.error_path();
+            return Err(Error::TooManyEntries { path, limit });
         }
 
+        self.de.push(name);
         let val = seed.deserialize(&mut *self.de).map(Some);
 
         self.de.pop();
@@ -454,7 +1537,7 @@ impl<'a> SequentialDeserializer<'a> {
     }
 }
 
-impl<'de, 'a> SeqAccess<'de> for SequentialDeserializer<'a> {
+impl<'de, 'a, F: FileSystem> SeqAccess<'de> for SequentialDeserializer<'a, F> {
     type Error = Error;
 
     fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>>
@@ -463,41 +1546,258 @@ impl<'de, 'a> SeqAccess<'de> for SequentialDeserializer<'a> {
     {
         self.deserialize_next(seed)
     }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.entries.len())
+    }
+}
+
+/// Returns true if `s` is an integer the way the serializer writes them: an optional `-` followed
+/// by decimal digits without leading zeros.
+///
+/// Anything else Rust's `parse` would accept, like `+5` or `05`, is rejected so that every number
+/// has exactly one spelling on disk. `5.0` is a float and is rejected too
+fn is_canonical_int(s: &str) -> bool {
+    let digits = s.strip_prefix('-').unwrap_or(s);
+    match digits.as_bytes() {
+        [b'0'] => s == "0",
+        [b'1'..=b'9', rest @ ..] => rest.iter().all(u8::is_ascii_digit),
+        _ => false,
+    }
+}
+
+/// Removes the zero padding written by `Serializer::pad_int_keys` from a decimal integer. Returns
+/// `None` if `s` is not a decimal integer
+fn unpad_int(s: &str) -> Option<String> {
+    let (sign, digits) = match s.strip_prefix('-') {
+        Some(digits) => ("-", digits),
+        None => ("", s),
+    };
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    match digits.trim_start_matches('0') {
+        "" => Some("0".to_owned()),
+        digits => Some(format!("{}{}", sign, digits)),
+    }
+}
+
+/// Rewrites an integer in one of the spellings accepted by [`Deserializer::lenient_ints`] as plain
+/// decimal. Returns `None` if `s` uses none of them
+fn coerce_int(s: &str) -> Option<String> {
+    let s = s.trim();
+    let (sign, digits) = match s.strip_prefix('-') {
+        Some(digits) => ("-", digits),
+        None => ("", s),
+    };
+    let digits = match digits.split_once('.') {
+        Some((int, frac)) if !frac.is_empty() && frac.bytes().all(|b| b == b'0') => int,
+        Some(_) => return None,
+        None => digits,
+    };
+    let radix = [("0x", 16), ("0X", 16), ("0o", 8), ("0b", 2)]
+        .into_iter()
+        .find_map(|(prefix, radix)| digits.strip_prefix(prefix).map(|d| (d, radix)));
+    let digits = match radix {
+        // from_str_radix would accept a sign after the prefix
+        Some((d, _)) if d.starts_with(['+', '-']) => return None,
+        Some((d, radix)) => u128::from_str_radix(d, radix).ok()?.to_string(),
+        None => digits.to_owned(),
+    };
+    Some(format!("{}{}", sign, digits))
+}
+
+/// Returns true if `s` is a float the way the serializer writes them: a canonical integer part
+/// (see [`is_canonical_int`]), optionally followed by `.` and at least one digit, or one of
+/// `inf`, `-inf` and `NaN`.
+///
+/// Floats never use exponents on disk, so `1e5` is rejected, as are `+5` and `05`. `5.0` and `5`
+/// are both accepted since the trailing zero does not make the value ambiguous
+fn is_canonical_float(s: &str) -> bool {
+    if matches!(s, "inf" | "-inf" | "NaN") {
+        return true;
+    }
+    let (int, frac) = match s.split_once('.') {
+        Some((int, frac)) => (int, Some(frac)),
+        None => (s, None),
+    };
+    // `-0` is a distinct float
+    let int_ok = is_canonical_int(int) || int == "-0";
+    int_ok && frac.is_none_or(|f| !f.is_empty() && f.bytes().all(|b| b.is_ascii_digit()))
+}
+
+struct MapDeserializer<'a, F> {
+    de: &'a mut Deserializer<F>,
+    it: std::vec::IntoIter<OsString>,
+    /// Whether entry names have an order prefix to strip
+    ordered: bool,
+    /// Whether entries named `json*` hold JSON. Only struct fields do, map keys never
+    json_fields: bool,
+    /// Whether the value of the current entry is JSON
+    json_value: bool,
+    /// Path of the entry whose key was read last, until its value is read
+    entry: Option<PathBuf>,
+}
+
+impl<'a, F: FileSystem> MapDeserializer<'a, F> {
+    /// An empty directory is a valid, empty map. Only the directory itself is required to exist
+    fn new(de: &'a mut Deserializer<F>, ordered: bool) -> Result<Self> {
+        let mut names = de.list_dir()?;
+        if let Some(limit) = de.config.max_map_entries {
+            let entries = names
+                .iter()
+                .filter(|name| !de.is_internal_entry(&name.to_string_lossy()))
+                .count();
+            if entries > limit {
+                let path = de.error_path();
+                return Err(Error::TooManyEntries { path, limit });
+            }
+        }
+        if ordered {
+            names.sort_by_cached_key(|name| {
+                name.to_str()
+                    .and_then(split_order_prefix)
+                    .map(|(index, _)| index)
+            });
+        }
+        Ok(Self {
+            de,
+            it: names.into_iter(),
+            ordered,
+            json_fields: false,
+            json_value: false,
+            entry: None,
+        })
+    }
+
+    /// Fails with [`DeError::ShapeMismatch`] if the entries are exactly those of a sequence. A
+    /// struct whose `fields` are not numbers never looks like one, so the directory was most
+    /// likely written from an older version of the type
+    fn not_a_sequence(self, fields: &[&str]) -> Result<Self> {
+        if fields.iter().any(|field| is_canonical_int(field)) {
+            return Ok(self);
+        }
+        if self.de.is_sequence(self.it.as_slice())? {
+            return Err(Error::ShapeMismatch {
+                expected: "struct",
+                found: "sequence",
+                path: self.de.error_path(),
+            });
+        }
+        Ok(self)
+    }
+
+    /// Reads the entries as the fields of a struct: fields named `json*` hold JSON, like the
+    /// serializer writes them, and the entries named in `fields` are visited first, in
+    /// declaration order. Adjacently tagged enums rely on this to read their tag before their
+    /// content
+    fn struct_fields(mut self, fields: &[&str]) -> Self {
+        self.json_fields = true;
+        let mut names: Vec<_> = self.it.collect();
+        names.sort_by_key(|name| {
+            fields
+                .iter()
+                .position(|field| name.to_str() == Some(field))
+                .unwrap_or(fields.len())
+        });
+        self.it = names.into_iter();
+        self
+    }
+}
+
+/// Fills in where the file of a field reported missing by the visitor of the directory `dir`
+/// should have been. Errors of nested directories already have their path
+fn locate_missing_field(err: Error, dir: &Path) -> Error {
+    match err {
+        Error::MissingField {
+            field,
+            expected_path,
+        } if expected_path.as_os_str().is_empty() => Error::MissingField {
+            field,
+            expected_path: dir.join(field),
+        },
+        err => err,
+    }
+}
+
+/// Resolves the `.` and `..` components of `path` without touching the file system. Returns
+/// `None` if a `..` would climb above the start of `path`
+fn normalize(path: &Path) -> Option<PathBuf> {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match normalized.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    normalized.pop();
+                }
+                _ => return None,
+            },
+            component => normalized.push(component),
+        }
+    }
+    Some(normalized)
 }
 
-struct MapDeserializer<'a> {
-    de: &'a mut Deserializer,
-    it: std::fs::ReadDir,
+/// Resolves `path` against the current directory, then resolves `.` and `..` like [`normalize`]
+fn absolute(path: &Path) -> Option<PathBuf> {
+    normalize(&std::path::absolute(path).ok()?)
 }
 
-impl<'a> MapDeserializer<'a> {
-    fn new(de: &'a mut Deserializer) -> Result<Self> {
-        let it = de.path.read_dir().unwrap();
-        Ok(Self { de, it })
+/// Decodes lowercase or uppercase hex, returning `None` on odd lengths or other characters
+fn decode_hex(hex: &[u8]) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
     }
+    let digit = |c: u8| (c as char).to_digit(16).map(|d| d as u8);
+    hex.chunks(2)
+        .map(|pair| Some(digit(pair[0])? << 4 | digit(pair[1])?))
+        .collect()
+}
+
+/// Splits the `<index>.` prefix written by `Serializer::ordered_maps` off an entry name
+fn split_order_prefix(name: &str) -> Option<(u64, &str)> {
+    let (index, key) = name.split_once('.')?;
+    if index.is_empty() || !index.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some((index.parse().ok()?, key))
 }
 
 // `MapAccess` is provided to the `Visitor` to give it the ability to iterate
 // through entries of the map.
-impl<'de, 'a> MapAccess<'de> for MapDeserializer<'a> {
+impl<'de, 'a, F: FileSystem> MapAccess<'de> for MapDeserializer<'a, F> {
     type Error = Error;
 
     fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>>
     where
         K: DeserializeSeed<'de>,
     {
-        let dir = self.it.next();
+        if let Some(entry) = self.entry.take() {
+            // the value of the previous entry was skipped without being read
+            self.de.path = entry;
+            self.de.pop();
+        }
+        let dir = loop {
+            match self.it.next() {
+                Some(name) if self.de.is_internal_entry(&name.to_string_lossy()) => continue,
+                dir => break dir,
+            }
+        };
         match dir {
             None => Ok(None),
-            Some(Err(err)) => Err(Error::IoError(err)),
-            Some(Ok(dir)) => {
-                let os_name = dir.file_name();
+            Some(os_name) => {
                 let path = os_name.to_str().ok_or(Error::InvalidUnicode)?;
-                if path.starts_with("json") {
-                    self.de.expect_json = true;
-                }
+                let key = match split_order_prefix(path) {
+                    Some((_, key)) if self.ordered => key,
+                    _ => path,
+                };
+                // only set for the value, so a value that is never read cannot leak it into the
+                // next entry
+                self.json_value = self.json_fields && key.starts_with("json");
                 self.de.push(path);
-                let mut de = KeyDeserializer::new(String::from(path), self.de);
+                self.entry = Some(self.de.path.clone());
+                let mut de = KeyDeserializer::new(String::from(key), self.de);
                 let a = Ok(Some(seed.deserialize(&mut de)?));
                 a
             }
@@ -508,22 +1808,47 @@ impl<'de, 'a> MapAccess<'de> for MapDeserializer<'a> {
     where
         V: DeserializeSeed<'de>,
     {
-        let val = seed.deserialize(&mut *self.de);
+        let entry = self
+            .entry
+            .take()
+            .expect("next_value_seed called before next_key_seed");
+        self.de.expect_json = std::mem::take(&mut self.json_value);
+        let val = seed.deserialize(&mut *self.de).map_err(|err| match err {
+            // the entry was just listed, so it or something below it was removed since
+            Error::IoError(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                Error::ConcurrentModification(self.de.error_path())
+            }
+            err => err,
+        });
+        self.de.path = entry;
         self.de.expect_json = false;
         self.de.pop();
         val
     }
+
+    fn size_hint(&self) -> Option<usize> {
+        let remaining = self.it.as_slice().iter();
+        Some(
+            remaining
+                .filter(|name| !self.de.is_internal_entry(&name.to_string_lossy()))
+                .count(),
+        )
+    }
 }
 
-struct Enum<'d> {
-    variant: Option<StringDeserializer<DeError>>,
-    de: &'d mut Deserializer,
+struct Enum<'d, F> {
+    /// The variant name to look up
+    variant: Option<String>,
+    /// What the variant was read from, reported when no variant matches
+    found: String,
+    de: &'d mut Deserializer<F>,
 }
 
-impl<'d> Enum<'d> {
-    fn new(variant: StringDeserializer<DeError>, de: &'d mut Deserializer) -> Self {
+impl<'d, F> Enum<'d, F> {
+    fn new(variant: String, found: String, de: &'d mut Deserializer<F>) -> Self {
         Enum {
             variant: Some(variant),
+            found,
             de,
         }
     }
@@ -534,7 +1859,7 @@ impl<'d> Enum<'d> {
 //
 // Note that all enum deserialization methods in Serde refer exclusively to the
 // "externally tagged" enum representation.
-impl<'de, 'd> EnumAccess<'de> for Enum<'d> {
+impl<'de, 'd, F: FileSystem> EnumAccess<'de> for Enum<'d, F> {
     type Error = Error;
     type Variant = Self;
 
@@ -542,7 +1867,11 @@ impl<'de, 'd> EnumAccess<'de> for Enum<'d> {
     where
         V: DeserializeSeed<'de>,
     {
-        let v = seed.deserialize(self.variant.take().unwrap())?;
+        let variant: StringDeserializer<DeError> = self.variant.take().unwrap().into_deserializer();
+        // the seed only fails when no variant has this name
+        let v = seed
+            .deserialize(variant)
+            .map_err(|_| Error::UnknownVariant(self.found.clone(), self.de.error_path()))?;
         Ok((v, self))
         // This is called and we have to figure which enum we are based on the current path.
         // The problem is that there are many files in the current path that might not be what we
@@ -552,7 +1881,7 @@ impl<'de, 'd> EnumAccess<'de> for Enum<'d> {
 
 // `VariantAccess` is provided to the `Visitor` to give it the ability to see
 // the content of the single variant that it decided to deserialize.
-impl<'de, 'd> VariantAccess<'de> for Enum<'d> {
+impl<'de, 'd, F: FileSystem> VariantAccess<'de> for Enum<'d, F> {
     type Error = Error;
 
     // If the `Visitor` expected this variant to be a unit variant, the input
@@ -572,63 +1901,105 @@ impl<'de, 'd> VariantAccess<'de> for Enum<'d> {
 
     // Tuple variants are represented in JSON as `{ NAME: [DATA...] }` so
     // deserialize the sequence of data here.
-    fn tuple_variant<V>(self, _len: usize, visitor: V) -> Result<V::Value>
+    fn tuple_variant<V>(self, len: usize, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        de::Deserializer::deserialize_seq(self.de, visitor)
+        de::Deserializer::deserialize_tuple(self.de, len, visitor)
     }
 
     // Struct variants are represented in JSON as `{ NAME: { K: V, ... } }` so
     // deserialize the inner map here.
-    fn struct_variant<V>(self, _fields: &'static [&'static str], visitor: V) -> Result<V::Value>
+    fn struct_variant<V>(self, fields: &'static [&'static str], visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        de::Deserializer::deserialize_map(self.de, visitor)
+        let dir = self.de.error_path();
+        visitor
+            .visit_map(MapDeserializer::new(self.de, false)?.struct_fields(fields))
+            .map_err(|err| locate_missing_field(err, &dir))
     }
 }
 
 /// Holds a string internally that is uses to respond to deserialize requests
-struct KeyDeserializer<'de> {
+struct KeyDeserializer<'de, F> {
     inner: String,
-    de: &'de mut Deserializer,
+    de: &'de mut Deserializer<F>,
 }
 
-impl<'de> KeyDeserializer<'de> {
-    fn new(inner: String, de: &'de mut Deserializer) -> Self {
+impl<'de, F: FileSystem> KeyDeserializer<'de, F> {
+    fn new(inner: String, de: &'de mut Deserializer<F>) -> Self {
         Self { inner, de }
     }
 
-    fn parse_int<T: FromStr>(&self) -> Result<T>
+    fn parse_int<T>(&self) -> Result<T>
+    where
+        T: Integer,
+    {
+        match unpad_int(&self.inner).filter(|_| self.de.config.padded_int_keys) {
+            Some(int) => self.de.parse_int_str(int).map_err(|err| match err {
+                Error::IntOutOfRange { ty, min, max, .. } => Error::IntOutOfRange {
+                    value: self.inner.clone(),
+                    ty,
+                    min,
+                    max,
+                },
+                _ => Error::ParseError(self.inner.clone()),
+            }),
+            None => self.de.parse_int_str(self.inner.clone()),
+        }
+    }
+
+    fn parse_float<T>(&self) -> Result<T>
     where
-        T: FromStr<Err = ParseIntError>,
+        T: FromStr,
     {
-        Ok(self
-            .inner
-            .parse::<T>()
-            .map_err(|e| Error::ParseError(e.to_string()))?)
+        self.de.parse_number(self.inner.clone(), is_canonical_float)
     }
 
-    fn parse_float<T: FromStr>(&self) -> Result<T>
+    /// Parses a key written by [`Serializer::json_keys`](crate::Serializer::json_keys)
+    fn visit_json<T, J>(&self, f: J) -> Result<T>
     where
-        T: FromStr<Err = ParseFloatError>,
+        J: FnOnce(&mut JsonDeserializer) -> serde_json::Result<T>,
     {
-        Ok(self
-            .inner
-            .parse::<T>()
-            .map_err(|e| Error::ParseError(e.to_string()))?)
+        let json =
+            decode_json_key(&self.inner).ok_or_else(|| Error::ParseError(self.inner.clone()))?;
+        let mut json_de = serde_json::Deserializer::from_reader(Cursor::new(json.into_bytes()));
+        let value = f(&mut json_de)?;
+        json_de.end()?;
+        Ok(value)
+    }
+}
+
+/// Reverses the percent encoding of keys written by `Serializer::json_keys`. Returns `None` on
+/// malformed escapes
+fn decode_json_key(name: &str) -> Option<String> {
+    let mut json = String::with_capacity(name.len());
+    let mut rest = name;
+    while let Some(i) = rest.find('%') {
+        json.push_str(&rest[..i]);
+        let c = match rest.get(i + 1..i + 3)? {
+            "25" => '%',
+            "2F" => '/',
+            "5C" => '\\',
+            "00" => '\0',
+            _ => return None,
+        };
+        json.push(c);
+        rest = &rest[i + 3..];
     }
+    json.push_str(rest);
+    Some(json)
 }
 
-impl<'de, 'a, 'myde> de::Deserializer<'de> for &'a mut KeyDeserializer<'myde> {
+impl<'de, 'a, 'myde, F: FileSystem> de::Deserializer<'de> for &'a mut KeyDeserializer<'myde, F> {
     type Error = Error;
 
-    fn deserialize_any<V>(self, _visitor: V) -> Result<V::Value>
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        unimplemented!()
+        self.deserialize_string(visitor)
     }
 
     fn deserialize_identifier<V>(self, visitor: V) -> Result<V::Value>
@@ -642,11 +2013,7 @@ impl<'de, 'a, 'myde> de::Deserializer<'de> for &'a mut KeyDeserializer<'myde> {
     where
         V: Visitor<'de>,
     {
-        visitor.visit_bool(match self.inner.as_str() {
-            "true" => true,
-            "false" => false,
-            _ => panic!(),
-        })
+        visitor.visit_bool(self.de.parse_bool(std::mem::take(&mut self.inner))?)
     }
 
     fn deserialize_i8<V>(self, visitor: V) -> Result<V::Value>
@@ -723,13 +2090,11 @@ impl<'de, 'a, 'myde> de::Deserializer<'de> for &'a mut KeyDeserializer<'myde> {
     where
         V: Visitor<'de>,
     {
-        let c = self
-            .inner
-            .chars()
-            .next()
-            .ok_or(Error::EmptyFile(PathBuf::new()))?;
-
-        visitor.visit_char(c)
+        let mut it = self.inner.chars();
+        match (it.next(), it.next()) {
+            (Some(c), None) => visitor.visit_char(c),
+            _ => Err(Error::InvalidChar(self.inner.clone(), self.de.error_path())),
+        }
     }
 
     fn deserialize_str<V>(self, visitor: V) -> Result<V::Value>
@@ -749,20 +2114,68 @@ impl<'de, 'a, 'myde> de::Deserializer<'de> for &'a mut KeyDeserializer<'myde> {
     fn deserialize_enum<V>(
         self,
         _name: &'static str,
-        _variants: &'static [&'static str],
+        variants: &'static [&'static str],
         visitor: V,
     ) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        let variant = std::mem::take(&mut self.inner).into_deserializer();
-        visitor.visit_enum(Enum::new(variant, &mut self.de))
+        let found = std::mem::take(&mut self.inner);
+        let variant = match variants.iter().find(|v| v.to_lowercase() == found) {
+            Some(variant) if self.de.config.lowercase_keys => variant.to_string(),
+            _ => found.clone(),
+        };
+        visitor.visit_enum(Enum::new(variant, found, self.de))
     }
 
-    serde::forward_to_deserialize_any! {
+    // Keys that are not a single string can only have been written as JSON
+    fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.visit_json(|de| de.deserialize_seq(visitor))
+    }
+
+    fn deserialize_tuple<V>(self, len: usize, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.visit_json(|de| de.deserialize_tuple(len, visitor))
+    }
+
+    fn deserialize_tuple_struct<V>(
+        self,
+        name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.visit_json(|de| de.deserialize_tuple_struct(name, len, visitor))
+    }
+
+    fn deserialize_map<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.visit_json(|de| de.deserialize_map(visitor))
+    }
+
+    fn deserialize_struct<V>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.visit_json(|de| de.deserialize_struct(name, fields, visitor))
+    }
 
-    bytes byte_buf option unit unit_struct newtype_struct seq tuple
-        tuple_struct map struct ignored_any
+    serde::forward_to_deserialize_any! {
+        bytes byte_buf option unit unit_struct newtype_struct ignored_any
     }
 }
 
@@ -781,11 +2194,12 @@ mod tests {
             let path = format!("{}/{}", base_dir, path);
             let path = Path::new(path.as_str());
             let _ = std::fs::create_dir_all(path.parent().unwrap());
-            std::fs::write(&path, expected).unwrap();
+            std::fs::write(path, expected).unwrap();
         }
     }
 
     #[test]
+    #[allow(dead_code)]
     fn test_struct() {
         #[derive(Deserialize, PartialEq, Debug)]
         struct BasicTest {
@@ -903,8 +2317,7 @@ mod tests {
         let _ = std::fs::remove_dir_all(test_dir);
     }
 
-    //#[test]
-    #[allow(dead_code)]
+    #[test]
     fn test_json() {
         let test_dir = "./.test-de-json";
         #[derive(Deserialize, PartialEq, Debug)]
@@ -918,6 +2331,1360 @@ mod tests {
         };
         assert_eq!(expected, from_fs(test_dir).unwrap());
 
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Complex {
+            json_vec: Vec<i32>,
+            json_nested: BTreeMap<String, BTreeMap<String, u32>>,
+            json_tuple: (u8, String),
+        }
+
+        setup_test(
+            test_dir,
+            vec![
+                ("json_vec", "[1,-2,3]"),
+                ("json_nested", r#"{"a":{"x":1},"b":{}}"#),
+                ("json_tuple", r#"[7,"seven"]"#),
+            ],
+        );
+        let expected = Complex {
+            json_vec: vec![1, -2, 3],
+            json_nested: [
+                ("a".into(), [("x".into(), 1)].into()),
+                ("b".into(), BTreeMap::new()),
+            ]
+            .into(),
+            json_tuple: (7, "seven".into()),
+        };
+        assert_eq!(expected, from_fs(test_dir).unwrap());
+
+        let _ = std::fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_char_leaves() {
+        let test_dir = "./.test-de-char-leaves";
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Data {
+            c: char,
+        }
+
+        setup_test(test_dir, vec![("c", "🦀"), (VERSION_MARKER, "2")]);
+        assert_eq!(Data { c: '🦀' }, from_fs(test_dir).unwrap());
+
+        let path = Path::new(test_dir).join("c");
+        for contents in ["ab", "x\0\0\0\0\0\0\0"] {
+            setup_test(test_dir, vec![("c", contents), (VERSION_MARKER, "2")]);
+            let res: Result<Data> = from_fs(test_dir);
+            assert!(
+                matches!(res, Err(DeError::InvalidChar(ref s, ref p)) if s == contents && p == &path),
+                "{:?}",
+                res
+            );
+        }
+
+        // map keys are held to the same rule
+        setup_test(test_dir, vec![("c", "1"), ("ab", "2")]);
+        let res: Result<BTreeMap<char, u32>> = from_fs(test_dir);
+        let path = Path::new(test_dir).join("ab");
+        assert!(
+            matches!(res, Err(DeError::InvalidChar(ref s, ref p)) if s == "ab" && p == &path),
+            "{:?}",
+            res
+        );
+        setup_test(test_dir, vec![("c", "1"), ("🦀", "2")]);
+        let map: BTreeMap<char, u32> = from_fs(test_dir).unwrap();
+        assert_eq!(map, BTreeMap::from([('c', 1), ('🦀', 2)]));
+
+        let _ = std::fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_version() {
+        let test_dir = "./.test-de-version";
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Data {
+            c: char,
+            s: String,
+        }
+
+        // V1 trees have no marker and NUL padded chars
+        setup_test(test_dir, vec![("c", "x\0\0\0\0\0\0\0"), ("s", "abc")]);
+        let de = Deserializer::from_fs(test_dir);
+        assert_eq!(de.version, FormatVersion::V1);
+        let expected = Data {
+            c: 'x',
+            s: "abc".into(),
+        };
+        assert_eq!(expected, from_fs(test_dir).unwrap());
+
+        // The marker is not mistaken for a field or an enum variant
+        setup_test(
+            test_dir,
+            vec![("c", "x"), ("s", "abc"), (VERSION_MARKER, "2")],
+        );
+        assert_eq!(
+            Deserializer::from_fs(test_dir).version,
+            FormatVersion::CURRENT
+        );
+        let map: BTreeMap<String, String> = from_fs(test_dir).unwrap();
+        assert_eq!(map.len(), 2);
+
+        #[derive(Deserialize, PartialEq, Debug)]
+        enum E {
+            Newtype(u32),
+        }
+        setup_test(test_dir, vec![("Newtype", "8"), (VERSION_MARKER, "2")]);
+        assert_eq!(E::Newtype(8), from_fs(test_dir).unwrap());
+
+        let _ = std::fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_reset() {
+        let test_dir = "./.test-de-reset";
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct A {
+            a: u32,
+        }
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct B {
+            b: String,
+        }
+
+        setup_test(test_dir, vec![("first/a", "1"), ("second/b", "two")]);
+        let first = Path::new(test_dir).join("first");
+        let second = Path::new(test_dir).join("second");
+
+        let mut de = Deserializer::from_fs(&first);
+        assert_eq!(de.at_path(), first);
+        assert_eq!(A { a: 1 }, A::deserialize(&mut de).unwrap());
+        assert_eq!(de.at_path(), first);
+
+        de.reset(&second);
+        assert_eq!(de.at_path(), second);
+        assert_eq!(B { b: "two".into() }, B::deserialize(&mut de).unwrap());
+
+        let _ = std::fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_empty_map() {
+        let test_dir = "./.test-de-empty-map";
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Data {
+            map: BTreeMap<String, u32>,
+        }
+
+        setup_test(test_dir, vec![]);
+        std::fs::create_dir_all(Path::new(test_dir).join("map")).unwrap();
+
+        let expected = Data {
+            map: BTreeMap::new(),
+        };
+        assert_eq!(expected, from_fs(test_dir).unwrap());
+
+        let map: BTreeMap<String, u32> = from_fs(&format!("{}/map", test_dir)).unwrap();
+        assert!(map.is_empty());
+
+        // A missing directory is an error rather than an empty map
+        let res: Result<BTreeMap<String, u32>> = from_fs(&format!("{}/missing", test_dir));
+        assert!(matches!(res, Err(DeError::IoError(_))));
+
+        let _ = std::fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_flatten_map() {
+        let test_dir = "./.test-de-flatten-map";
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Data {
+            name: String,
+            port: u16,
+            #[serde(flatten)]
+            extra: BTreeMap<String, String>,
+        }
+
+        setup_test(
+            test_dir,
+            vec![
+                ("name", "app"),
+                ("port", "8080"),
+                ("owner", "ops"),
+                ("notes", "hand written"),
+            ],
+        );
+
+        let expected = Data {
+            name: "app".into(),
+            port: 8080,
+            extra: [
+                ("owner".into(), "ops".into()),
+                ("notes".into(), "hand written".into()),
+            ]
+            .into(),
+        };
+        assert_eq!(expected, from_fs(test_dir).unwrap());
+
+        let _ = std::fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_struct_layout_mismatch() {
+        let test_dir = "./.test-de-struct-layout-mismatch";
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Inner {
+            a: u32,
+        }
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Outer {
+            inner: Inner,
+        }
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Json {
+            json: Inner,
+        }
+
+        setup_test(test_dir, vec![("inner", "5")]);
+        let res: Result<Outer> = from_fs(test_dir);
+        let expected = Path::new(test_dir).join("inner");
+        assert!(matches!(res, Err(DeError::ExpectedDirectory(p)) if p == expected));
+
+        setup_test(test_dir, vec![("json/a", "5")]);
+        let res: Result<Json> = from_fs(test_dir);
+        let expected = Path::new(test_dir).join("json");
+        assert!(matches!(res, Err(DeError::ExpectedFile(p)) if p == expected));
+
+        let _ = std::fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_array_length_mismatch() {
+        let test_dir = "./.test-de-array-length-mismatch";
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Arrays {
+            bytes: [u8; 4],
+            names: [String; 2],
+            pair: (bool, i32),
+        }
+
+        let files = |bytes: &[&'static str]| {
+            let mut files = vec![
+                ("names/0", "a"),
+                ("names/1", "b"),
+                ("pair/0", "true"),
+                ("pair/1", "-3"),
+            ];
+            let paths = ["bytes/0", "bytes/1", "bytes/2", "bytes/3", "bytes/4"];
+            files.extend(paths.into_iter().zip(bytes.iter().copied()));
+            files
+        };
+
+        setup_test(test_dir, files(&["1", "2", "3", "4"]));
+        let expected = Arrays {
+            bytes: [1, 2, 3, 4],
+            names: ["a".into(), "b".into()],
+            pair: (true, -3),
+        };
+        assert_eq!(expected, from_fs(test_dir).unwrap());
+
+        let bytes_path = Path::new(test_dir).join("bytes");
+        setup_test(test_dir, files(&["1", "2", "3"]));
+        let res: Result<Arrays> = from_fs(test_dir);
+        assert!(matches!(
+            res,
+            Err(DeError::ArrayLengthMismatch { expected: 4, found: 3, ref path }) if path == &bytes_path
+        ));
+
+        setup_test(test_dir, files(&["1", "2", "3", "4", "5"]));
+        let res: Result<Arrays> = from_fs(test_dir);
+        assert!(matches!(
+            res,
+            Err(DeError::ArrayLengthMismatch { expected: 4, found: 5, ref path }) if path == &bytes_path
+        ));
+
+        let _ = std::fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_tuple_length_mismatch() {
+        let test_dir = "./.test-de-tuple-length-mismatch";
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Point(i32, i32, i32);
+
+        #[derive(Deserialize, PartialEq, Debug)]
+        enum Shape {
+            Line(Point, Point),
+        }
+
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Tuples {
+            tup: (u8, u32, String),
+            point: Point,
+            shape: Shape,
+        }
+
+        let base = vec![
+            ("tup/0", "1"),
+            ("tup/1", "2"),
+            ("tup/2", "three"),
+            ("point/0", "1"),
+            ("point/1", "2"),
+            ("point/2", "3"),
+            ("shape/Line/0/0", "0"),
+            ("shape/Line/0/1", "0"),
+            ("shape/Line/0/2", "0"),
+            ("shape/Line/1/0", "1"),
+            ("shape/Line/1/1", "1"),
+            ("shape/Line/1/2", "1"),
+        ];
+        setup_test(test_dir, base.clone());
+        let tuples: Tuples = from_fs(test_dir).unwrap();
+        assert_eq!(tuples.tup, (1, 2, "three".into()));
+
+        // each tuple kind reports the directory that is too short
+        let truncated = [
+            ("tup/2", "tup", 3),
+            ("point/2", "point", 3),
+            ("shape/Line/1", "shape/Line", 2),
+        ];
+        for (removed, tuple, expected) in truncated {
+            setup_test(test_dir, base.clone());
+            let removed = Path::new(test_dir).join(removed);
+            if removed.is_dir() {
+                std::fs::remove_dir_all(removed).unwrap();
+            } else {
+                std::fs::remove_file(removed).unwrap();
+            }
+            let tuple_path = Path::new(test_dir).join(tuple);
+            let res: Result<Tuples> = from_fs(test_dir);
+            assert!(
+                matches!(
+                    res,
+                    Err(DeError::ArrayLengthMismatch { expected: e, found, ref path })
+                        if e == expected && found == expected - 1 && path == &tuple_path
+                ),
+                "{:?}",
+                res
+            );
+        }
+
+        let _ = std::fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_canonical_numbers() {
+        let test_dir = "./.test-de-canonical-numbers";
+        let cases = [
+            ("plus", "+5"),
+            ("leading_zero", "05"),
+            ("trailing_zero", "5.0"),
+            ("space", " 5"),
+            ("exponent", "5e0"),
+            ("plain", "5"),
+            ("negative", "-5"),
+            ("zero", "0"),
+            ("negative_zero", "-0"),
+        ];
+        setup_test(test_dir, cases.to_vec());
+        let read = |name: &str| -> (Option<u32>, Option<i32>, Option<f64>) {
+            let path = format!("{}/{}", test_dir, name);
+            (
+                from_fs(&path).ok(),
+                from_fs(&path).ok(),
+                from_fs(&path).ok(),
+            )
+        };
+
+        assert_eq!(read("plus"), (None, None, None));
+        assert_eq!(read("leading_zero"), (None, None, None));
+        assert_eq!(read("trailing_zero"), (None, None, Some(5.0)));
+        assert_eq!(read("space"), (None, None, None));
+        assert_eq!(read("exponent"), (None, None, None));
+        assert_eq!(read("plain"), (Some(5), Some(5), Some(5.0)));
+        assert_eq!(read("negative"), (None, Some(-5), Some(-5.0)));
+        assert_eq!(read("zero"), (Some(0), Some(0), Some(0.0)));
+        assert_eq!(read("negative_zero"), (None, None, Some(-0.0)));
+
+        // Map keys follow the same rules
+        setup_test(test_dir, vec![("5", "a"), ("05", "b")]);
+        let res: Result<BTreeMap<u32, String>> = from_fs(test_dir);
+        assert!(matches!(res, Err(DeError::ParseError(ref s)) if s == "05"));
+
+        let _ = std::fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_int_out_of_range() {
+        let test_dir = "./.test-de-int-out-of-range";
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Small {
+            value: i8,
+        }
+
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Unsigned {
+            value: u64,
+        }
+
+        setup_test(test_dir, vec![("value", "200")]);
+        let err = from_fs::<Small>(test_dir).unwrap_err();
+        assert!(matches!(
+            err,
+            DeError::IntOutOfRange { ref value, ty: "i8", ref min, ref max }
+                if value == "200" && min == "-128" && max == "127"
+        ));
+        assert_eq!(
+            err.to_string(),
+            "200 is out of range for i8, expected -128..=127"
+        );
+
+        setup_test(test_dir, vec![("value", "-1")]);
+        assert_eq!(
+            from_fs::<Unsigned>(test_dir).unwrap_err().to_string(),
+            "-1 is out of range for u64, expected 0..=18446744073709551615"
+        );
+
+        // Values that are not integers at all are still parse errors
+        setup_test(test_dir, vec![("value", "2OO")]);
+        assert!(matches!(
+            from_fs::<Small>(test_dir),
+            Err(DeError::ParseError(_))
+        ));
+
+        let _ = std::fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_probe_fs() {
+        let test_dir = "./.test-de-probe-fs";
+        setup_test(
+            test_dir,
+            vec![
+                ("leaf", "1"),
+                ("map/a", "1"),
+                ("seq/0", "a"),
+                ("seq/1", "b"),
+                ("gappy/0", "a"),
+                ("gappy/2", "c"),
+            ],
+        );
+        std::fs::create_dir(format!("{}/empty", test_dir)).unwrap();
+        let probe = |name: &str| probe_fs(Path::new(test_dir).join(name)).unwrap();
+        assert_eq!(probe("leaf"), NodeKind::Leaf);
+        assert_eq!(probe("map"), NodeKind::Map);
+        assert_eq!(probe("seq"), NodeKind::Seq);
+        assert_eq!(probe("gappy"), NodeKind::Map);
+        assert_eq!(probe("empty"), NodeKind::Map);
+        assert!(probe_fs(Path::new(test_dir).join("missing")).is_err());
+
+        let _ = std::fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_concurrent_modification() {
+        use crate::MemFs;
+
+        /// Lists `gone` in its parent, but has it vanish before it is read
+        struct Vanishing {
+            fs: MemFs,
+            gone: PathBuf,
+        }
+
+        impl FileSystem for Vanishing {
+            fn metadata(&self, path: &Path) -> std::io::Result<EntryKind> {
+                if path.starts_with(&self.gone) {
+                    return Err(std::io::ErrorKind::NotFound.into());
+                }
+                self.fs.metadata(path)
+            }
+
+            fn read(&self, path: &Path) -> std::io::Result<Vec<u8>> {
+                self.metadata(path)?;
+                self.fs.read(path)
+            }
+
+            fn read_dir(&self, path: &Path) -> std::io::Result<Vec<OsString>> {
+                self.metadata(path)?;
+                self.fs.read_dir(path)
+            }
+        }
+
+        #[derive(Deserialize, Debug)]
+        #[allow(dead_code)]
+        struct Inner {
+            value: u32,
+        }
+
+        let mut fs = MemFs::new();
+        fs.insert("plain/a", "1");
+        fs.insert("plain/b", "2");
+        fs.insert("nested/x/value", "3");
+        let vanishing = |gone: &str| Vanishing {
+            fs: fs.clone(),
+            gone: PathBuf::from(gone),
+        };
+
+        let mut de = Deserializer::with_fs(vanishing("plain/b"), "plain");
+        let res = BTreeMap::<String, u32>::deserialize(&mut de);
+        assert!(
+            matches!(res, Err(DeError::ConcurrentModification(ref p)) if p == Path::new("plain/b")),
+            "{:?}",
+            res
+        );
+        // the deserializer is back where it started
+        assert_eq!(de.path, Path::new("plain"));
+
+        let mut de = Deserializer::with_fs(vanishing("nested/x"), "nested");
+        let res = BTreeMap::<String, Inner>::deserialize(&mut de);
+        assert!(
+            matches!(res, Err(DeError::ConcurrentModification(ref p)) if p == Path::new("nested/x")),
+            "{:?}",
+            res
+        );
+        assert_eq!(de.path, Path::new("nested"));
+    }
+
+    #[test]
+    fn test_size_hint() {
+        use serde::de::{Deserializer as _, MapAccess, SeqAccess};
+
+        /// Records the size hint given before the first element
+        struct HintVisitor;
+
+        impl<'de> Visitor<'de> for HintVisitor {
+            type Value = Option<usize>;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a sequence or map")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(
+                self,
+                seq: A,
+            ) -> std::result::Result<Self::Value, A::Error> {
+                Ok(seq.size_hint())
+            }
+
+            fn visit_map<A: MapAccess<'de>>(
+                self,
+                map: A,
+            ) -> std::result::Result<Self::Value, A::Error> {
+                Ok(map.size_hint())
+            }
+        }
+
+        let test_dir = "./.test-de-size-hint";
+        setup_test(
+            test_dir,
+            vec![
+                ("seq/0", "a"),
+                ("seq/1", "b"),
+                ("seq/2", "c"),
+                // not reachable, since the sequence ends at the missing `3`
+                ("seq/4", "e"),
+                ("map/x", "1"),
+                ("map/y", "2"),
+            ],
+        );
+        std::fs::write(format!("{}/{}", test_dir, VERSION_MARKER), "2").unwrap();
+
+        let mut de = Deserializer::from_fs(format!("{}/seq", test_dir));
+        assert_eq!(de.deserialize_seq(HintVisitor).unwrap(), Some(3));
+        let mut de = Deserializer::from_fs(format!("{}/map", test_dir));
+        assert_eq!(de.deserialize_map(HintVisitor).unwrap(), Some(2));
+        // the version marker at the root is not an entry
+        let mut de = Deserializer::from_fs(test_dir);
+        assert_eq!(de.deserialize_map(HintVisitor).unwrap(), Some(2));
+
+        let seq: Vec<String> = from_fs(&format!("{}/seq", test_dir)).unwrap();
+        assert_eq!(seq, ["a", "b", "c"]);
+
+        let _ = std::fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_allow_underscores() {
+        let test_dir = "./.test-de-allow-underscores";
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Data {
+            int: u64,
+            float: f64,
+            map: BTreeMap<i32, String>,
+        }
+
+        setup_test(
+            test_dir,
+            vec![("int", "1_000"), ("float", "1_0.5"), ("map/-2_0", "a")],
+        );
+
+        let res: Result<Data> = from_fs(test_dir);
+        assert!(matches!(res, Err(DeError::ParseError(_))));
+
+        let mut de = Deserializer::from_fs(test_dir).allow_underscores(true);
+        let expected = Data {
+            int: 1000,
+            float: 10.5,
+            map: [(-20, "a".into())].into(),
+        };
+        assert_eq!(expected, Data::deserialize(&mut de).unwrap());
+
+        // Separators cannot lead a number
+        setup_test(test_dir, vec![("int", "_1")]);
+        let mut de = Deserializer::from_fs(format!("{}/int", test_dir)).allow_underscores(true);
+        assert!(u64::deserialize(&mut de).is_err());
+
+        let _ = std::fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_lenient_ints() {
+        let test_dir = "./.test-de-lenient-ints";
+        setup_test(
+            test_dir,
+            vec![
+                ("float", "42.0"),
+                ("hex", "0x2A"),
+                ("octal", "0o52"),
+                ("binary", "-0b101010"),
+                ("padded", " 42\n"),
+                ("fraction", "42.5"),
+            ],
+        );
+        let read = |name: &str, lenient: bool| -> Result<i64> {
+            let mut de =
+                Deserializer::from_fs(format!("{}/{}", test_dir, name)).lenient_ints(lenient);
+            i64::deserialize(&mut de)
+        };
+
+        for name in ["float", "hex", "octal", "padded"] {
+            assert_eq!(read(name, true).unwrap(), 42, "{}", name);
+            assert!(read(name, false).is_err(), "{}", name);
+        }
+        assert_eq!(read("binary", true).unwrap(), -42);
+        assert!(read("fraction", true).is_err());
+
+        let mut de = Deserializer::from_fs(format!("{}/hex", test_dir)).lenient_ints(true);
+        assert_eq!(u64::deserialize(&mut de).unwrap(), 42);
+
+        let _ = std::fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_lenient_bools() {
+        let test_dir = "./.test-de-lenient-bools";
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Flags {
+            yes: bool,
+            one: bool,
+            upper: bool,
+            off: bool,
+            keys: BTreeMap<bool, u8>,
+        }
+
+        setup_test(
+            test_dir,
+            vec![
+                ("yes", "yes"),
+                ("one", "1"),
+                ("upper", "True"),
+                ("off", "OFF"),
+                ("keys/Y", "1"),
+                ("keys/N", "0"),
+            ],
+        );
+
+        let res: Result<Flags> = from_fs(test_dir);
+        assert!(matches!(res, Err(DeError::InvalidBool(_, _))));
+
+        let mut de = Deserializer::from_fs(test_dir).lenient_bools(BoolTokens::new(
+            ["yes", "1", "true", "y"],
+            ["no", "0", "false", "off", "n"],
+        ));
+        let expected = Flags {
+            yes: true,
+            one: true,
+            upper: true,
+            off: false,
+            keys: [(true, 1), (false, 0)].into(),
+        };
+        assert_eq!(expected, Flags::deserialize(&mut de).unwrap());
+
+        // A strict key is an error rather than a panic
+        setup_test(test_dir, vec![("yes", "1")]);
+        let res: Result<BTreeMap<bool, u8>> = from_fs(test_dir);
+        assert!(matches!(res, Err(DeError::InvalidBool(ref s, _)) if s == "yes"));
+
+        let _ = std::fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_json_scalars() {
+        let test_dir = "./.test-de-json-scalars";
+        #[derive(Deserialize, PartialEq, Debug)]
+        enum E {
+            Unit,
+            Newtype(u32),
+        }
+
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Data {
+            json_num: u32,
+            json_float: f64,
+            json_str: String,
+            json_bool: bool,
+            json_array: Vec<String>,
+            json_none: Option<u32>,
+            json_some: Option<u32>,
+            json_unit: E,
+            json_newtype: E,
+        }
+
+        setup_test(
+            test_dir,
+            vec![
+                ("json_num", "42"),
+                ("json_float", "1.5"),
+                ("json_str", r#""quoted""#),
+                ("json_bool", "true"),
+                ("json_array", r#"["a","b"]"#),
+                ("json_none", "null"),
+                ("json_some", "3"),
+                ("json_unit", r#""Unit""#),
+                ("json_newtype", r#"{"Newtype":9}"#),
+            ],
+        );
+        let expected = Data {
+            json_num: 42,
+            json_float: 1.5,
+            json_str: "quoted".into(),
+            json_bool: true,
+            json_array: vec!["a".into(), "b".into()],
+            json_none: None,
+            json_some: Some(3),
+            json_unit: E::Unit,
+            json_newtype: E::Newtype(9),
+        };
+        assert_eq!(expected, from_fs(test_dir).unwrap());
+
+        let _ = std::fs::remove_dir_all(test_dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinked_options() {
+        let test_dir = "./.test-de-symlinked-options";
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Data {
+            plain: u32,
+            linked: u32,
+            linked_option: Option<u32>,
+            dangling_option: Option<u32>,
+        }
+
+        setup_test(test_dir, vec![("target", "7"), ("plain", "1")]);
+        let target = std::fs::canonicalize(format!("{}/target", test_dir)).unwrap();
+        std::os::unix::fs::symlink(&target, format!("{}/linked", test_dir)).unwrap();
+        std::os::unix::fs::symlink(&target, format!("{}/linked_option", test_dir)).unwrap();
+        std::os::unix::fs::symlink(
+            format!("{}/missing", target.parent().unwrap().display()),
+            format!("{}/dangling_option", test_dir),
+        )
+        .unwrap();
+
+        // Options follow symlinks exactly like other fields, and a dangling one is `None`
+        let expected = Data {
+            plain: 1,
+            linked: 7,
+            linked_option: Some(7),
+            dangling_option: None,
+        };
+        assert_eq!(expected, from_fs(test_dir).unwrap());
+
+        let _ = std::fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_too_many_entries() {
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Data {
+            map: BTreeMap<String, u32>,
+            seq: Vec<u32>,
+        }
+
+        let test_dir = "./.test-de-too-many-entries";
+        setup_test(
+            test_dir,
+            vec![
+                ("map/a", "1"),
+                ("map/b", "2"),
+                ("map/c", "3"),
+                ("seq/0", "1"),
+                ("seq/1", "2"),
+            ],
+        );
+
+        let mut de = Deserializer::from_fs(test_dir).max_map_entries(2);
+        assert!(matches!(
+            Data::deserialize(&mut de),
+            Err(DeError::TooManyEntries { ref path, limit: 2 }) if path.ends_with("map")
+        ));
+        let mut de = Deserializer::from_fs(test_dir).max_seq_len(1);
+        assert!(matches!(
+            Data::deserialize(&mut de),
+            Err(DeError::TooManyEntries { ref path, limit: 1 }) if path.ends_with("seq")
+        ));
+        // limits that are not exceeded change nothing
+        let mut de = Deserializer::from_fs(test_dir)
+            .max_map_entries(3)
+            .max_seq_len(2);
+        assert_eq!(Data::deserialize(&mut de).unwrap().seq, vec![1, 2]);
+
+        let _ = std::fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_validate_fs() {
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Server {
+            port: u16,
+            ratio: f32,
+        }
+
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Config {
+            enabled: bool,
+            name: String,
+            server: Server,
+        }
+
+        let test_dir = "./.test-de-validate-fs";
+        let files = vec![
+            ("enabled", "true"),
+            ("name", "app"),
+            ("server/port", "80"),
+            ("server/ratio", "0.5"),
+        ];
+        setup_test(test_dir, files.clone());
+        assert!(validate_fs::<Config>(test_dir).is_ok());
+
+        // both independent errors are reported, not only the first
+        let mut files = files;
+        files[0].1 = "maybe";
+        files[2].1 = "99999";
+        setup_test(test_dir, files.clone());
+        let errors = validate_fs::<Config>(test_dir).unwrap_err();
+        assert_eq!(errors.len(), 2, "{:?}", errors);
+        assert!(matches!(errors[0], DeError::InvalidBool(ref s, _) if s == "maybe"));
+        assert!(matches!(errors[1], DeError::IntOutOfRange { ref value, .. } if value == "99999"));
+
+        // errors that end the validation come after the leaf errors found so far
+        files.remove(1);
+        setup_test(test_dir, files);
+        let errors = validate_fs::<Config>(test_dir).unwrap_err();
+        assert_eq!(errors.len(), 3, "{:?}", errors);
+        assert!(errors[2].to_string().contains("missing field `name`"));
+
+        let _ = std::fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_unknown_variant() {
+        #[derive(Deserialize, PartialEq, Debug)]
+        enum Mode {
+            Unit,
+            Value(u32),
+        }
+
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Data {
+            mode: Mode,
+        }
+
+        let test_dir = "./.test-de-unknown-variant";
+        let mode_path = Path::new(test_dir).join("mode");
+
+        // surrounding whitespace, like the newline an editor adds, is ignored
+        setup_test(test_dir, vec![("mode", "Unit\n")]);
+        let data: Data = from_fs(test_dir).unwrap();
+        assert_eq!(data.mode, Mode::Unit);
+
+        // the error shows what the file holds
+        setup_test(test_dir, vec![("mode", "Unit extra")]);
+        let res: Result<Data> = from_fs(test_dir);
+        assert!(
+            matches!(res, Err(DeError::UnknownVariant(ref found, ref path)) if found == "Unit extra" && path == &mode_path),
+            "{:?}",
+            res
+        );
+
+        setup_test(test_dir, vec![("mode/Other", "1")]);
+        let res: Result<Data> = from_fs(test_dir);
+        assert!(matches!(
+            res,
+            Err(DeError::UnknownVariant(ref found, ref path)) if found == "Other" && path == &mode_path.join("Other")
+        ));
+
+        let _ = std::fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_relative_error_paths() {
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Data {
+            seq: Vec<bool>,
+        }
+
+        let test_dir = "./.test-de-relative-error-paths";
+        setup_test(test_dir, vec![("seq/0", "maybe")]);
+        let root = std::fs::canonicalize(test_dir).unwrap();
+
+        let mut de = Deserializer::from_fs(&root);
+        let err = Data::deserialize(&mut de).unwrap_err();
+        assert!(matches!(err, DeError::InvalidBool(_, ref path) if path == &root.join("seq/0")));
+
+        let mut de = Deserializer::from_fs(&root).relative_error_paths(true);
+        let err = Data::deserialize(&mut de).unwrap_err();
+        assert!(matches!(err, DeError::InvalidBool(_, ref path) if path == Path::new("seq/0")));
+        assert!(!err.to_string().contains(&*root.to_string_lossy()));
+
+        let _ = std::fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_clone() {
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Server {
+            port: u16,
+            host: String,
+        }
+
+        let test_dir = "./.test-de-clone";
+        setup_test(
+            test_dir,
+            vec![("server/port", "80"), ("server/host", "localhost")],
+        );
+
+        let mut original = Deserializer::from_fs(format!("{}/server", test_dir)).lenient_ints(true);
+        let mut clone = original.clone();
+        let server = Server::deserialize(&mut original).unwrap();
+        // the clone reads the same subtree as another type, unaffected by the original
+        let map = BTreeMap::<String, String>::deserialize(&mut clone).unwrap();
+        assert_eq!(server.port, 80);
+        assert_eq!(map["host"], "localhost");
+        assert_eq!(original.at_path(), clone.at_path());
+        assert_eq!(Server::deserialize(&mut clone.clone()).unwrap(), server);
+
+        let _ = std::fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_struct_from_json_file() {
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Server {
+            port: u16,
+            host: String,
+        }
+
+        let test_dir = "./.test-de-struct-from-json-file";
+        setup_test(
+            test_dir,
+            vec![
+                ("server.json", "{\"port\": 80, \"host\": \"localhost\"}\n"),
+                ("plain", "80"),
+            ],
+        );
+
+        let server: Server = from_fs(&format!("{}/server.json", test_dir)).unwrap();
+        assert_eq!(
+            server,
+            Server {
+                port: 80,
+                host: "localhost".into(),
+            }
+        );
+        // files that are not json objects still name the mismatch
+        let expected = Path::new(test_dir).join("plain");
+        let res: Result<Server> = from_fs(expected.to_str().unwrap());
+        assert!(matches!(res, Err(DeError::ExpectedDirectory(p)) if p == expected));
+
+        let _ = std::fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_follow_includes() {
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Server {
+            port: u16,
+            host: String,
+        }
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Config {
+            name: String,
+            server: Server,
+            tags: BTreeMap<String, String>,
+        }
+
+        let test_dir = "./.test-de-follow-includes";
+        setup_test(
+            test_dir,
+            vec![
+                ("base/name", "base"),
+                ("base/server/port", "80"),
+                ("base/server/host", "localhost"),
+                ("base/tags/env", "dev"),
+                ("over/.include", "../base\n"),
+                ("over/name", "over"),
+                ("over/server/port", "8080"),
+                ("over/tags/team", "infra"),
+                ("escape/.include", "../../"),
+            ],
+        );
+
+        let over = format!("{}/over", test_dir);
+        let mut de = Deserializer::from_fs(&over).follow_includes(test_dir);
+        let config = Config::deserialize(&mut de).unwrap();
+        assert_eq!(
+            config,
+            Config {
+                name: "over".into(),
+                server: Server {
+                    port: 8080,
+                    host: "localhost".into(),
+                },
+                tags: [
+                    ("env".to_owned(), "dev".to_owned()),
+                    ("team".to_owned(), "infra".to_owned()),
+                ]
+                .into(),
+            }
+        );
+
+        // without the option the base tree is never read, so `server.host` is missing
+        let res: Result<Config> = from_fs(&over);
+        assert!(res.is_err());
+
+        let escape = Path::new(test_dir).join("escape");
+        let mut de = Deserializer::from_fs(&escape).follow_includes(test_dir);
+        let res = Config::deserialize(&mut de);
+        let expected = escape.join(INCLUDE_FILE);
+        assert!(matches!(res, Err(DeError::IncludeOutsideRoot(ref p)) if p == &expected));
+
+        // roots are compared as absolute paths, so `.` does not allow everything
+        let outside = std::env::temp_dir().join(".test-de-follow-includes-outside");
+        let _ = std::fs::remove_dir_all(&outside);
+        std::fs::create_dir_all(outside.join("server")).unwrap();
+        std::fs::write(outside.join("name"), "leaked").unwrap();
+        std::fs::write(escape.join(INCLUDE_FILE), outside.to_str().unwrap()).unwrap();
+        let mut de = Deserializer::from_fs(&escape).follow_includes(".");
+        let res = Config::deserialize(&mut de);
+        assert!(matches!(res, Err(DeError::IncludeOutsideRoot(ref p)) if p == &expected));
+        std::fs::remove_dir_all(&outside).unwrap();
+
+        // a root with `..` in it is resolved before comparing
+        let mut de = Deserializer::from_fs(&over).follow_includes(format!("{}/over/..", test_dir));
+        assert_eq!(Config::deserialize(&mut de).unwrap().name, "over");
+        let mut de = Deserializer::from_fs(&over).follow_includes(format!("{}/base/..", over));
+        let res = Config::deserialize(&mut de);
+        let expected = Path::new(&over).join(INCLUDE_FILE);
+        assert!(matches!(res, Err(DeError::IncludeOutsideRoot(ref p)) if p == &expected));
+
+        // a root that cannot be resolved allows nothing
+        let mut de = Deserializer::from_fs(&over).follow_includes("/..");
+        let res = Config::deserialize(&mut de);
+        assert!(matches!(res, Err(DeError::IncludeOutsideRoot(ref p)) if p == Path::new("/..")));
+
+        let _ = std::fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_env_overrides() {
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Database {
+            url: String,
+            pool_size: u32,
+        }
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Config {
+            database: Database,
+        }
+
+        let test_dir = "./.test-de-env-overrides";
+        setup_test(
+            test_dir,
+            vec![
+                ("database/url", "postgres://localhost"),
+                ("database/pool_size", "4"),
+            ],
+        );
+        std::env::set_var("SERDE_FS_TEST_DATABASE_URL", "postgres://prod");
+        std::env::set_var("SERDE_FS_TEST_DATABASE_POOL_SIZE", "16");
+
+        let mut de = Deserializer::from_fs(test_dir).env_overrides("SERDE_FS_TEST");
+        let config = Config::deserialize(&mut de);
+        let plain: Config = from_fs(test_dir).unwrap();
+        std::env::remove_var("SERDE_FS_TEST_DATABASE_URL");
+        std::env::remove_var("SERDE_FS_TEST_DATABASE_POOL_SIZE");
+
+        assert_eq!(
+            config.unwrap(),
+            Config {
+                database: Database {
+                    url: "postgres://prod".into(),
+                    pool_size: 16,
+                },
+            }
+        );
+        assert_eq!(plain.database.url, "postgres://localhost");
+
+        let _ = std::fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_unknown_json_field() {
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Small {
+            a: u32,
+        }
+
+        let test_dir = "./.test-de-unknown-json-field";
+        setup_test(
+            test_dir,
+            vec![
+                ("a", "1"),
+                ("json_extra", r#"{"x":1}"#),
+                ("json_broken", "{"),
+            ],
+        );
+        let mut de = Deserializer::from_fs(test_dir);
+        assert_eq!(Small::deserialize(&mut de).unwrap(), Small { a: 1 });
+        std::fs::remove_dir_all(test_dir).unwrap();
+    }
+
+    #[test]
+    fn test_unit_sentinel_with_unknown_fields() {
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Flags {
+            unit: (),
+        }
+
+        let test_dir = "./.test-de-unit-sentinel-unknown";
+        setup_test(
+            test_dir,
+            vec![("unit", "unit"), ("extra", "hello"), ("dir/inner", "1")],
+        );
+        let mut de =
+            Deserializer::from_fs(test_dir).unit_repr(UnitRepr::SentinelFile("unit".into()));
+        assert_eq!(Flags::deserialize(&mut de).unwrap(), Flags { unit: () });
+        std::fs::remove_dir_all(test_dir).unwrap();
+    }
+
+    #[test]
+    fn test_any_typed_leaves() {
+        let test_dir = "./.test-de-any-typed-leaves";
+        setup_test(
+            test_dir,
+            vec![
+                ("count", "u5"),
+                ("bom", "\u{feff}i-3"),
+                ("name", "sname"),
+                ("port", "u80"),
+            ],
+        );
+        std::env::set_var("SERDE_FS_TEST_ANY_PORT", "8080");
+
+        // each leaf is read the same way a typed field would read it
+        let mut de = Deserializer::from_fs(test_dir)
+            .typed_leaves(true)
+            .env_overrides("SERDE_FS_TEST_ANY");
+        let value = serde_json::Value::deserialize(&mut de).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({ "count": 5, "bom": -3, "name": "name", "port": "8080" })
+        );
+
+        std::env::remove_var("SERDE_FS_TEST_ANY_PORT");
+        let _ = std::fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_skipped_json_value() {
+        /// Reads only the `normal` field, never asking for the value of any other
+        #[derive(Debug, PartialEq)]
+        struct OnlyNormal(String);
+
+        impl<'de> Deserialize<'de> for OnlyNormal {
+            fn deserialize<D: de::Deserializer<'de>>(
+                deserializer: D,
+            ) -> std::result::Result<Self, D::Error> {
+                struct OnlyNormalVisitor;
+
+                impl<'de> Visitor<'de> for OnlyNormalVisitor {
+                    type Value = OnlyNormal;
+
+                    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                        f.write_str("a struct with a normal field")
+                    }
+
+                    fn visit_map<A: MapAccess<'de>>(
+                        self,
+                        mut map: A,
+                    ) -> std::result::Result<OnlyNormal, A::Error> {
+                        let mut normal = None;
+                        while let Some(key) = map.next_key::<String>()? {
+                            if key == "normal" {
+                                normal = Some(map.next_value()?);
+                            }
+                        }
+                        normal
+                            .map(OnlyNormal)
+                            .ok_or_else(|| de::Error::missing_field("normal"))
+                    }
+                }
+
+                deserializer.deserialize_struct(
+                    "OnlyNormal",
+                    &["json_skipped", "normal"],
+                    OnlyNormalVisitor,
+                )
+            }
+        }
+
+        let test_dir = "./.test-de-skipped-json-value";
+        setup_test(
+            test_dir,
+            vec![("json_skipped", "[1, 2]"), ("normal", "plain text")],
+        );
+        let value: OnlyNormal = from_fs(test_dir).unwrap();
+        assert_eq!(value, OnlyNormal("plain text".into()));
+
+        let _ = std::fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_padded_seq_indices() {
+        let test_dir = "./.test-de-padded-seq-indices";
+        let files: Vec<(String, String)> = (0..=10)
+            .map(|i| (format!("seq/{:02}", i), i.to_string()))
+            .collect();
+        setup_test(
+            test_dir,
+            files
+                .iter()
+                .map(|(path, data)| (path.as_str(), data.as_str()))
+                .collect(),
+        );
+
+        let map: BTreeMap<String, Vec<u32>> = from_fs(test_dir).unwrap();
+        assert_eq!(map["seq"], (0..=10).collect::<Vec<_>>());
+
+        // a gap still ends the sequence
+        std::fs::remove_file(format!("{}/seq/05", test_dir)).unwrap();
+        let map: BTreeMap<String, Vec<u32>> = from_fs(test_dir).unwrap();
+        assert_eq!(map["seq"], vec![0, 1, 2, 3, 4]);
+
+        // a file is not an empty sequence
+        #[derive(Deserialize, Debug)]
+        #[allow(dead_code)]
+        struct V {
+            v: Vec<String>,
+        }
+        setup_test(test_dir, vec![("v", "abc")]);
+        assert!(from_fs::<V>(test_dir).is_err());
+
+        let _ = std::fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_struct_from_sequence() {
+        #[derive(Deserialize, Debug)]
+        #[allow(dead_code)]
+        struct Point {
+            x: i32,
+            y: i32,
+        }
+        #[derive(Deserialize, Debug)]
+        #[allow(dead_code)]
+        struct Shape {
+            origin: Point,
+        }
+
+        let test_dir = "./.test-de-struct-from-sequence";
+        setup_test(test_dir, vec![("origin/0", "1"), ("origin/1", "2")]);
+        let res: Result<Shape> = from_fs(test_dir);
+        let expected = Path::new(test_dir).join("origin");
+        assert!(matches!(
+            res,
+            Err(DeError::ShapeMismatch {
+                expected: "struct",
+                found: "sequence",
+                path,
+            }) if path == expected
+        ));
+
+        let _ = std::fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_missing_field() {
+        #[derive(Deserialize, Debug)]
+        #[allow(dead_code)]
+        struct Database {
+            url: String,
+            port: u16,
+            user: Option<String>,
+        }
+        #[derive(Deserialize, Debug)]
+        #[allow(dead_code)]
+        struct Config {
+            database: Database,
+        }
+
+        let test_dir = "./.test-de-missing-field";
+        setup_test(test_dir, vec![("database/url", "localhost")]);
+        let res: Result<Config> = from_fs(test_dir);
+        let expected = Path::new(test_dir).join("database").join("port");
+        assert!(matches!(
+            res,
+            Err(DeError::MissingField {
+                field: "port",
+                expected_path,
+            }) if expected_path == expected
+        ));
+
+        let _ = std::fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_strip_bom() {
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Leaves {
+            number: u32,
+            text: String,
+            flag: bool,
+        }
+
+        let test_dir = "./.test-de-strip-bom";
+        setup_test(
+            test_dir,
+            vec![
+                ("number", "\u{FEFF}42"),
+                ("text", "\u{FEFF}hello"),
+                ("flag", "\u{FEFF}true"),
+            ],
+        );
+        let leaves: Leaves = from_fs(test_dir).unwrap();
+        assert_eq!(
+            leaves,
+            Leaves {
+                number: 42,
+                text: "hello".into(),
+                flag: true,
+            }
+        );
+
+        let mut de = Deserializer::from_fs(test_dir).strip_bom(false);
+        assert!(Leaves::deserialize(&mut de).is_err());
+
         let _ = std::fs::remove_dir_all(test_dir);
     }
 }
@@ -0,0 +1,109 @@
+//! Stores a sequence of scalars as a single leaf, each element followed by a delimiter, instead of
+//! as a directory holding a file per element. Meant for short lists such as tags and flags:
+//!
+//! ```
+//! # use serde::{Deserialize, Serialize};
+//! #[derive(Serialize, Deserialize)]
+//! struct Post {
+//!     // one tag per line
+//!     #[serde(with = "serde_fs::delimited")]
+//!     tags: Vec<String>,
+//!     // `1,2,3,`
+//!     #[serde(with = "serde_fs::delimited::With::<','>")]
+//!     ids: Vec<i32>,
+//! }
+//! ```
+//!
+//! Elements are written with [`Display`] and read with [`FromStr`]. A delimiter or `\` inside an
+//! element is escaped with a `\`. The delimiter after the last element may be left out when the
+//! leaf is edited by hand, though an empty last element then has to keep it.
+
+use std::fmt::Display;
+use std::str::FromStr;
+
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::Serializer;
+
+/// Writes `values` as one leaf, each followed by a newline
+pub fn serialize<T, S>(values: &[T], serializer: S) -> Result<S::Ok, S::Error>
+where
+    T: Display,
+    S: Serializer,
+{
+    With::<'\n'>::serialize(values, serializer)
+}
+
+/// Reads a leaf written by [`serialize`]
+pub fn deserialize<'de, T, D>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    T: FromStr,
+    T::Err: Display,
+    D: Deserializer<'de>,
+{
+    With::<'\n'>::deserialize(deserializer)
+}
+
+/// Like the functions of this module, with `DELIMITER` following each element instead of a
+/// newline
+pub struct With<const DELIMITER: char>;
+
+impl<const DELIMITER: char> With<DELIMITER> {
+    pub fn serialize<T, S>(values: &[T], serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: Display,
+        S: Serializer,
+    {
+        assert_ne!(DELIMITER, '\\', "\\ escapes delimiters and cannot be one");
+        let mut leaf = String::new();
+        for value in values {
+            for c in value.to_string().chars() {
+                if c == DELIMITER || c == '\\' {
+                    leaf.push('\\');
+                }
+                leaf.push(c);
+            }
+            leaf.push(DELIMITER);
+        }
+        serializer.serialize_str(&leaf)
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<Vec<T>, D::Error>
+    where
+        T: FromStr,
+        T::Err: Display,
+        D: Deserializer<'de>,
+    {
+        let leaf = String::deserialize(deserializer)?;
+        let mut values = Vec::new();
+        let mut element = String::new();
+        let mut chars = leaf.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some(escaped) => element.push(escaped),
+                    None => return Err(de::Error::custom("leaf ends in an unfinished escape")),
+                },
+                c if c == DELIMITER => {
+                    values.push(parse(&element)?);
+                    element.clear();
+                }
+                c => element.push(c),
+            }
+        }
+        if !element.is_empty() {
+            values.push(parse(&element)?);
+        }
+        Ok(values)
+    }
+}
+
+fn parse<T, E>(element: &str) -> Result<T, E>
+where
+    T: FromStr,
+    T::Err: Display,
+    E: de::Error,
+{
+    element
+        .parse()
+        .map_err(|err| E::custom(format_args!("invalid element {:?}: {}", element, err)))
+}
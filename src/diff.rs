@@ -0,0 +1,158 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::de::Result;
+use crate::ser::{GIT_ATTRIBUTES, GIT_IGNORE, OBJECTS_DIR};
+use crate::share::SHARED_MANIFEST;
+use crate::version::VERSION_MARKER;
+
+/// A difference between two trees found by [`diff_fs`]. Paths are relative to the roots
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TreeDiff {
+    /// A leaf or empty directory that only exists in the first tree
+    OnlyInA(PathBuf),
+    /// A leaf or empty directory that only exists in the second tree
+    OnlyInB(PathBuf),
+    /// A leaf that exists in both trees with different contents
+    Changed {
+        path: PathBuf,
+        a_bytes: Vec<u8>,
+        b_bytes: Vec<u8>,
+    },
+}
+
+/// What a tree holds at a path
+#[derive(PartialEq)]
+enum Entry {
+    File(Vec<u8>),
+    /// An empty directory, which is how empty collections are stored
+    EmptyDir,
+}
+
+/// Compares the trees at `a` and `b` and returns every leaf that differs, sorted by path.
+///
+/// Files holding format metadata, like the [`VERSION_MARKER`], are not compared. Symlinks to
+/// files are compared by their target's contents, symlinks to directories are not followed
+pub fn diff_fs(a: impl AsRef<Path>, b: impl AsRef<Path>) -> Result<Vec<TreeDiff>> {
+    let mut a_entries = BTreeMap::new();
+    collect(a.as_ref(), a.as_ref(), &mut a_entries)?;
+    let mut b_entries = BTreeMap::new();
+    collect(b.as_ref(), b.as_ref(), &mut b_entries)?;
+
+    let mut diffs = Vec::new();
+    for (path, a_entry) in &a_entries {
+        match (a_entry, b_entries.get(path)) {
+            (a_entry, Some(b_entry)) if a_entry == b_entry => {}
+            (Entry::File(a_bytes), Some(Entry::File(b_bytes))) => diffs.push(TreeDiff::Changed {
+                path: path.clone(),
+                a_bytes: a_bytes.clone(),
+                b_bytes: b_bytes.clone(),
+            }),
+            (_, Some(_)) => {
+                diffs.push(TreeDiff::OnlyInA(path.clone()));
+                diffs.push(TreeDiff::OnlyInB(path.clone()));
+            }
+            (_, None) => diffs.push(TreeDiff::OnlyInA(path.clone())),
+        }
+    }
+    for path in b_entries.keys() {
+        if !a_entries.contains_key(path) {
+            diffs.push(TreeDiff::OnlyInB(path.clone()));
+        }
+    }
+    diffs.sort_by(|x, y| diff_path(x).cmp(diff_path(y)));
+    Ok(diffs)
+}
+
+fn diff_path(diff: &TreeDiff) -> &Path {
+    match diff {
+        TreeDiff::OnlyInA(path) | TreeDiff::OnlyInB(path) => path,
+        TreeDiff::Changed { path, .. } => path,
+    }
+}
+
+/// Records every leaf and empty directory below `dir`, keyed by its path relative to `root`
+fn collect(root: &Path, dir: &Path, out: &mut BTreeMap<PathBuf, Entry>) -> Result<()> {
+    let mut empty = true;
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        empty = false;
+        let relative = path.strip_prefix(root).unwrap().to_owned();
+        let internal = [
+            VERSION_MARKER,
+            OBJECTS_DIR,
+            GIT_ATTRIBUTES,
+            GIT_IGNORE,
+            SHARED_MANIFEST,
+        ];
+        if internal.iter().any(|name| relative == Path::new(name)) {
+            continue;
+        }
+        let file_type = fs::symlink_metadata(&path)?.file_type();
+        if file_type.is_dir() {
+            collect(root, &path, out)?;
+        } else if !file_type.is_symlink() || fs::metadata(&path)?.is_file() {
+            out.insert(relative, Entry::File(fs::read(&path)?));
+        }
+    }
+    if empty && dir != root {
+        out.insert(dir.strip_prefix(root).unwrap().to_owned(), Entry::EmptyDir);
+    }
+    Ok(())
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde::Serialize;
+
+    #[test]
+    fn test_diff_fs() {
+        #[derive(Serialize)]
+        struct Config {
+            name: &'static str,
+            port: u16,
+            tags: Vec<&'static str>,
+        }
+
+        let a_dir = "./.test-diff-a";
+        let b_dir = "./.test-diff-b";
+        let _ = fs::remove_dir_all(a_dir);
+        let _ = fs::remove_dir_all(b_dir);
+        let config = Config {
+            name: "app",
+            port: 80,
+            tags: vec!["a"],
+        };
+        crate::to_fs(&config, a_dir).unwrap();
+        crate::to_fs(
+            &Config {
+                port: 8080,
+                ..config
+            },
+            b_dir,
+        )
+        .unwrap();
+        fs::write(format!("{}/extra", b_dir), "new").unwrap();
+
+        assert_eq!(diff_fs(a_dir, a_dir).unwrap(), vec![]);
+        assert_eq!(
+            diff_fs(a_dir, b_dir).unwrap(),
+            vec![
+                TreeDiff::OnlyInB(PathBuf::from("extra")),
+                TreeDiff::Changed {
+                    path: PathBuf::from("port"),
+                    a_bytes: b"80".to_vec(),
+                    b_bytes: b"8080".to_vec(),
+                },
+            ]
+        );
+
+        fs::remove_dir_all(a_dir).unwrap();
+        fs::remove_dir_all(b_dir).unwrap();
+    }
+}
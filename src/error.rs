@@ -15,6 +15,36 @@ pub enum SerError {
     #[error("io error: {0}")]
     IoError(#[from] std::io::Error),
 
+    #[error("root path is an existing file {0}")]
+    RootIsFile(PathBuf),
+
+    #[error("root directory is not empty {0}")]
+    RootNotEmpty(PathBuf),
+
+    #[error("path {0} is not a plain relative path")]
+    InvalidRelativePath(PathBuf),
+
+    #[error("{0} cannot be used as map keys")]
+    UnsupportedKey(&'static str),
+
+    #[error("path is longer than the configured maximum {0}")]
+    PathTooLong(PathBuf),
+
+    #[error("map key {0:?} cannot be used as a path component")]
+    InvalidKey(String),
+
+    #[error("{0} cannot be serialized by the float policy")]
+    NonFiniteFloat(f64),
+
+    #[error("map keys {a:?} and {b:?} differ only in case")]
+    CaseCollision { a: String, b: String },
+
+    #[error("symlink points outside of the root {0}")]
+    SymlinkOutsideRoot(PathBuf),
+
+    #[error("reading the existing tree: {0}")]
+    ReadExisting(#[from] DeError),
+
     #[error("{0}")]
     Serde(String),
 
@@ -39,15 +69,73 @@ pub enum DeError {
     #[error("symlinks are not allowed {0}")]
     EncounteredSymlink(PathBuf),
 
+    #[error("expected a directory but found a file {0}")]
+    ExpectedDirectory(PathBuf),
+
+    #[error("expected a file but found a directory {0}")]
+    ExpectedFile(PathBuf),
+
     #[error("invalid unicode")]
     InvalidUnicode,
 
     #[error("invalid bool \"{0}\" {1}")]
     InvalidBool(String, PathBuf),
 
+    #[error("entry removed while it was being read {0}")]
+    ConcurrentModification(PathBuf),
+
+    #[error("expected {expected} elements but found {found} {path}")]
+    ArrayLengthMismatch {
+        expected: usize,
+        found: usize,
+        path: PathBuf,
+    },
+
+    #[error("missing field `{field}` {expected_path}")]
+    MissingField {
+        field: &'static str,
+        expected_path: PathBuf,
+    },
+
+    #[error("expected a {expected} but found a {found} {path}")]
+    ShapeMismatch {
+        expected: &'static str,
+        found: &'static str,
+        path: PathBuf,
+    },
+
+    #[error("unknown variant \"{0}\" {1}")]
+    UnknownVariant(String, PathBuf),
+
+    #[error("no variant with index \"{0}\" {1}")]
+    UnknownVariantIndex(String, PathBuf),
+
+    #[error("more than {limit} entries {path}")]
+    TooManyEntries { path: PathBuf, limit: usize },
+
+    #[error("include {0} points outside of the allowed root")]
+    IncludeOutsideRoot(PathBuf),
+
+    #[error("includes nested too deeply, likely a cycle {0}")]
+    IncludeCycle(PathBuf),
+
+    #[error("expected a single char but found \"{0}\" {1}")]
+    InvalidChar(String, PathBuf),
+
+    #[error("invalid hex \"{0}\" {1}")]
+    InvalidHex(String, PathBuf),
+
     #[error("parse: {0}")]
     ParseError(String),
 
+    #[error("{value} is out of range for {ty}, expected {min}..={max}")]
+    IntOutOfRange {
+        value: String,
+        ty: &'static str,
+        min: String,
+        max: String,
+    },
+
     #[error("{0}")]
     Serde(String),
 
@@ -71,16 +159,24 @@ impl serde::de::Error for DeError {
     {
         DeError::Serde(t.to_string())
     }
+
+    /// The path is filled in by the deserializer of the struct missing the field
+    fn missing_field(field: &'static str) -> Self {
+        DeError::MissingField {
+            field,
+            expected_path: PathBuf::new(),
+        }
+    }
 }
 
 impl From<ParseIntError> for DeError {
     fn from(e: ParseIntError) -> Self {
-        DeError::ParseError(e.to_string()).into()
+        DeError::ParseError(e.to_string())
     }
 }
 
 impl From<ParseFloatError> for DeError {
     fn from(e: ParseFloatError) -> Self {
-        DeError::ParseError(e.to_string()).into()
+        DeError::ParseError(e.to_string())
     }
 }
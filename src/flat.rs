@@ -0,0 +1,175 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::de::{self, Deserializer};
+use crate::error::SerError;
+use crate::ser::{Result, Serializer, Write};
+use crate::vfs::MemFs;
+
+type Error = SerError;
+
+/// Serializes `value` into a flat map from `parent/child/leaf` style keys to leaf contents,
+/// without touching the file system.
+///
+/// Empty collections have no leaves, so they do not appear in the map.
+pub fn to_flat_map<T>(value: &T) -> Result<BTreeMap<String, String>>
+where
+    T: Serialize,
+{
+    to_flat_map_with_separator(value, "/")
+}
+
+/// Like [`to_flat_map`], but joins path components with `separator`. A map key containing
+/// `separator` could not be told apart from a nested path, so it is an
+/// [`InvalidKey`](SerError::InvalidKey)
+pub fn to_flat_map_with_separator<T>(value: &T, separator: &str) -> Result<BTreeMap<String, String>>
+where
+    T: Serialize,
+{
+    let mut serializer = Serializer::buffered();
+    value.serialize(&mut serializer)?;
+
+    let mut map = BTreeMap::new();
+    for write in serializer.into_writes() {
+        if let Write::File(path, data) = write {
+            let key = path
+                .iter()
+                .map(|c| match c.to_str() {
+                    Some(c) if !c.contains(separator) => Ok(c),
+                    _ => Err(Error::InvalidKey(c.to_string_lossy().into())),
+                })
+                .collect::<Result<Vec<_>>>()?
+                .join(separator);
+            let value = String::from_utf8(data).map_err(Error::Utf8Error)?;
+            map.insert(key, value);
+        }
+    }
+    Ok(map)
+}
+
+/// Deserializes a value from a flat map produced by [`to_flat_map`], without touching the file
+/// system
+pub fn from_flat_map<T>(map: &BTreeMap<String, String>) -> de::Result<T>
+where
+    T: DeserializeOwned,
+{
+    from_flat_map_with_separator(map, "/")
+}
+
+/// Like [`from_flat_map`], but splits keys into path components at `separator`
+pub fn from_flat_map_with_separator<T>(
+    map: &BTreeMap<String, String>,
+    separator: &str,
+) -> de::Result<T>
+where
+    T: DeserializeOwned,
+{
+    let mut fs = MemFs::new();
+    for (key, value) in map {
+        let path: PathBuf = key.split(separator).collect();
+        fs.insert(path, value.as_bytes());
+    }
+    let mut deserializer = Deserializer::with_fs(fs, "");
+    T::deserialize(&mut deserializer)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[test]
+    fn test_to_flat_map() {
+        #[derive(Serialize)]
+        struct Database {
+            url: &'static str,
+            port: u16,
+        }
+
+        #[derive(Serialize)]
+        struct Config {
+            name: &'static str,
+            database: Database,
+            tags: Vec<&'static str>,
+            missing: Option<u8>,
+        }
+
+        let config = Config {
+            name: "app",
+            database: Database {
+                url: "localhost",
+                port: 5432,
+            },
+            tags: vec!["a", "b"],
+            missing: None,
+        };
+
+        let expected: BTreeMap<String, String> = [
+            ("name", "app"),
+            ("database/url", "localhost"),
+            ("database/port", "5432"),
+            ("tags/0", "a"),
+            ("tags/1", "b"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_owned(), v.to_owned()))
+        .collect();
+        assert_eq!(to_flat_map(&config).unwrap(), expected);
+
+        let flat = to_flat_map_with_separator(&config, "__").unwrap();
+        assert_eq!(flat["database__port"], "5432");
+        assert_eq!(flat["tags__1"], "b");
+    }
+
+    #[test]
+    fn test_flat_map_roundtrip() {
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        enum Mode {
+            Fast,
+            Custom { level: u8 },
+        }
+
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct Config {
+            name: String,
+            ports: Vec<u16>,
+            limits: BTreeMap<String, u64>,
+            mode: Mode,
+            other_mode: Mode,
+            json_extra: Vec<bool>,
+            opt: Option<String>,
+        }
+
+        let config = Config {
+            name: "app".into(),
+            ports: vec![80, 443],
+            limits: [("cpu".into(), 4), ("mem".into(), 1024)].into(),
+            mode: Mode::Fast,
+            other_mode: Mode::Custom { level: 3 },
+            json_extra: vec![true, false],
+            opt: None,
+        };
+
+        let flat = to_flat_map(&config).unwrap();
+        assert_eq!(from_flat_map::<Config>(&flat).unwrap(), config);
+
+        let flat = to_flat_map_with_separator(&config, ".").unwrap();
+        assert_eq!(flat["limits.mem"], "1024");
+        assert_eq!(
+            from_flat_map_with_separator::<Config>(&flat, ".").unwrap(),
+            config
+        );
+    }
+
+    #[test]
+    fn test_key_containing_separator() {
+        let versions: BTreeMap<String, u8> = [("1.5".into(), 1)].into();
+        let err = to_flat_map_with_separator(&versions, ".").unwrap_err();
+        assert!(matches!(err, SerError::InvalidKey(ref key) if key == "1.5"));
+        assert_eq!(to_flat_map(&versions).unwrap()["1.5"], "1");
+    }
+}
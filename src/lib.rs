@@ -4,9 +4,39 @@
 /// # Example
 /// ```
 /// ```
+mod as_file;
+mod cache;
 mod de;
+pub mod delimited;
+mod diff;
 mod error;
+mod flat;
 mod ser;
+mod share;
+mod sync;
+mod temp;
+mod transcode;
+mod version;
+mod vfs;
 
-pub use de::{from_fs, Deserializer};
-pub use ser::{to_fs, Serializer};
+pub use as_file::{AsFile, Json, LeafFormat};
+pub use cache::{CachedFs, LeafCache};
+pub use de::{
+    from_fs, probe_fs, validate_fs, BoolTokens, DeConfig, Deserializer, NodeKind, INCLUDE_FILE,
+};
+pub use diff::{diff_fs, TreeDiff};
+pub use error::{DeError, SerError};
+pub use flat::{
+    from_flat_map, from_flat_map_with_separator, to_flat_map, to_flat_map_with_separator,
+};
+pub use ser::{
+    serialize_iter_to_fs, to_fs, to_fs_at, to_fs_path, to_fs_stats, Align, BytesRepr, EnumRepr,
+    FloatPolicy, OnExistingRoot, OptionRepr, SerConfig, SerStats, Serializer, UnitRepr, INDEX_FILE,
+    OBJECTS_DIR,
+};
+pub use share::SHARED_MANIFEST;
+pub use sync::{migrate_fs, sync_fs, SyncReport};
+pub use temp::cleanup_fs;
+pub use transcode::{fs_to_json, json_to_fs};
+pub use version::{FormatVersion, VERSION_MARKER};
+pub use vfs::{EntryKind, FileSystem, MemFs, StdFs};
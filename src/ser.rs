@@ -1,19 +1,357 @@
+use std::borrow::Cow;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write as _;
 use std::fs;
+use std::ops::Range;
 use std::path::{Path, PathBuf};
 
 use serde::{ser, Serialize};
 
+use crate::as_file::{Json, LeafFormat};
 use crate::error::SerError;
+use crate::share::{share_subtrees, SHARED_MANIFEST};
+use crate::version::{FormatVersion, VERSION_MARKER};
 
 type Error = SerError;
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Name of the directory at the root of a tree holding the shared leaf contents written by
+/// [`Serializer::dedup`]
+pub const OBJECTS_DIR: &str = ".serde_fs_objects";
+
+/// Name of the file listing the entries of a directory, see [`Serializer::write_indexes`]
+pub const INDEX_FILE: &str = "_index";
+
+/// Names of the files written at the root by [`Serializer::git_helpers`]
+pub(crate) const GIT_ATTRIBUTES: &str = ".gitattributes";
+pub(crate) const GIT_IGNORE: &str = ".gitignore";
+
+/// The default [`Serializer::max_path_len`]: `MAX_PATH` on Windows, `PATH_MAX` elsewhere
+#[cfg(windows)]
+const DEFAULT_MAX_PATH_LEN: usize = 260;
+#[cfg(not(windows))]
+const DEFAULT_MAX_PATH_LEN: usize = 4096;
+
+/// A single change to the file system produced by serialization
+pub(crate) enum Write {
+    /// Creates a (possibly empty) directory
+    Dir(PathBuf),
+    /// Writes a leaf file
+    File(PathBuf, Vec<u8>),
+}
+
+impl Write {
+    /// Moves the write from below `from` to below `to`
+    fn rebased(self, from: &Path, to: &Path) -> Self {
+        match self {
+            Write::Dir(path) => Write::Dir(rebase(path, from, to)),
+            Write::File(path, data) => Write::File(rebase(path, from, to), data),
+        }
+    }
+}
+
+/// Moves `path` from below `from` to below `to`, leaving other paths as they are
+fn rebase(path: PathBuf, from: &Path, to: &Path) -> PathBuf {
+    match path.strip_prefix(from) {
+        // joining an empty path would add a trailing separator
+        Ok(rest) if rest.as_os_str().is_empty() => to.to_owned(),
+        Ok(rest) => to.join(rest),
+        Err(_) => path,
+    }
+}
+
+/// Writes that have been buffered instead of sent to the file system
+type Writes = Vec<Write>;
+
+/// Counts of what a serialization wrote, see [`to_fs_stats`]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SerStats {
+    /// Number of leaf files written
+    pub files: usize,
+    /// Number of directories below the root
+    pub directories: usize,
+    /// Sum of the sizes of all leaf files
+    pub total_bytes: u64,
+    /// Deepest level written to, where direct children of the root are at depth 1
+    pub max_depth: usize,
+}
+
+/// Records which paths a serialization wrote to the file system
+#[derive(Debug, Default)]
+pub(crate) struct Tracker {
+    /// Every file and directory written, including the parents of written files
+    pub written: HashSet<PathBuf>,
+    /// Files that did not exist before
+    pub added: Vec<PathBuf>,
+    /// Files that existed with different contents
+    pub updated: Vec<PathBuf>,
+    /// Directories created, once each time a write could not reuse the last one created
+    pub created_dirs: Vec<PathBuf>,
+}
+
+impl Tracker {
+    /// Adds what `other` recorded
+    fn merge(&mut self, other: Tracker) {
+        self.written.extend(other.written);
+        self.added.extend(other.added);
+        self.updated.extend(other.updated);
+        self.created_dirs.extend(other.created_dirs);
+    }
+
+    fn record(&mut self, write: &Write) {
+        let path = match write {
+            Write::Dir(path) => path,
+            Write::File(path, data) => {
+                match fs::read(path) {
+                    Ok(old) if &old == data => {}
+                    Ok(_) => self.updated.push(path.clone()),
+                    Err(_) => self.added.push(path.clone()),
+                }
+                path
+            }
+        };
+        for p in path.ancestors() {
+            if !self.written.insert(p.to_owned()) {
+                break;
+            }
+        }
+    }
+}
+
+/// What to do with a root directory that already has entries in it, see
+/// [`Serializer::on_existing_root`]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OnExistingRoot {
+    /// Write over the existing tree. Entries that the value does not overwrite are left in place
+    #[default]
+    Reuse,
+    /// Delete everything inside the root before writing
+    Clear,
+    /// Return [`SerError::RootNotEmpty`] without writing anything
+    Fail,
+}
+
+/// How `None` is stored on disk, see [`Serializer::option_repr`] and
+/// [`Deserializer::option_repr`](crate::Deserializer::option_repr)
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub enum OptionRepr {
+    /// Nothing is written for `None`
+    #[default]
+    MissingFile,
+    /// `None` is an empty file, so `Some("")` cannot be told apart from `None`
+    EmptyFile,
+    /// `None` is a file containing the given marker, for example `null`
+    SentinelFile(String),
+}
+
+impl OptionRepr {
+    /// Returns the contents of the file written for `None`, if any
+    pub(crate) fn none_contents(&self) -> Option<&[u8]> {
+        match self {
+            OptionRepr::MissingFile => None,
+            OptionRepr::EmptyFile => Some(b""),
+            OptionRepr::SentinelFile(sentinel) => Some(sentinel.as_bytes()),
+        }
+    }
+}
+
+/// How `()` and unit structs are stored on disk, see [`Serializer::unit_repr`] and
+/// [`Deserializer::unit_repr`](crate::Deserializer::unit_repr)
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub enum UnitRepr {
+    /// Nothing is written, so `Some(())` cannot be told apart from `None`
+    #[default]
+    MissingFile,
+    /// Units are a file containing the given marker. Pick one that differs from the
+    /// [`OptionRepr`] sentinel, if any
+    SentinelFile(String),
+}
+
+/// How the variant of an enum is stored on disk, see [`Serializer::enum_repr`] and
+/// [`Deserializer::enum_repr`](crate::Deserializer::enum_repr)
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum EnumRepr {
+    /// Variants are written by name
+    #[default]
+    ByName,
+    /// Variants are written by their index in the enum declaration, which is more compact and
+    /// survives renaming variants, but not reordering them
+    ByIndex,
+}
+
+/// How byte buffers, like `serde_bytes` fields, are stored on disk, see
+/// [`Serializer::bytes_repr`] and [`Deserializer::bytes_repr`](crate::Deserializer::bytes_repr)
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum BytesRepr {
+    /// The bytes are written as they are
+    #[default]
+    Raw,
+    /// The bytes are written as lowercase hex, which keeps short buffers like hashes and keys
+    /// readable at twice the size
+    Hex,
+}
+
+/// What is written for NaN and infinite floats, see [`Serializer::float_policy`]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum FloatPolicy {
+    /// Serializing them fails with [`SerError::NonFiniteFloat`]
+    Reject,
+    /// They are written as `NaN`, `inf` and `-inf`, which loses the sign and payload of NaNs
+    #[default]
+    AllowLossy,
+    /// They are written as their bit pattern in hex, like `0x7ff8000000000000`, so that they are
+    /// read back bit for bit
+    EncodeBits,
+}
+
+/// Which side of a padded number leaf the number sits on, see [`Serializer::pad_numbers`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Align {
+    /// The fill follows the number
+    Left,
+    /// The fill precedes the number
+    Right,
+}
+
+/// The type of a scalar leaf, stored as its first byte by [`Serializer::typed_leaves`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LeafType {
+    Bool,
+    Signed,
+    Unsigned,
+    Float,
+    Char,
+    Str,
+    Bytes,
+}
+
+impl LeafType {
+    const ALL: [LeafType; 7] = [
+        LeafType::Bool,
+        LeafType::Signed,
+        LeafType::Unsigned,
+        LeafType::Float,
+        LeafType::Char,
+        LeafType::Str,
+        LeafType::Bytes,
+    ];
+
+    pub(crate) fn tag(self) -> u8 {
+        match self {
+            LeafType::Bool => b'b',
+            LeafType::Signed => b'i',
+            LeafType::Unsigned => b'u',
+            LeafType::Float => b'f',
+            LeafType::Char => b'c',
+            LeafType::Str => b's',
+            LeafType::Bytes => b'x',
+        }
+    }
+
+    pub(crate) fn from_tag(tag: u8) -> Option<Self> {
+        Self::ALL.into_iter().find(|ty| ty.tag() == tag)
+    }
+}
+
+/// The options of a [`Serializer`], as set by its builder methods. See the method of the same name
+/// for what each does
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SerConfig {
+    pub sort_maps: bool,
+    pub skip_none_entries: bool,
+    pub detect_case_collisions: bool,
+    pub lowercase_keys: bool,
+    /// See [`Serializer::collapse_below_depth`]
+    pub collapse_below_depth: Option<usize>,
+    pub on_existing_root: OnExistingRoot,
+    pub option_repr: OptionRepr,
+    pub unit_repr: UnitRepr,
+    pub enum_repr: EnumRepr,
+    pub bytes_repr: BytesRepr,
+    pub float_policy: FloatPolicy,
+    pub typed_leaves: bool,
+    /// See [`Serializer::pad_int_keys`]
+    pub int_key_width: Option<usize>,
+    /// Width, fill and alignment, see [`Serializer::pad_numbers`]
+    pub number_padding: Option<(usize, char, Align)>,
+    pub seq_base: usize,
+    pub json_keys: bool,
+    pub git_helpers: bool,
+    pub share_subtrees: bool,
+    pub write_indexes: bool,
+    pub ordered_maps: bool,
+    pub dedup: bool,
+    pub durable: bool,
+    pub max_path_len: usize,
+    #[cfg(unix)]
+    pub file_mode: Option<u32>,
+    #[cfg(unix)]
+    pub dir_mode: Option<u32>,
+}
+
+impl Default for SerConfig {
+    fn default() -> Self {
+        Self {
+            sort_maps: false,
+            skip_none_entries: false,
+            detect_case_collisions: false,
+            lowercase_keys: false,
+            collapse_below_depth: None,
+            on_existing_root: OnExistingRoot::default(),
+            option_repr: OptionRepr::default(),
+            unit_repr: UnitRepr::default(),
+            enum_repr: EnumRepr::default(),
+            bytes_repr: BytesRepr::default(),
+            float_policy: FloatPolicy::default(),
+            typed_leaves: false,
+            int_key_width: None,
+            number_padding: None,
+            seq_base: 0,
+            json_keys: false,
+            git_helpers: false,
+            share_subtrees: false,
+            write_indexes: false,
+            ordered_maps: false,
+            dedup: false,
+            durable: false,
+            max_path_len: DEFAULT_MAX_PATH_LEN,
+            #[cfg(unix)]
+            file_mode: None,
+            #[cfg(unix)]
+            dir_mode: None,
+        }
+    }
+}
+
 pub struct Serializer {
+    /// The directory everything is written below
+    root: PathBuf,
     /// The current path this serializer is at
     path: PathBuf,
     path_dirty: bool,
     /// How many push we have
     dir_level: usize,
+    config: SerConfig,
+    /// Whether `on_existing_root` has been applied yet
+    root_prepared: bool,
+    /// For each struct being written, its index header and the fields written so far, when
+    /// `write_indexes` is set
+    struct_indexes: Vec<(String, Vec<String>)>,
+    /// Raw byte leaves written so far, relative to the root, when `git_helpers` is set
+    binary_leaves: Vec<PathBuf>,
+    /// Existing leaves may be hard links shared with other leaves, so they have to be unlinked
+    /// instead of written through
+    unlink_leaves: bool,
+    /// The directory the last write created, so writes to its siblings skip creating it again
+    created_dir: Option<PathBuf>,
+    /// When set, writes are recorded here instead of being sent to the file system
+    buffer: Option<Writes>,
+    /// Records the paths written, when enabled
+    tracker: Option<Tracker>,
+    /// For each pushed path, whether it has been counted as a directory in `stats`
+    counted_dirs: Vec<bool>,
+    stats: SerStats,
 }
 
 pub fn to_fs<T>(value: &T, path: impl AsRef<Path>) -> Result<()>
@@ -22,51 +360,901 @@ where
 {
     let mut serializer = Serializer::new(path)?;
     value.serialize(&mut serializer)?;
+    serializer.finish()?;
+    Ok(())
+}
+
+/// Like [`to_fs`], but also returns how many files, directories and bytes were written
+pub fn to_fs_stats<T>(value: &T, path: impl AsRef<Path>) -> Result<SerStats>
+where
+    T: Serialize,
+{
+    let mut serializer = Serializer::new(path)?;
+    value.serialize(&mut serializer)?;
+    serializer.finish()
+}
+
+/// Like [`to_fs`], but also returns the root that was written, made absolute with every symlink
+/// resolved so it can be handed to other processes
+pub fn to_fs_path<T>(value: &T, path: impl AsRef<Path>) -> Result<PathBuf>
+where
+    T: Serialize,
+{
+    to_fs(value, path.as_ref())?;
+    Ok(fs::canonicalize(path)?)
+}
+
+/// Serializes each item of `iter` as an element of a sequence at `path`, written to `0`, `1`, ...
+/// as it is produced. Unlike collecting into a `Vec` and calling [`to_fs`], only one item is held
+/// at a time, so arbitrarily long sequences can be streamed to disk
+pub fn serialize_iter_to_fs<I>(iter: I, path: impl AsRef<Path>) -> Result<()>
+where
+    I: IntoIterator,
+    I::Item: Serialize,
+{
+    let mut serializer = Serializer::new(path)?;
+    let mut seq = ser::Serializer::serialize_seq(&mut serializer, None)?;
+    for item in iter {
+        SerializeSeq::serialize_element(&mut seq, &item)?;
+    }
+    SerializeSeq::end(seq)?;
+    serializer.finish()?;
     Ok(())
 }
 
-impl Serializer {
-    fn new(path: impl AsRef<Path>) -> Result<Self> {
-        let path = PathBuf::from(path.as_ref());
-        Ok(Self {
-            path,
-            path_dirty: false,
-            dir_level: 0,
-        })
+/// Serializes `value` into `root.join(relative)`, replacing whatever was there before while
+/// leaving the rest of the tree under `root` untouched. The value may be a single leaf, which
+/// updates one file of the tree.
+///
+/// `relative` must be a non empty relative path without `.` or `..` components, so that the
+/// write can never escape `root` nor replace all of it
+pub fn to_fs_at<T>(value: &T, root: impl AsRef<Path>, relative: impl AsRef<Path>) -> Result<()>
+where
+    T: Serialize,
+{
+    let (root, relative) = (root.as_ref(), relative.as_ref());
+    check_relative(relative)?;
+    let components = relative
+        .iter()
+        .map(|c| c.to_str())
+        .collect::<Option<Vec<_>>>()
+        .filter(|components| !components.is_empty())
+        .ok_or_else(|| Error::InvalidRelativePath(relative.to_owned()))?;
+
+    // remove eagerly, a value with no leaves still replaces the old entry
+    let target = root.join(relative);
+    match fs::symlink_metadata(&target) {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(&target)?,
+        // also removes symlinks without touching their target
+        Ok(_) => fs::remove_file(&target)?,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err.into()),
+    }
+
+    // written from the root, like any other part of the tree, so leaves are allowed
+    let mut serializer = Serializer::new(root)?;
+    for component in &components {
+        serializer.push(component)?;
+    }
+    value.serialize(&mut serializer)?;
+    for _ in &components {
+        serializer.pop();
+    }
+    serializer.finish()?;
+    Ok(())
+}
+
+impl Serializer {
+    pub fn new(path: impl AsRef<Path>) -> Result<Self> {
+        Self::with_config(path, SerConfig::default())
+    }
+
+    /// Creates a serializer with all options given at once, the same as calling each builder
+    /// method on a new serializer
+    pub fn with_config(path: impl AsRef<Path>, config: SerConfig) -> Result<Self> {
+        let path = PathBuf::from(path.as_ref());
+        // Everything is written below the root, which cannot happen if the root is a file
+        if fs::metadata(&path).is_ok_and(|m| m.is_file()) {
+            return Err(Error::RootIsFile(path));
+        }
+        let buffer = config.share_subtrees.then(Vec::new);
+        Ok(Self {
+            root: path.clone(),
+            path,
+            path_dirty: false,
+            dir_level: 0,
+            config,
+            root_prepared: false,
+            struct_indexes: Vec::new(),
+            binary_leaves: Vec::new(),
+            unlink_leaves: false,
+            created_dir: None,
+            buffer,
+            tracker: None,
+            counted_dirs: Vec::new(),
+            stats: SerStats::default(),
+        })
+    }
+
+    /// Returns the options this serializer writes with
+    pub fn config(&self) -> &SerConfig {
+        &self.config
+    }
+
+    /// Returns the directory everything is written below
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Returns the path this serializer is currently writing to
+    pub fn at_path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Creates a serializer that records every write relative to an empty root instead of
+    /// touching the file system. See [`Serializer::into_writes`]
+    pub(crate) fn buffered() -> Self {
+        Self {
+            buffer: Some(Vec::new()),
+            ..Self::new("").unwrap()
+        }
+    }
+
+    /// Returns a serializer with the same options that writes below `relative` from the current
+    /// path, so that part of the tree can be written separately, for example from another thread.
+    ///
+    /// The branch starts with its own [`SerStats`]. Hand it back with [`Serializer::join`] once
+    /// it is done instead of finishing it, since finishing a branch writes the [`VERSION_MARKER`]
+    /// where the branch starts, and what a branch holds back until the end, like the writes of
+    /// [`Serializer::share_subtrees`] or the leaves listed by [`Serializer::git_helpers`], is
+    /// only written by finishing the original
+    pub fn branch(&mut self, relative: impl AsRef<Path>) -> Result<Self> {
+        let relative = relative.as_ref();
+        check_relative(relative)?;
+        // prepare now, so clearing the root later cannot remove what the branch wrote
+        if !self.root_prepared {
+            self.prepare_root()?;
+        }
+        Ok(Self {
+            // keep the root, shared state like `OBJECTS_DIR` lives there
+            root: self.root.clone(),
+            path: self.path.join(relative),
+            path_dirty: false,
+            dir_level: 0,
+            config: self.config.clone(),
+            root_prepared: true,
+            struct_indexes: Vec::new(),
+            binary_leaves: Vec::new(),
+            unlink_leaves: self.unlink_leaves,
+            created_dir: None,
+            buffer: self.buffer.as_ref().map(|_| Vec::new()),
+            tracker: self.tracker.as_ref().map(|_| Tracker::default()),
+            counted_dirs: Vec::new(),
+            stats: SerStats::default(),
+        })
+    }
+
+    /// Takes back a serializer made by [`Serializer::branch`]: its stats are added to this one's,
+    /// and whatever it held back is written when this serializer is finished
+    pub fn join(&mut self, branch: Serializer) {
+        if let (Some(buffer), Some(writes)) = (&mut self.buffer, branch.buffer) {
+            buffer.extend(writes);
+        }
+        self.binary_leaves.extend(branch.binary_leaves);
+        if let (Some(tracker), Some(branched)) = (&mut self.tracker, branch.tracker) {
+            tracker.merge(branched);
+        }
+        // the branch counts its depth from where it starts
+        let offset = branch
+            .path
+            .strip_prefix(&branch.root)
+            .map_or(0, |start| start.components().count());
+        self.stats.files += branch.stats.files;
+        self.stats.directories += branch.stats.directories;
+        self.stats.total_bytes += branch.stats.total_bytes;
+        self.stats.max_depth = self.stats.max_depth.max(branch.stats.max_depth + offset);
+    }
+
+    pub(crate) fn into_writes(self) -> Vec<Write> {
+        self.buffer.unwrap_or_default()
+    }
+
+    pub(crate) fn track(&mut self) {
+        self.tracker = Some(Tracker::default());
+    }
+
+    /// Ends the serialization: writes the [`VERSION_MARKER`] at the root and returns what was
+    /// written.
+    ///
+    /// Values serialized without calling this are complete on disk, but cannot be told apart
+    /// from trees written by versions of this crate that predate the marker
+    pub fn finish(self) -> Result<SerStats> {
+        self.finish_tracked().map(|(stats, _)| stats)
+    }
+
+    /// Like [`Serializer::finish`], but also returns the paths recorded since [`Serializer::track`]
+    pub(crate) fn finish_tracked(mut self) -> Result<(SerStats, Option<Tracker>)> {
+        if self.config.share_subtrees {
+            self.write_shared()?;
+        }
+        self.write_version_marker()?;
+        if self.config.git_helpers {
+            self.write_git_helpers()?;
+        }
+        Ok((self.stats, self.tracker))
+    }
+
+    /// Returns what has been written so far
+    pub fn stats(&self) -> &SerStats {
+        &self.stats
+    }
+
+    /// Buffers the entries of each map and writes them sorted by their stringified key.
+    ///
+    /// This makes the order in which files are created deterministic, so unordered maps like
+    /// `HashMap` produce the same tree as the equivalent `BTreeMap`.
+    pub fn sort_maps(mut self, sort_maps: bool) -> Self {
+        self.config.sort_maps = sort_maps;
+        self
+    }
+
+    /// Writes every value more than `depth` levels below the root as a single JSON leaf, so that
+    /// the top of the tree stays browsable while deep, bulky subtrees take one file each instead
+    /// of one per leaf. With a depth of 1 the fields of the root are directories, and their
+    /// fields are JSON files.
+    ///
+    /// Trees must be read with the same
+    /// [`Deserializer::collapse_below_depth`](crate::Deserializer::collapse_below_depth)
+    pub fn collapse_below_depth(mut self, depth: usize) -> Self {
+        self.config.collapse_below_depth = Some(depth);
+        self
+    }
+
+    /// Fails with [`SerError::CaseCollision`] when two keys of a map differ only in case, like
+    /// `Key` and `key`, since on case insensitive file systems such as the defaults of macOS and
+    /// Windows one would overwrite the other
+    pub fn detect_case_collisions(mut self, detect: bool) -> Self {
+        self.config.detect_case_collisions = detect;
+        self
+    }
+
+    /// Lowercases map keys, so that a tree is written the same on every file system. Keys that
+    /// collide once lowercased fail with [`SerError::CaseCollision`].
+    ///
+    /// The original case of string keys is lost. Trees should be read with
+    /// [`Deserializer::lowercase_keys`](crate::Deserializer::lowercase_keys) so that enum keys
+    /// still find their variant
+    pub fn lowercase_keys(mut self, lowercase: bool) -> Self {
+        self.config.lowercase_keys = lowercase;
+        self
+    }
+
+    /// Leaves out map entries whose value is `None`, so that neither the key nor a file for the
+    /// `None` is written whatever the [`Serializer::option_repr`]. Such entries read back as
+    /// missing from the map.
+    ///
+    /// Only applies to maps serialized through `serialize_entry`, which is what the standard
+    /// collections use
+    pub fn skip_none_entries(mut self, skip: bool) -> Self {
+        self.config.skip_none_entries = skip;
+        self
+    }
+
+    /// Sets how `None` is written. Trees should be read with the same
+    /// [`Deserializer::option_repr`](crate::Deserializer::option_repr)
+    pub fn option_repr(mut self, repr: OptionRepr) -> Self {
+        self.config.option_repr = repr;
+        self
+    }
+
+    /// Sets how `()` and unit structs are written. Trees should be read with the same
+    /// [`Deserializer::unit_repr`](crate::Deserializer::unit_repr)
+    pub fn unit_repr(mut self, repr: UnitRepr) -> Self {
+        self.config.unit_repr = repr;
+        self
+    }
+
+    /// Sets how the variants of externally tagged enums are written, which is both the contents
+    /// of unit variants and the directory name of the others. Enum map keys are always written by
+    /// name. Trees should be read with the same
+    /// [`Deserializer::enum_repr`](crate::Deserializer::enum_repr)
+    pub fn enum_repr(mut self, repr: EnumRepr) -> Self {
+        self.config.enum_repr = repr;
+        self
+    }
+
+    /// Sets how byte buffers are written. Trees should be read with the same
+    /// [`Deserializer::bytes_repr`](crate::Deserializer::bytes_repr)
+    pub fn bytes_repr(mut self, repr: BytesRepr) -> Self {
+        self.config.bytes_repr = repr;
+        self
+    }
+
+    /// Sets what is written for NaN and infinite floats. Finite floats are always written in
+    /// decimal, which already reads back exactly
+    pub fn float_policy(mut self, policy: FloatPolicy) -> Self {
+        self.config.float_policy = policy;
+        self
+    }
+
+    /// Prefixes scalar leaves with a byte naming their type: `b`ool, `i`nteger, `u`nsigned
+    /// integer, `f`loat, `c`har, `s`tring or `x` for bytes. This makes the tree self describing,
+    /// so reads without a type hint, like into `serde_json::Value`, tell the string `"42"` from
+    /// the number `42`.
+    ///
+    /// Trees must be read with [`Deserializer::typed_leaves`](crate::Deserializer::typed_leaves)
+    pub fn typed_leaves(mut self, typed: bool) -> Self {
+        self.config.typed_leaves = typed;
+        self
+    }
+
+    /// Numbers the elements of sequences from `base` instead of 0, for tools that expect
+    /// `1`, `2`, ... Trees must be read with the same
+    /// [`Deserializer::seq_base`](crate::Deserializer::seq_base)
+    pub fn seq_base(mut self, base: usize) -> Self {
+        self.config.seq_base = base;
+        self
+    }
+
+    /// Zero pads integer map keys to `width` digits, after the sign, so that listing a directory
+    /// sorts non negative keys numerically: `7` becomes `0007` and `-7` becomes `-0007` for a
+    /// width of 4. Wider keys are written as they are.
+    ///
+    /// Trees must be read with
+    /// [`Deserializer::padded_int_keys`](crate::Deserializer::padded_int_keys)
+    pub fn pad_int_keys(mut self, width: usize) -> Self {
+        self.config.int_key_width = Some(width);
+        self
+    }
+
+    /// Pads the contents of number leaves to `width` chars with `fill`, for trees read by fixed
+    /// width parsers or displayed in columns: `42` becomes `   42` for a width of 5, a space
+    /// fill and [`Align::Right`]. Wider numbers are written as they are.
+    ///
+    /// Trees must be read with
+    /// [`Deserializer::padded_numbers`](crate::Deserializer::padded_numbers) and the same fill.
+    ///
+    /// # Panics
+    ///
+    /// If `fill` is a char numbers are written with, like a digit or `-`, since the padding
+    /// could then not be told apart from the number
+    pub fn pad_numbers(mut self, width: usize, fill: char, align: Align) -> Self {
+        assert!(
+            !fill.is_ascii_digit() && !matches!(fill, '-' | '.' | '_'),
+            "{:?} cannot pad numbers",
+            fill
+        );
+        self.config.number_padding = Some((width, fill, align));
+        self
+    }
+
+    /// Writes map keys that are not a single string, like tuples and structs, as their JSON with
+    /// `%`, `/`, `\` and NUL percent encoded, so maps such as `BTreeMap<(u8, u8), V>` can be
+    /// serialized. Without this those keys are [`SerError::UnsupportedKey`].
+    ///
+    /// The deserializer always reads such keys back
+    pub fn json_keys(mut self, json_keys: bool) -> Self {
+        self.config.json_keys = json_keys;
+        self
+    }
+
+    /// Makes [`Serializer::finish`] write a `.gitattributes` marking raw byte leaves as binary,
+    /// and a `.gitignore` for the temporary entries of interrupted writes, at the root of the
+    /// tree. The deserializer skips both files
+    pub fn git_helpers(mut self, git_helpers: bool) -> Self {
+        self.config.git_helpers = git_helpers;
+        self
+    }
+
+    /// Writes an [`INDEX_FILE`] in every struct and map directory, listing the names of its
+    /// entries, to help browsing large trees by hand. Struct indexes start with the struct's name.
+    ///
+    /// The deserializer skips these files by default, see
+    /// [`Deserializer::ignore_index_files`](crate::Deserializer::ignore_index_files). Map keys
+    /// named like the index are [`SerError::InvalidKey`]
+    pub fn write_indexes(mut self, write: bool) -> Self {
+        self.config.write_indexes = write;
+        self
+    }
+
+    /// Stores each directory whose whole subtree repeats an earlier one, in path order, as an empty
+    /// directory, and records which directory it repeats in [`SHARED_MANIFEST`] at the root. The
+    /// first copy is written as usual, so the tree can still be browsed. Subtrees with a single
+    /// leaf are always written out.
+    ///
+    /// Nothing is written until [`Serializer::finish`], since a subtree can only be compared once
+    /// the whole value is known. The deserializer reads shared directories from the copy named in
+    /// the manifest, when reading from the root of the tree
+    pub fn share_subtrees(mut self, share: bool) -> Self {
+        self.config.share_subtrees = share;
+        if share && self.buffer.is_none() {
+            self.buffer = Some(Vec::new());
+        }
+        self
+    }
+
+    /// Stores each distinct leaf content once in [`OBJECTS_DIR`], keyed by a hash of the content,
+    /// and writes leaves as hard links to it.
+    ///
+    /// Trees with many identical values take up a fraction of the space, and since hard links are
+    /// indistinguishable from regular files they are read like any other tree
+    pub fn dedup(mut self, dedup: bool) -> Self {
+        self.config.dedup = dedup;
+        self
+    }
+
+    /// Names map entries `<index>.<key>`, where `index` counts up from 0 in the order the map
+    /// yields its entries, or in key order when [`Serializer::sort_maps`] is set.
+    ///
+    /// Directories have no order, so this is what lets insertion ordered maps like `IndexMap` keep
+    /// their order. Trees must be read with
+    /// [`Deserializer::ordered_maps`](crate::Deserializer::ordered_maps)
+    pub fn ordered_maps(mut self, ordered: bool) -> Self {
+        self.config.ordered_maps = ordered;
+        self
+    }
+
+    /// Flushes every file written, and the directory entries pointing at it, to disk before moving
+    /// on, so that a tree which was written successfully survives a crash or power loss.
+    ///
+    /// This costs two fsyncs per leaf, which can make serialization orders of magnitude slower
+    /// on trees with many small leaves. Directories can only be flushed on unix
+    pub fn durable(mut self, durable: bool) -> Self {
+        self.config.durable = durable;
+        self
+    }
+
+    /// Sets the longest path, in bytes and including the root, that may be written to.
+    ///
+    /// Paths are checked as they are built, so a value nested too deeply fails with
+    /// [`SerError::PathTooLong`] before the branch holding the long path is created. Defaults to
+    /// the platform's limit
+    pub fn max_path_len(mut self, max: usize) -> Self {
+        self.config.max_path_len = max;
+        self
+    }
+
+    /// Sets the permissions of every file written, for example `0o600`, regardless of the umask
+    #[cfg(unix)]
+    pub fn file_mode(mut self, mode: u32) -> Self {
+        self.config.file_mode = Some(mode);
+        self
+    }
+
+    /// Sets the permissions of every directory created, for example `0o700`, regardless of the
+    /// umask. Directories that already exist keep their permissions
+    #[cfg(unix)]
+    pub fn dir_mode(mut self, mode: u32) -> Self {
+        self.config.dir_mode = Some(mode);
+        self
+    }
+
+    /// Sets what happens when the root directory already has entries in it.
+    ///
+    /// The policy is applied right before the first write, so a root is never cleared by a
+    /// serialization that fails before writing anything.
+    pub fn on_existing_root(mut self, policy: OnExistingRoot) -> Self {
+        self.config.on_existing_root = policy;
+        self
+    }
+
+    /// Writes `data` to a file at `relative` below the root, for files that are not part of the
+    /// serialized value, like a README.
+    ///
+    /// The write goes through the same policies as serialized leaves, such as
+    /// [`Serializer::on_existing_root`] and [`Serializer::dedup`]. `relative` must be a non-empty
+    /// relative path without `.` or `..` components
+    pub fn write_leaf(&mut self, relative: impl AsRef<Path>, data: impl AsRef<[u8]>) -> Result<()> {
+        let relative = relative.as_ref();
+        check_relative(relative)?;
+        let components = relative
+            .iter()
+            .map(|c| c.to_str())
+            .collect::<Option<Vec<_>>>()
+            .filter(|c| !c.is_empty())
+            .ok_or_else(|| Error::InvalidRelativePath(relative.to_owned()))?;
+
+        let level = self.dir_level;
+        for component in components {
+            self.push(component)?;
+        }
+        let res = self.write_data(data);
+        while self.dir_level > level {
+            self.pop();
+        }
+        res
+    }
+
+    /// Writes data to the current file position.
+    ///
+    /// # Panics
+    /// This function panics if it is called representedly without a call to [`pop`] before.
+    /// This is done to prevet data loss, as there may be data already written to the current path
+    /// that we cant overwrite
+    fn write_data(&mut self, s: impl AsRef<[u8]>) -> Result<()> {
+        if self.path_dirty {
+            panic!("BUG: path dirty: {}", self.path.to_string_lossy());
+        }
+        // A leaf needs a file name, which the root does not have
+        self.fail_if_at_root("values")?;
+        let path = self.path.clone();
+        self.emit(Write::File(path, s.as_ref().to_vec()))?;
+        self.path_dirty = true;
+
+        self.count_dirs(self.dir_level - 1);
+        self.stats.files += 1;
+        self.stats.total_bytes += s.as_ref().len() as u64;
+        self.stats.max_depth = self.stats.max_depth.max(self.dir_level);
+        Ok(())
+    }
+
+    /// Writes a scalar leaf, prefixed with its type if [`Serializer::typed_leaves`] is set
+    fn write_scalar(&mut self, ty: LeafType, data: impl AsRef<[u8]>) -> Result<()> {
+        if !self.config.typed_leaves {
+            return self.write_data(data);
+        }
+        let mut leaf = Vec::with_capacity(data.as_ref().len() + 1);
+        leaf.push(ty.tag());
+        leaf.extend_from_slice(data.as_ref());
+        self.write_data(leaf)
+    }
+
+    /// Writes a text leaf. Readers strip a leading byte order mark by default, see
+    /// [`Deserializer::strip_bom`](crate::Deserializer::strip_bom), so text that starts with one
+    /// gets a second one in front of it to read back unchanged
+    fn write_text(&mut self, ty: LeafType, text: &str) -> Result<()> {
+        if self.config.typed_leaves || !text.starts_with('\u{feff}') {
+            return self.write_scalar(ty, text);
+        }
+        self.write_scalar(ty, format!("\u{feff}{}", text))
+    }
+
+    /// Writes a number leaf, padded as set by [`Serializer::pad_numbers`]
+    fn write_number(&mut self, ty: LeafType, number: &str) -> Result<()> {
+        let (width, fill, align) = match self.config.number_padding {
+            Some(padding) if number.len() < padding.0 => padding,
+            _ => return self.write_scalar(ty, number),
+        };
+        let fill: String = std::iter::repeat_n(fill, width - number.len()).collect();
+        let padded = match align {
+            Align::Left => number.to_owned() + &fill,
+            Align::Right => fill + number,
+        };
+        self.write_scalar(ty, padded)
+    }
+
+    /// Writes a NaN or infinite float according to [`Serializer::float_policy`], `bits` being
+    /// its bit pattern in hex
+    fn write_non_finite(&mut self, v: f64, bits: String) -> Result<()> {
+        match self.config.float_policy {
+            FloatPolicy::Reject => Err(Error::NonFiniteFloat(v)),
+            FloatPolicy::AllowLossy => self.write_number(LeafType::Float, &v.to_string()),
+            // never padded, the fill could be mistaken for hex digits
+            FloatPolicy::EncodeBits => self.write_scalar(LeafType::Float, bits),
+        }
+    }
+
+    /// Serializes `value` at the current path, as a JSON leaf if `json` is set or the path is
+    /// deeper than [`Serializer::collapse_below_depth`]
+    fn serialize_child<T>(&mut self, value: &T, json: bool) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        let depth = self
+            .path
+            .strip_prefix(&self.root)
+            .map_or(0, |p| p.components().count());
+        let collapsed = self
+            .config
+            .collapse_below_depth
+            .is_some_and(|max| depth > max);
+        if json || collapsed {
+            let s = serde_json::to_string(value)?;
+            s.serialize(self)
+        } else {
+            value.serialize(self)
+        }
+    }
+
+    /// Creates the directory at the current path so that empty collections still exist on disk
+    fn write_dir(&mut self) -> Result<()> {
+        let path = self.path.clone();
+        self.emit(Write::Dir(path))?;
+
+        self.count_dirs(self.dir_level);
+        self.stats.max_depth = self.stats.max_depth.max(self.dir_level);
+        Ok(())
+    }
+
+    /// Creates the directory at the current path unless something was written below it, so that
+    /// containers that are empty, or whose entries all wrote nothing, still exist on disk and are
+    /// not read back as `None`
+    fn ensure_dir(&mut self) -> Result<()> {
+        match self.dir_level.checked_sub(1) {
+            Some(level) if !self.counted_dirs[level] => self.write_dir(),
+            None if self.stats.files == 0 && self.stats.directories == 0 => self.write_dir(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Counts the first `levels` pushed paths as directories, unless they already were
+    fn count_dirs(&mut self, levels: usize) {
+        for counted in self.counted_dirs[..levels].iter_mut().rev() {
+            if *counted {
+                // parents of a counted directory are counted too
+                break;
+            }
+            *counted = true;
+            self.stats.directories += 1;
+        }
+    }
+
+    /// Sends a write to the active buffer, or to the file system if nothing is buffering
+    fn emit(&mut self, write: Write) -> Result<()> {
+        if let Some(buffer) = &mut self.buffer {
+            buffer.push(write);
+            return Ok(());
+        }
+        if !self.root_prepared {
+            self.prepare_root()?;
+        }
+        if let Some(tracker) = &mut self.tracker {
+            tracker.record(&write);
+        }
+        let dir = match &write {
+            Write::Dir(path) => path.as_path(),
+            Write::File(path, _) => path.parent().unwrap(),
+        };
+        if self.created_dir.as_deref() != Some(dir) {
+            self.create_dir_all(dir)?;
+            self.created_dir = Some(dir.to_owned());
+            if let Some(tracker) = &mut self.tracker {
+                tracker.created_dirs.push(dir.to_owned());
+            }
+        }
+        if let Write::File(path, data) = write {
+            if self.config.dedup {
+                self.write_deduplicated(&path, &data)?;
+            } else {
+                if self.unlink_leaves {
+                    remove_leaf(&path)?;
+                }
+                self.write_file(&path, &data)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Creates `dir` and its missing parents, applying `dir_mode` to the ones created
+    fn create_dir_all(&self, dir: &Path) -> Result<()> {
+        #[cfg(unix)]
+        let dir_mode = self.config.dir_mode;
+        #[cfg(not(unix))]
+        let dir_mode: Option<u32> = None;
+
+        if dir_mode.is_none() && !self.config.durable {
+            return match fs::create_dir_all(dir) {
+                Ok(()) => Ok(()),
+                Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => Ok(()),
+                Err(err) => Err(err.into()),
+            };
+        }
+        let missing: Vec<_> = dir.ancestors().take_while(|d| !d.exists()).collect();
+        for d in missing.into_iter().rev() {
+            match fs::create_dir(d) {
+                Err(err) if err.kind() != std::io::ErrorKind::AlreadyExists => {
+                    return Err(err.into())
+                }
+                _ => {}
+            }
+            #[cfg(unix)]
+            if let Some(mode) = dir_mode {
+                use std::os::unix::fs::PermissionsExt;
+                fs::set_permissions(d, fs::Permissions::from_mode(mode))?;
+            }
+            if self.config.durable {
+                sync_parent(d)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes a leaf file, applying `file_mode` and `durable`
+    fn write_file(&self, path: &Path, data: &[u8]) -> Result<()> {
+        use std::io::Write as _;
+
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        if let Some(mode) = self.config.file_mode {
+            use std::os::unix::fs::OpenOptionsExt;
+            // Created with the mode so the contents are never readable with looser permissions
+            options.mode(mode);
+        }
+        let mut file = options.open(path)?;
+        #[cfg(unix)]
+        if let Some(mode) = self.config.file_mode {
+            use std::os::unix::fs::PermissionsExt;
+            // The umask applies on creation and existing files keep their old mode
+            file.set_permissions(fs::Permissions::from_mode(mode))?;
+        }
+        file.write_all(data)?;
+        if self.config.durable {
+            file.sync_all()?;
+            sync_parent(path)?;
+        }
+        Ok(())
+    }
+
+    /// Hard links `path` to the object holding `data`, creating the object if needed
+    fn write_deduplicated(&mut self, path: &Path, data: &[u8]) -> Result<()> {
+        let objects = self.root.join(OBJECTS_DIR);
+        self.create_dir_all(&objects)?;
+        let object = objects.join(format!("{:016x}", content_hash(data)));
+        if let Some(tracker) = &mut self.tracker {
+            tracker.written.insert(objects.clone());
+            tracker.written.insert(object.clone());
+        }
+
+        remove_leaf(path)?;
+        match fs::read(&object) {
+            Ok(existing) if existing == data => {}
+            // Hash collision, the leaf gets its own copy
+            Ok(_) => return self.write_file(path, data),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                self.write_file(&object, data)?
+            }
+            Err(err) => return Err(err.into()),
+        }
+        fs::hard_link(&object, path)?;
+        if self.config.durable {
+            sync_parent(path)?;
+        }
+        Ok(())
+    }
+
+    /// Applies `on_existing_root` to the root directory
+    fn prepare_root(&mut self) -> Result<()> {
+        self.root_prepared = true;
+        let mut entries = match fs::read_dir(&self.root) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err.into()),
+        };
+        match self.config.on_existing_root {
+            OnExistingRoot::Reuse => {}
+            OnExistingRoot::Fail => {
+                if entries.next().is_some() {
+                    return Err(Error::RootNotEmpty(self.root.clone()));
+                }
+            }
+            OnExistingRoot::Clear => {
+                // The root itself is kept, so a symlinked root still points at the same place
+                for entry in entries {
+                    let path = entry?.path();
+                    // symlink_metadata does not follow links, so links are removed as links and
+                    // nothing outside of the root is deleted
+                    if fs::symlink_metadata(&path)?.is_dir() {
+                        fs::remove_dir_all(&path)?;
+                    } else {
+                        fs::remove_file(&path)?;
+                    }
+                }
+            }
+        }
+        self.unlink_leaves = self.root.join(OBJECTS_DIR).exists();
+        Ok(())
+    }
+
+    /// Records the layout version at the root of the tree. See [`FormatVersion`]
+    pub(crate) fn write_version_marker(&mut self) -> Result<()> {
+        debug_assert_eq!(self.dir_level, 0);
+        self.push(VERSION_MARKER)?;
+        self.write_data(FormatVersion::CURRENT.as_str())?;
+        self.pop();
+        Ok(())
+    }
+
+    /// Writes the buffered value with repeated subtrees shared, see
+    /// [`Serializer::share_subtrees`]
+    fn write_shared(&mut self) -> Result<()> {
+        debug_assert_eq!(self.dir_level, 0);
+        let writes = self.buffer.take().unwrap_or_default();
+        let (writes, manifest) = share_subtrees(&self.root, writes);
+        for write in writes {
+            self.emit(write)?;
+        }
+        if !manifest.is_empty() {
+            self.push(SHARED_MANIFEST)?;
+            self.write_data(serde_json::to_vec_pretty(&manifest)?)?;
+            self.pop();
+        }
+        Ok(())
+    }
+
+    /// Writes the [`INDEX_FILE`] of the current directory, see [`Serializer::write_indexes`]
+    fn write_index(&mut self, header: &str, names: &[String]) -> Result<()> {
+        let mut index = format!("# {}\n", header);
+        for name in names {
+            index.push_str(name);
+            index.push('\n');
+        }
+        self.push(INDEX_FILE)?;
+        self.write_data(index)?;
+        self.pop();
+        Ok(())
+    }
+
+    /// Starts recording the fields of a struct for its index, if indexes are written
+    fn begin_struct_index(&mut self, header: impl FnOnce() -> String) {
+        if self.config.write_indexes {
+            self.struct_indexes.push((header(), Vec::new()));
+        }
+    }
+
+    /// Records a field of the struct being written, if indexes are written
+    fn index_field(&mut self, key: &str) {
+        if let Some((_, fields)) = self.struct_indexes.last_mut() {
+            fields.push(key.to_owned());
+        }
     }
 
-    /// Writes data to the current file position.
-    ///
-    /// # Panics
-    /// This function panics if it is called representedly without a call to [`pop`] before.
-    /// This is done to prevet data loss, as there may be data already written to the current path
-    /// that we cant overwrite
-    fn write_data(&mut self, s: impl AsRef<[u8]>) -> Result<()> {
-        if self.path_dirty {
-            panic!("BUG: path dirty: {}", self.path.to_string_lossy());
+    /// Writes the index of the struct that just ended, if indexes are written
+    fn end_struct_index(&mut self) -> Result<()> {
+        match self
+            .config
+            .write_indexes
+            .then(|| self.struct_indexes.pop())
+            .flatten()
+        {
+            Some((header, fields)) => self.write_index(&header, &fields),
+            None => Ok(()),
         }
-        assert!(self.dir_level > 0);
-        match fs::create_dir_all(&self.path.parent().unwrap()) {
-            Ok(()) => {}
-            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {}
-            Err(err) => return Err(err.into()),
+    }
+
+    /// Writes the files described in [`Serializer::git_helpers`]
+    fn write_git_helpers(&mut self) -> Result<()> {
+        debug_assert_eq!(self.dir_level, 0);
+        let mut attributes = String::from("# Written by serde_fs: leaves holding raw bytes\n");
+        for leaf in &self.binary_leaves {
+            writeln!(attributes, "{} binary", git_pattern(leaf)).unwrap();
+        }
+        let ignore = "# Written by serde_fs: temporary entries of interrupted writes\n.*.tmp.*\n";
+        for (name, contents) in [(GIT_ATTRIBUTES, attributes.as_str()), (GIT_IGNORE, ignore)] {
+            self.push(name)?;
+            self.write_data(contents)?;
+            self.pop();
         }
-        fs::write(&self.path, s.as_ref())?;
-        self.path_dirty = true;
         Ok(())
     }
 
+    /// Returns how the variant at `index` named `variant` is written, see
+    /// [`Serializer::enum_repr`]
+    fn variant_name(&self, index: u32, variant: &'static str) -> Cow<'static, str> {
+        match self.config.enum_repr {
+            EnumRepr::ByName => Cow::Borrowed(variant),
+            EnumRepr::ByIndex => Cow::Owned(index.to_string()),
+        }
+    }
+
     /// Pushes `path` to the current path pointer so that later calls to [`write_data`] create the
     /// parent directories pushed, with the file name being the last item to be pushed
     fn push(&mut self, path: &str) -> Result<()> {
+        // Checked before anything below this path is written, so a branch that is too deep
+        // fails before any of its directories exist
+        if self.path.as_os_str().len() + 1 + path.len() > self.config.max_path_len {
+            return Err(Error::PathTooLong(self.path.join(path)));
+        }
         self.path.push(path);
         self.dir_level += 1;
+        self.counted_dirs.push(false);
         Ok(())
     }
 
     fn pop(&mut self) {
         self.path.pop();
         self.dir_level -= 1;
+        self.counted_dirs.pop();
         self.path_dirty = false;
     }
 
@@ -80,6 +1268,66 @@ impl Serializer {
     }
 }
 
+/// Returns a `.gitattributes` pattern matching exactly the leaf at `relative`
+fn git_pattern(relative: &Path) -> String {
+    let mut pattern = String::new();
+    for component in relative.iter() {
+        pattern.push('/');
+        for c in component.to_string_lossy().chars() {
+            match c {
+                '*' | '?' | '[' | '\\' | '!' | '#' => {
+                    pattern.push('\\');
+                    pattern.push(c);
+                }
+                c if c.is_whitespace() => pattern.push_str("[[:space:]]"),
+                c => pattern.push(c),
+            }
+        }
+    }
+    pattern
+}
+
+/// Fails unless `relative` only has plain components, so that joining it to a root stays below it
+fn check_relative(relative: &Path) -> Result<()> {
+    if !relative
+        .components()
+        .all(|c| matches!(c, std::path::Component::Normal(_)))
+    {
+        return Err(Error::InvalidRelativePath(relative.to_owned()));
+    }
+    Ok(())
+}
+
+/// Flushes the directory holding `path`, which persists the creation of `path` itself
+fn sync_parent(path: &Path) -> Result<()> {
+    let parent = match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    };
+    // Only unix allows opening directories as files
+    #[cfg(unix)]
+    fs::File::open(parent)?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = parent;
+    Ok(())
+}
+
+/// Removes the file at `path` if there is one
+fn remove_leaf(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+        _ => Ok(()),
+    }
+}
+
+/// 64 bit FNV-1a. Unlike `DefaultHasher` it is stable across Rust versions, so objects written by
+/// one build are found again by the next
+pub(crate) fn content_hash(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, &b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x100000001b3)
+    })
+}
+
 impl<'a> ser::Serializer for &'a mut Serializer {
     type Ok = ();
 
@@ -90,13 +1338,14 @@ impl<'a> ser::Serializer for &'a mut Serializer {
     type SerializeTuple = SequentialSerializer<'a>;
     type SerializeTupleStruct = SequentialSerializer<'a>;
     type SerializeTupleVariant = SequentialSerializer<'a>;
-    type SerializeMap = Self;
+    type SerializeMap = MapSerializer<'a>;
     type SerializeStruct = Self;
     type SerializeStructVariant = Self;
 
     fn serialize_bool(self, v: bool) -> Result<()> {
+        self.fail_if_at_root("bools")?;
         let s = if v { "true" } else { "false" };
-        self.write_data(s)
+        self.write_scalar(LeafType::Bool, s)
     }
 
     //We do not distinguish between integer types
@@ -119,7 +1368,8 @@ impl<'a> ser::Serializer for &'a mut Serializer {
         self.fail_if_at_root("i64's")?;
         let mut bytes = [0u8; 32];
         let len = itoa::write(&mut bytes[..], v)?;
-        self.write_data(&bytes[0..len])?;
+        let number = std::str::from_utf8(&bytes[..len]).unwrap();
+        self.write_number(LeafType::Signed, number)?;
         Ok(())
     }
 
@@ -142,40 +1392,69 @@ impl<'a> ser::Serializer for &'a mut Serializer {
         self.fail_if_at_root("u64's")?;
         let mut bytes = [0u8; 32];
         let len = itoa::write(&mut bytes[..], v)?;
-        self.write_data(&bytes[..len])?;
+        let number = std::str::from_utf8(&bytes[..len]).unwrap();
+        self.write_number(LeafType::Unsigned, number)?;
         Ok(())
     }
 
+    // `Display` never uses exponents or a `+` sign, which is the canonical format the deserializer
+    // expects. The same goes for itoa above
     fn serialize_f32(self, v: f32) -> Result<()> {
         self.fail_if_at_root("f32's")?;
-        self.write_data(v.to_string())
+        if !v.is_finite() {
+            let bits = format!("{:#010x}", v.to_bits());
+            return self.write_non_finite(f64::from(v), bits);
+        }
+        self.write_number(LeafType::Float, &v.to_string())
     }
 
     fn serialize_f64(self, v: f64) -> Result<()> {
         self.fail_if_at_root("f64's")?;
-        self.write_data(v.to_string())
+        if !v.is_finite() {
+            let bits = format!("{:#018x}", v.to_bits());
+            return self.write_non_finite(v, bits);
+        }
+        self.write_number(LeafType::Float, &v.to_string())
     }
 
     fn serialize_char(self, v: char) -> Result<()> {
         self.fail_if_at_root("chars")?;
-        let mut bytes = [0u8; 8];
-        v.encode_utf8(&mut bytes);
-        self.write_data(bytes)
+        let mut bytes = [0u8; 4];
+        let encoded = v.encode_utf8(&mut bytes);
+        self.write_text(LeafType::Char, encoded)
     }
 
     fn serialize_str(self, v: &str) -> Result<()> {
         self.fail_if_at_root("str's")?;
-        self.write_data(v)
+        self.write_text(LeafType::Str, v)
     }
 
     fn serialize_bytes(self, v: &[u8]) -> Result<()> {
         self.fail_if_at_root("bytes")?;
-        self.write_data(v)
+        match self.config.bytes_repr {
+            BytesRepr::Raw => {
+                if self.config.git_helpers {
+                    let leaf = self.path.strip_prefix(&self.root).unwrap().to_owned();
+                    self.binary_leaves.push(leaf);
+                }
+                self.write_scalar(LeafType::Bytes, v)
+            }
+            BytesRepr::Hex => {
+                let mut hex = String::with_capacity(v.len() * 2);
+                for byte in v {
+                    write!(hex, "{:02x}", byte).unwrap();
+                }
+                self.write_scalar(LeafType::Bytes, hex)
+            }
+        }
     }
 
     fn serialize_none(self) -> Result<()> {
-        // Nop. Dont write to any file
-        Ok(())
+        match self.config.option_repr.none_contents().map(<[u8]>::to_vec) {
+            Some(contents) => self.write_data(contents),
+            // Nop. Dont write to any file
+            None => Ok(()),
+        }
     }
 
     fn serialize_some<T>(self, value: &T) -> Result<()>
@@ -186,8 +1465,14 @@ impl<'a> ser::Serializer for &'a mut Serializer {
     }
 
     fn serialize_unit(self) -> Result<()> {
-        // Nop
-        Ok(())
+        match &self.config.unit_repr {
+            UnitRepr::SentinelFile(sentinel) => {
+                let sentinel = sentinel.clone();
+                self.write_data(sentinel)
+            }
+            // Nop
+            UnitRepr::MissingFile => Ok(()),
+        }
     }
 
     // Unit struct means a named value containing no data
@@ -199,33 +1484,39 @@ impl<'a> ser::Serializer for &'a mut Serializer {
     fn serialize_unit_variant(
         self,
         _name: &'static str,
-        _variant_index: u32,
+        variant_index: u32,
         variant: &'static str,
     ) -> Result<()> {
         self.fail_if_at_root("enums")?;
-        self.serialize_str(variant)?;
+        let variant = self.variant_name(variant_index, variant);
+        self.serialize_str(&variant)?;
         Ok(())
     }
 
-    fn serialize_newtype_struct<T>(self, _name: &'static str, value: &T) -> Result<()>
+    fn serialize_newtype_struct<T>(self, name: &'static str, value: &T) -> Result<()>
     where
         T: ?Sized + Serialize,
     {
+        if name == Json::NEWTYPE_NAME {
+            let s = serde_json::to_string(value)?;
+            return self.write_data(s);
+        }
         value.serialize(self)
     }
 
     fn serialize_newtype_variant<T>(
         self,
         _name: &'static str,
-        _variant_index: u32,
+        variant_index: u32,
         variant: &'static str,
         value: &T,
     ) -> Result<()>
     where
         T: ?Sized + Serialize,
     {
-        self.push(variant)?;
-        value.serialize(&mut *self)?;
+        let variant = self.variant_name(variant_index, variant);
+        self.push(&variant)?;
+        self.serialize_child(value, false)?;
         self.pop();
         Ok(())
     }
@@ -266,30 +1557,34 @@ impl<'a> ser::Serializer for &'a mut Serializer {
     fn serialize_tuple_variant(
         self,
         _name: &'static str,
-        _variant_index: u32,
+        variant_index: u32,
         variant: &'static str,
         _len: usize,
     ) -> Result<Self::SerializeTupleVariant> {
-        self.push(variant)?;
+        let variant = self.variant_name(variant_index, variant);
+        self.push(&variant)?;
         Ok(SequentialSerializer::new(self))
     }
 
     fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
-        Ok(self)
+        Ok(MapSerializer::new(self))
     }
 
-    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct> {
+    fn serialize_struct(self, name: &'static str, _len: usize) -> Result<Self::SerializeStruct> {
+        self.begin_struct_index(|| format!("struct {}", name));
         Ok(self)
     }
 
     fn serialize_struct_variant(
         self,
-        _name: &'static str,
-        _variant_index: u32,
+        name: &'static str,
+        variant_index: u32,
         variant: &'static str,
         _len: usize,
     ) -> Result<Self::SerializeStructVariant> {
-        self.push(variant)?;
+        self.begin_struct_index(|| format!("struct {}::{}", name, variant));
+        let variant = self.variant_name(variant_index, variant);
+        self.push(&variant)?;
         Ok(self)
     }
 }
@@ -301,24 +1596,32 @@ pub struct SequentialSerializer<'a> {
 
 impl<'a> SequentialSerializer<'a> {
     fn new(ser: &'a mut Serializer) -> Self {
-        Self { index: 0, ser }
+        Self {
+            index: ser.config.seq_base,
+            ser,
+        }
     }
 
-    fn serialize<T: ?Sized>(&mut self, value: &T) -> Result<()>
+    fn serialize<T>(&mut self, value: &T) -> Result<()>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
         let mut bytes = [0u8; 32];
         let len = itoa::write(&mut bytes[..], self.index)?;
         let num = std::str::from_utf8(&bytes[..len]).unwrap();
 
         self.ser.push(num)?;
-        value.serialize(&mut *self.ser)?;
+        self.ser.serialize_child(value, false)?;
         self.ser.pop();
         self.index += 1;
 
         Ok(())
     }
+
+    /// Called once all elements are serialized
+    fn finish(&mut self) -> Result<()> {
+        self.ser.ensure_dir()
+    }
 }
 
 impl<'a> SerializeSeq for SequentialSerializer<'a> {
@@ -326,15 +1629,15 @@ impl<'a> SerializeSeq for SequentialSerializer<'a> {
 
     type Error = SerError;
 
-    fn serialize_element<T: ?Sized>(&mut self, value: &T) -> Result<()>
+    fn serialize_element<T>(&mut self, value: &T) -> Result<()>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
         self.serialize(value)
     }
 
-    fn end(self) -> Result<()> {
-        Ok(())
+    fn end(mut self) -> Result<()> {
+        self.finish()
     }
 }
 
@@ -343,15 +1646,15 @@ impl<'a> SerializeTuple for SequentialSerializer<'a> {
 
     type Error = SerError;
 
-    fn serialize_element<T: ?Sized>(&mut self, value: &T) -> Result<()>
+    fn serialize_element<T>(&mut self, value: &T) -> Result<()>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
         self.serialize(value)
     }
 
-    fn end(self) -> Result<()> {
-        Ok(())
+    fn end(mut self) -> Result<()> {
+        self.finish()
     }
 }
 
@@ -360,15 +1663,15 @@ impl<'a> SerializeTupleStruct for SequentialSerializer<'a> {
 
     type Error = SerError;
 
-    fn serialize_field<T: ?Sized>(&mut self, value: &T) -> Result<()>
+    fn serialize_field<T>(&mut self, value: &T) -> Result<()>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
         self.serialize(value)
     }
 
-    fn end(self) -> Result<()> {
-        Ok(())
+    fn end(mut self) -> Result<()> {
+        self.finish()
     }
 }
 
@@ -383,12 +1686,63 @@ impl<'a> ser::SerializeTupleVariant for SequentialSerializer<'a> {
         self.serialize(value)
     }
 
-    fn end(self) -> Result<()> {
+    fn end(mut self) -> Result<()> {
+        self.finish()?;
+        // pop the variant name pushed by `serialize_tuple_variant`
+        self.ser.pop();
         Ok(())
     }
 }
 
-impl<'a> ser::SerializeMap for &'a mut Serializer {
+pub struct MapSerializer<'a> {
+    ser: &'a mut Serializer,
+    /// The stringified key of the entry currently being serialized
+    key: Option<String>,
+    /// How many entries have been serialized
+    len: usize,
+    /// Entries waiting to be written in sorted order, with the range of `binary_leaves` each
+    /// recorded. `None` unless `sort_maps` is enabled
+    entries: Option<Vec<(String, Writes, Range<usize>)>>,
+    /// Entry names for the index, when `write_indexes` is set
+    names: Vec<String>,
+    /// Entry names by their lowercase form, when case collisions are checked
+    folded_names: HashMap<String, String>,
+}
+
+impl<'a> MapSerializer<'a> {
+    fn new(ser: &'a mut Serializer) -> Self {
+        let entries = if ser.config.sort_maps {
+            Some(Vec::new())
+        } else {
+            None
+        };
+        Self {
+            ser,
+            key: None,
+            len: 0,
+            entries,
+            names: Vec::new(),
+            folded_names: HashMap::new(),
+        }
+    }
+
+    /// Fails if `name` differs only in case from an earlier entry, which would share its file on
+    /// case insensitive file systems
+    fn check_case_collision(&mut self, name: &str) -> Result<()> {
+        match self.folded_names.entry(name.to_lowercase()) {
+            Entry::Occupied(earlier) => Err(Error::CaseCollision {
+                a: earlier.get().clone(),
+                b: name.to_owned(),
+            }),
+            Entry::Vacant(entry) => {
+                entry.insert(name.to_owned());
+                Ok(())
+            }
+        }
+    }
+}
+
+impl<'a> ser::SerializeMap for MapSerializer<'a> {
     type Ok = ();
     type Error = SerError;
 
@@ -397,30 +1751,119 @@ impl<'a> ser::SerializeMap for &'a mut Serializer {
         T: ?Sized + Serialize,
     {
         //convert key to string so we can stick in path
-        let mut str_serializer = StringSerializer::new();
-        key.serialize(&mut str_serializer)?;
-        let name = str_serializer.finish();
-        self.push(name.as_str())
+        let mut str_serializer = StringSerializer::new(self.ser.config.int_key_width);
+        let mut name = match key.serialize(&mut str_serializer) {
+            Err(Error::UnsupportedKey(_)) if self.ser.config.json_keys => {
+                encode_json_key(&serde_json::to_string(key)?)
+            }
+            res => {
+                res?;
+                str_serializer.finish()?
+            }
+        };
+        if self.ser.config.detect_case_collisions || self.ser.config.lowercase_keys {
+            self.check_case_collision(&name)?;
+        }
+        if self.ser.config.lowercase_keys {
+            name = name.to_lowercase();
+        }
+        // Sorted entries are only numbered once they are sorted, in `end`
+        if self.ser.config.ordered_maps && self.entries.is_none() {
+            name = format!("{}.{}", self.len, name);
+        }
+        if self.ser.config.write_indexes {
+            if name == INDEX_FILE {
+                return Err(Error::InvalidKey(name));
+            }
+            self.names.push(name.clone());
+        }
+        self.ser.push(name.as_str())?;
+        self.key = Some(name);
+        Ok(())
+    }
+
+    fn serialize_entry<K, V>(&mut self, key: &K, value: &V) -> Result<()>
+    where
+        K: ?Sized + Serialize,
+        V: ?Sized + Serialize,
+    {
+        if self.ser.config.skip_none_entries && NoneProbe::is_none(value) {
+            return Ok(());
+        }
+        self.serialize_key(key)?;
+        self.serialize_value(value)
     }
 
     fn serialize_value<T>(&mut self, value: &T) -> Result<()>
     where
         T: ?Sized + Serialize,
     {
-        value.serialize(&mut **self)?;
-        self.pop();
+        let key = self
+            .key
+            .take()
+            .expect("serialize_value called before serialize_key");
+        match &mut self.entries {
+            Some(entries) => {
+                // Collect the writes for this entry so they can be replayed in order in `end`
+                let outer = self.ser.buffer.replace(Vec::new());
+                let binary_start = self.ser.binary_leaves.len();
+                let res = self.ser.serialize_child(value, false);
+                let writes = std::mem::replace(&mut self.ser.buffer, outer).unwrap();
+                res?;
+                entries.push((key, writes, binary_start..self.ser.binary_leaves.len()));
+            }
+            None => self.ser.serialize_child(value, false)?,
+        }
+        self.ser.pop();
+        self.len += 1;
 
         Ok(())
     }
 
-    fn end(self) -> Result<()> {
+    fn end(mut self) -> Result<()> {
+        if let Some(mut entries) = self.entries.take() {
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+            if self.ser.config.ordered_maps && self.ser.config.write_indexes {
+                self.names.clear();
+            }
+            for (index, (key, writes, binary)) in entries.into_iter().enumerate() {
+                let mut renamed = None;
+                if self.ser.config.ordered_maps {
+                    let name = format!("{}.{}", index, key);
+                    let (from, to) = (self.ser.path.join(&key), self.ser.path.join(&name));
+                    // binary leaves are recorded relative to the root
+                    let root = &self.ser.root;
+                    let (rel_from, rel_to) = (from.strip_prefix(root), to.strip_prefix(root));
+                    if let (Ok(rel_from), Ok(rel_to)) = (rel_from, rel_to) {
+                        for leaf in &mut self.ser.binary_leaves[binary] {
+                            *leaf = rebase(std::mem::take(leaf), rel_from, rel_to);
+                        }
+                    }
+                    renamed = Some((from, to));
+                    if self.ser.config.write_indexes {
+                        self.names.push(name);
+                    }
+                }
+                for write in writes {
+                    let write = match &renamed {
+                        Some((from, to)) => write.rebased(from, to),
+                        None => write,
+                    };
+                    self.ser.emit(write)?;
+                }
+            }
+        }
+        self.ser.ensure_dir()?;
+        if self.ser.config.write_indexes {
+            self.ser.write_index("map", &self.names)?;
+        }
         Ok(())
     }
 }
 
 // Structs are like maps in which the keys are constrained to be compile-time
 // constant strings.
-impl<'a> ser::SerializeStruct for &'a mut Serializer {
+impl ser::SerializeStruct for &mut Serializer {
     type Ok = ();
     type Error = SerError;
 
@@ -428,26 +1871,23 @@ impl<'a> ser::SerializeStruct for &'a mut Serializer {
     where
         T: ?Sized + Serialize,
     {
+        self.index_field(key);
         self.push(key)?;
-        if key.starts_with("json") {
-            let s = serde_json::to_string(value)?;
-            s.serialize(&mut **self)?;
-        } else {
-            value.serialize(&mut **self)?;
-        }
+        self.serialize_child(value, key.starts_with("json"))?;
         self.pop();
 
         Ok(())
     }
 
     fn end(self) -> Result<()> {
-        Ok(())
+        self.ensure_dir()?;
+        self.end_struct_index()
     }
 }
 
 // Similar to `SerializeTupleVariant`, here the `end` method is responsible for
 // closing both of the curly braces opened by `serialize_struct_variant`.
-impl<'a> ser::SerializeStructVariant for &'a mut Serializer {
+impl ser::SerializeStructVariant for &mut Serializer {
     type Ok = ();
     type Error = SerError;
 
@@ -455,37 +1895,56 @@ impl<'a> ser::SerializeStructVariant for &'a mut Serializer {
     where
         T: ?Sized + Serialize,
     {
+        self.index_field(key);
         self.push(key)?;
-        if key.starts_with("json") {
-            let s = serde_json::to_string(value)?;
-            s.serialize(&mut **self)?;
-        } else {
-            value.serialize(&mut **self)?;
-        }
+        self.serialize_child(value, key.starts_with("json"))?;
         self.pop();
 
         Ok(())
     }
 
     fn end(self) -> Result<()> {
+        self.ensure_dir()?;
+        self.end_struct_index()?;
         self.pop();
 
         Ok(())
     }
 }
 
+/// Percent encodes the characters of a JSON map key that cannot be part of a file name, see
+/// [`Serializer::json_keys`]
+fn encode_json_key(json: &str) -> String {
+    let mut name = String::with_capacity(json.len());
+    for c in json.chars() {
+        match c {
+            '%' => name.push_str("%25"),
+            '/' => name.push_str("%2F"),
+            '\\' => name.push_str("%5C"),
+            '\0' => name.push_str("%00"),
+            c => name.push(c),
+        }
+    }
+    name
+}
+
 struct StringSerializer {
     s: String,
+    /// See [`Serializer::pad_int_keys`]
+    int_width: Option<usize>,
 }
 
-#[track_caller]
-fn unsupported() -> ! {
-    panic!("Unsupported")
+/// Map keys become file names, so only values that format as a single string can be keys
+fn unsupported<T>(kind: &'static str) -> Result<T> {
+    Err(Error::UnsupportedKey(kind))
 }
 
 impl StringSerializer {
-    fn new() -> Self {
-        Self { s: String::new() }
+    fn new(int_width: Option<usize>) -> Self {
+        Self {
+            s: String::new(),
+            int_width,
+        }
     }
 
     fn set_str(&mut self, new_string: impl ToString) -> Result<()> {
@@ -494,13 +1953,32 @@ impl StringSerializer {
         Ok(())
     }
 
-    fn finish(self) -> String {
-        self.s
+    /// Sets an integer key, zero padded if [`Serializer::pad_int_keys`] is set
+    fn set_int(&mut self, v: impl ToString) -> Result<()> {
+        let Some(width) = self.int_width else {
+            return self.set_str(v);
+        };
+        let s = v.to_string();
+        match s.strip_prefix('-') {
+            Some(digits) => self.set_str(format!("-{:0>1$}", digits, width)),
+            None => self.set_str(format!("{:0>1$}", s, width)),
+        }
+    }
+
+    /// Returns the key, making sure it can be used as a single path component
+    fn finish(self) -> Result<String> {
+        let invalid =
+            matches!(self.s.as_str(), "" | "." | "..") || self.s.contains(['/', '\\', '\0']);
+        if invalid {
+            Err(Error::InvalidKey(self.s))
+        } else {
+            Ok(self.s)
+        }
     }
 }
 
 use serde::ser::{Impossible, SerializeSeq, SerializeTuple, SerializeTupleStruct};
-impl<'a> ser::Serializer for &'a mut StringSerializer {
+impl ser::Serializer for &mut StringSerializer {
     type Ok = ();
     type Error = SerError;
     type SerializeSeq = Impossible<(), SerError>;
@@ -520,35 +1998,35 @@ impl<'a> ser::Serializer for &'a mut StringSerializer {
     }
 
     fn serialize_i8(self, v: i8) -> Result<()> {
-        self.set_str(v)
+        self.set_int(v)
     }
 
     fn serialize_i16(self, v: i16) -> Result<()> {
-        self.set_str(v)
+        self.set_int(v)
     }
 
     fn serialize_i32(self, v: i32) -> Result<()> {
-        self.set_str(v)
+        self.set_int(v)
     }
 
     fn serialize_i64(self, v: i64) -> Result<()> {
-        self.set_str(v)
+        self.set_int(v)
     }
 
     fn serialize_u8(self, v: u8) -> Result<()> {
-        self.set_str(v)
+        self.set_int(v)
     }
 
     fn serialize_u16(self, v: u16) -> Result<()> {
-        self.set_str(v)
+        self.set_int(v)
     }
 
     fn serialize_u32(self, v: u32) -> Result<()> {
-        self.set_str(v)
+        self.set_int(v)
     }
 
     fn serialize_u64(self, v: u64) -> Result<()> {
-        self.set_str(v)
+        self.set_int(v)
     }
 
     fn serialize_f32(self, v: f32) -> Result<()> {
@@ -568,63 +2046,238 @@ impl<'a> ser::Serializer for &'a mut StringSerializer {
     }
 
     fn serialize_bytes(self, _v: &[u8]) -> Result<()> {
-        unsupported()
+        unsupported("bytes")
     }
 
     fn serialize_none(self) -> Result<()> {
-        unsupported()
+        unsupported("options")
+    }
+
+    fn serialize_some<T>(self, _value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        unsupported("options")
+    }
+
+    fn serialize_unit(self) -> Result<()> {
+        unsupported("units")
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<()> {
+        unsupported("unit structs")
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<()> {
+        self.set_str(String::from(variant))
+    }
+
+    fn serialize_newtype_struct<T>(self, _name: &'static str, _value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        unsupported("newtype structs")
+    }
+
+    fn serialize_newtype_variant<T>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        unsupported("newtype variants")
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
+        unsupported("sequences")
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple> {
+        unsupported("tuples")
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct> {
+        unsupported("tuple structs")
     }
 
-    fn serialize_some<T: ?Sized>(self, _value: &T) -> Result<()>
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant> {
+        unsupported("tuple variants")
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
+        unsupported("maps")
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct> {
+        unsupported("structs")
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant> {
+        unsupported("struct variants")
+    }
+}
+
+/// Tells whether a value serializes to `None`, for [`Serializer::skip_none_entries`]. Values
+/// holding other values are never `None`, and stop the probe with an error that is discarded
+struct NoneProbe;
+
+impl NoneProbe {
+    fn is_none<T: ?Sized + Serialize>(value: &T) -> bool {
+        value.serialize(NoneProbe).unwrap_or(false)
+    }
+}
+
+/// Ends a [`NoneProbe`] on a compound value
+fn not_none<T>() -> Result<T> {
+    Err(Error::Serde(String::new()))
+}
+
+impl ser::Serializer for NoneProbe {
+    type Ok = bool;
+    type Error = SerError;
+    type SerializeSeq = Impossible<bool, SerError>;
+    type SerializeTuple = Impossible<bool, SerError>;
+    type SerializeTupleStruct = Impossible<bool, SerError>;
+    type SerializeTupleVariant = Impossible<bool, SerError>;
+    type SerializeMap = Impossible<bool, SerError>;
+    type SerializeStruct = Impossible<bool, SerError>;
+    type SerializeStructVariant = Impossible<bool, SerError>;
+
+    fn serialize_bool(self, _v: bool) -> Result<bool> {
+        Ok(false)
+    }
+
+    fn serialize_i8(self, _v: i8) -> Result<bool> {
+        Ok(false)
+    }
+
+    fn serialize_i16(self, _v: i16) -> Result<bool> {
+        Ok(false)
+    }
+
+    fn serialize_i32(self, _v: i32) -> Result<bool> {
+        Ok(false)
+    }
+
+    fn serialize_i64(self, _v: i64) -> Result<bool> {
+        Ok(false)
+    }
+
+    fn serialize_u8(self, _v: u8) -> Result<bool> {
+        Ok(false)
+    }
+
+    fn serialize_u16(self, _v: u16) -> Result<bool> {
+        Ok(false)
+    }
+
+    fn serialize_u32(self, _v: u32) -> Result<bool> {
+        Ok(false)
+    }
+
+    fn serialize_u64(self, _v: u64) -> Result<bool> {
+        Ok(false)
+    }
+
+    fn serialize_f32(self, _v: f32) -> Result<bool> {
+        Ok(false)
+    }
+
+    fn serialize_f64(self, _v: f64) -> Result<bool> {
+        Ok(false)
+    }
+
+    fn serialize_char(self, _v: char) -> Result<bool> {
+        Ok(false)
+    }
+
+    fn serialize_str(self, _v: &str) -> Result<bool> {
+        Ok(false)
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<bool> {
+        Ok(false)
+    }
+
+    fn serialize_none(self) -> Result<bool> {
+        Ok(true)
+    }
+
+    fn serialize_some<T>(self, _value: &T) -> Result<bool>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
-        unsupported()
+        Ok(false)
     }
 
-    fn serialize_unit(self) -> Result<()> {
-        unsupported()
+    fn serialize_unit(self) -> Result<bool> {
+        Ok(false)
     }
 
-    fn serialize_unit_struct(self, _name: &'static str) -> Result<()> {
-        unsupported()
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<bool> {
+        Ok(false)
     }
 
     fn serialize_unit_variant(
         self,
         _name: &'static str,
         _variant_index: u32,
-        variant: &'static str,
-    ) -> Result<()> {
-        self.set_str(String::from(variant))
+        _variant: &'static str,
+    ) -> Result<bool> {
+        Ok(false)
     }
 
-    fn serialize_newtype_struct<T: ?Sized>(self, _name: &'static str, _value: &T) -> Result<()>
+    fn serialize_newtype_struct<T>(self, _name: &'static str, value: &T) -> Result<bool>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
-        unsupported()
+        value.serialize(self)
     }
 
-    fn serialize_newtype_variant<T: ?Sized>(
+    fn serialize_newtype_variant<T>(
         self,
         _name: &'static str,
         _variant_index: u32,
         _variant: &'static str,
         _value: &T,
-    ) -> Result<()>
+    ) -> Result<bool>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
-        unsupported()
+        Ok(false)
     }
 
     fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
-        unsupported()
+        not_none()
     }
 
     fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple> {
-        unsupported()
+        not_none()
     }
 
     fn serialize_tuple_struct(
@@ -632,7 +2285,7 @@ impl<'a> ser::Serializer for &'a mut StringSerializer {
         _name: &'static str,
         _len: usize,
     ) -> Result<Self::SerializeTupleStruct> {
-        unsupported()
+        not_none()
     }
 
     fn serialize_tuple_variant(
@@ -642,15 +2295,15 @@ impl<'a> ser::Serializer for &'a mut StringSerializer {
         _variant: &'static str,
         _len: usize,
     ) -> Result<Self::SerializeTupleVariant> {
-        unsupported()
+        not_none()
     }
 
     fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
-        unsupported()
+        not_none()
     }
 
     fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct> {
-        unsupported()
+        not_none()
     }
 
     fn serialize_struct_variant(
@@ -660,7 +2313,7 @@ impl<'a> ser::Serializer for &'a mut StringSerializer {
         _variant: &'static str,
         _len: usize,
     ) -> Result<Self::SerializeStructVariant> {
-        unsupported()
+        not_none()
     }
 }
 
@@ -690,6 +2343,24 @@ mod tests {
         std::fs::remove_dir_all(test_dir).unwrap();
     }
 
+    /// Reads every file under `dir` into a map of relative path to contents
+    fn read_tree(dir: &str) -> BTreeMap<PathBuf, Vec<u8>> {
+        fn walk(root: &Path, dir: &Path, out: &mut BTreeMap<PathBuf, Vec<u8>>) {
+            for entry in std::fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    walk(root, &path, out);
+                } else {
+                    let bytes = std::fs::read(&path).unwrap();
+                    out.insert(path.strip_prefix(root).unwrap().to_owned(), bytes);
+                }
+            }
+        }
+        let mut out = BTreeMap::new();
+        walk(Path::new(dir), Path::new(dir), &mut out);
+        out
+    }
+
     #[test]
     #[allow(dead_code)]
     fn test_struct() {
@@ -780,10 +2451,7 @@ mod tests {
             json_comp: "abc".into(),
         };
         to_fs(&u, test_dir).unwrap();
-        check_and_reset(
-            test_dir,
-            vec![("json", "0"), ("json_comp", "\"abc\"".into())],
-        );
+        check_and_reset(test_dir, vec![("json", "0"), ("json_comp", "\"abc\"")]);
 
         #[derive(Serialize)]
         struct Struct {
@@ -798,4 +2466,695 @@ mod tests {
         to_fs(&u, test_dir).unwrap();
         check_and_reset(test_dir, vec![("json", r#"{"k1":"v1","k2":"v2"}"#)]);
     }
+
+    #[test]
+    fn test_sorted_maps() {
+        use std::collections::HashMap;
+
+        #[derive(Serialize)]
+        struct Test<M> {
+            map: M,
+        }
+
+        let test_dir = "./.test-ser-sorted-maps";
+        let _ = std::fs::remove_dir_all(test_dir);
+
+        let hash_map: HashMap<u32, String> = (0..64).map(|i| (i, format!("v{}", i))).collect();
+        let mut ser = Serializer::new(test_dir).unwrap().sort_maps(true);
+        ser.track();
+        Test { map: &hash_map }.serialize(&mut ser).unwrap();
+        let (_, tracker) = ser.finish_tracked().unwrap();
+
+        // entries are written in the order of their file names, not the order of the map
+        let mut expected: Vec<String> = (0..64).map(|i| i.to_string()).collect();
+        expected.sort();
+        let expected: Vec<PathBuf> = expected
+            .iter()
+            .map(|name| Path::new(test_dir).join("map").join(name))
+            .collect();
+        let added = tracker.unwrap().added;
+        assert_eq!(&added[..64], &expected[..]);
+        assert_eq!(added[64], Path::new(test_dir).join(VERSION_MARKER));
+        std::fs::remove_dir_all(test_dir).unwrap();
+
+        // the same map serialized twice gives byte identical trees, even though each `HashMap`
+        // is seeded differently and yields its entries in a different order
+        let dirs = ["./.test-ser-sorted-maps-a", "./.test-ser-sorted-maps-b"];
+        for dir in dirs {
+            let _ = std::fs::remove_dir_all(dir);
+            let hash_map: HashMap<u32, String> = (0..64).map(|i| (i, format!("v{}", i))).collect();
+            let mut ser = Serializer::new(dir).unwrap().sort_maps(true);
+            Test { map: &hash_map }.serialize(&mut ser).unwrap();
+            ser.finish().unwrap();
+        }
+        assert_eq!(crate::diff_fs(dirs[0], dirs[1]).unwrap(), vec![]);
+        assert_eq!(read_tree(dirs[0]).len(), 65);
+        for dir in dirs {
+            std::fs::remove_dir_all(dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_root_is_file() {
+        #[derive(Serialize)]
+        struct Test {
+            int: u32,
+        }
+
+        let test_file = "./.test-ser-root-is-file";
+        std::fs::write(test_file, "data").unwrap();
+
+        let err = to_fs(&Test { int: 1 }, test_file).unwrap_err();
+        assert!(matches!(err, SerError::RootIsFile(ref p) if p == Path::new(test_file)));
+        assert_eq!(std::fs::read_to_string(test_file).unwrap(), "data");
+
+        std::fs::remove_file(test_file).unwrap();
+    }
+
+    #[test]
+    fn test_on_existing_root() {
+        #[derive(Serialize)]
+        struct Test {
+            int: u32,
+        }
+
+        let test_dir = "./.test-ser-on-existing-root";
+        let populate = || {
+            let _ = std::fs::remove_dir_all(test_dir);
+            std::fs::create_dir_all(format!("{}/old_dir", test_dir)).unwrap();
+            std::fs::write(format!("{}/old_dir/leaf", test_dir), "1").unwrap();
+            std::fs::write(format!("{}/old", test_dir), "2").unwrap();
+        };
+        let serialize = |policy| {
+            let mut ser = Serializer::new(test_dir).unwrap().on_existing_root(policy);
+            Test { int: 5 }.serialize(&mut ser)
+        };
+
+        populate();
+        serialize(OnExistingRoot::Reuse).unwrap();
+        assert_eq!(
+            read_tree(test_dir),
+            [("int", "5"), ("old", "2"), ("old_dir/leaf", "1")]
+                .into_iter()
+                .map(|(k, v)| (PathBuf::from(k), v.as_bytes().to_vec()))
+                .collect()
+        );
+
+        populate();
+        serialize(OnExistingRoot::Clear).unwrap();
+        assert_eq!(
+            read_tree(test_dir),
+            [(PathBuf::from("int"), b"5".to_vec())].into()
+        );
+
+        populate();
+        let err = serialize(OnExistingRoot::Fail).unwrap_err();
+        assert!(matches!(err, SerError::RootNotEmpty(ref p) if p == Path::new(test_dir)));
+        assert!(!Path::new(test_dir).join("int").exists());
+
+        // An empty or missing root is fine
+        std::fs::remove_dir_all(test_dir).unwrap();
+        serialize(OnExistingRoot::Fail).unwrap();
+        std::fs::remove_dir_all(test_dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_clear_root_keeps_symlink_targets() {
+        #[derive(Serialize)]
+        struct Test {
+            int: u32,
+        }
+
+        let test_dir = "./.test-ser-clear-symlink";
+        let outside = "./.test-ser-clear-symlink-outside";
+        let _ = std::fs::remove_dir_all(test_dir);
+        let _ = std::fs::remove_dir_all(outside);
+        std::fs::create_dir_all(test_dir).unwrap();
+        std::fs::create_dir_all(outside).unwrap();
+        std::fs::write(format!("{}/keep", outside), "data").unwrap();
+        std::os::unix::fs::symlink(
+            std::fs::canonicalize(outside).unwrap(),
+            format!("{}/link", test_dir),
+        )
+        .unwrap();
+
+        let mut ser = Serializer::new(test_dir)
+            .unwrap()
+            .on_existing_root(OnExistingRoot::Clear);
+        Test { int: 5 }.serialize(&mut ser).unwrap();
+        assert!(std::fs::symlink_metadata(format!("{}/link", test_dir)).is_err());
+        assert_eq!(
+            std::fs::read_to_string(format!("{}/keep", outside)).unwrap(),
+            "data"
+        );
+
+        std::fs::remove_dir_all(test_dir).unwrap();
+        std::fs::remove_dir_all(outside).unwrap();
+    }
+
+    #[test]
+    fn test_invalid_char_key() {
+        #[derive(Serialize)]
+        struct Test {
+            map: BTreeMap<char, u32>,
+        }
+
+        let test_dir = "./.test-ser-invalid-char-key";
+        let _ = std::fs::remove_dir_all(test_dir);
+
+        let test = Test {
+            map: [('a', 1), ('é', 2)].into(),
+        };
+        to_fs(&test, test_dir).unwrap();
+        check_and_reset(test_dir, vec![("map/a", "1"), ("map/é", "2")]);
+
+        for c in ['/', '\\', '\0', '.'] {
+            let test = Test {
+                map: [(c, 1)].into(),
+            };
+            let res = to_fs(&test, test_dir);
+            assert!(matches!(res, Err(SerError::InvalidKey(ref k)) if k == &c.to_string()));
+        }
+        let _ = std::fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_to_fs_at() {
+        #[derive(Serialize)]
+        struct Branch {
+            value: u32,
+        }
+
+        #[derive(Serialize)]
+        struct Tree {
+            left: BTreeMap<&'static str, u32>,
+            right: Branch,
+        }
+
+        let test_dir = "./.test-ser-to-fs-at";
+        let _ = std::fs::remove_dir_all(test_dir);
+        to_fs(
+            &Tree {
+                left: [("a", 1), ("b", 2)].into(),
+                right: Branch { value: 3 },
+            },
+            test_dir,
+        )
+        .unwrap();
+        let before = read_tree(test_dir);
+
+        to_fs_at(&BTreeMap::from([("c", 4)]), test_dir, "left").unwrap();
+        let after = read_tree(test_dir);
+        assert_eq!(after[Path::new("left/c")], b"4");
+        assert!(!after.contains_key(Path::new("left/a")));
+        assert!(!after.contains_key(Path::new("left/b")));
+        // siblings are untouched
+        for (path, data) in &before {
+            if !path.starts_with("left") {
+                assert_eq!(&after[path], data, "{}", path.display());
+            }
+        }
+
+        // a single leaf, replacing a directory and then a file
+        to_fs_at(&5u32, test_dir, "left").unwrap();
+        to_fs_at(&6u32, test_dir, "right/value").unwrap();
+        let after = read_tree(test_dir);
+        assert_eq!(after[Path::new("left")], b"5");
+        assert_eq!(after[Path::new("right/value")], b"6");
+
+        let err = to_fs_at(&Branch { value: 5 }, test_dir, "../escape").unwrap_err();
+        assert!(matches!(err, SerError::InvalidRelativePath(_)));
+        // the whole tree is never replaced
+        let err = to_fs_at(&Branch { value: 5 }, test_dir, "").unwrap_err();
+        assert!(matches!(err, SerError::InvalidRelativePath(_)));
+        assert_eq!(read_tree(test_dir), after);
+
+        std::fs::remove_dir_all(test_dir).unwrap();
+    }
+
+    #[test]
+    fn test_dedup() {
+        #[derive(Serialize, serde::Deserialize, PartialEq, Debug)]
+        struct Defaults {
+            a: String,
+            b: String,
+            c: String,
+            d: Vec<String>,
+        }
+
+        let test_dir = "./.test-ser-dedup";
+        let _ = std::fs::remove_dir_all(test_dir);
+        let value = Defaults {
+            a: "default".into(),
+            b: "default".into(),
+            c: "default".into(),
+            d: vec!["default".into(); 10],
+        };
+        let mut ser = Serializer::new(test_dir).unwrap().dedup(true);
+        value.serialize(&mut ser).unwrap();
+
+        let objects: Vec<_> = std::fs::read_dir(Path::new(test_dir).join(OBJECTS_DIR))
+            .unwrap()
+            .collect();
+        assert_eq!(objects.len(), 1);
+        assert_eq!(crate::from_fs::<Defaults>(test_dir).unwrap(), value);
+
+        // Writing over a linked leaf without dedup must not change the shared object
+        to_fs(
+            &Defaults {
+                a: "changed".into(),
+                ..value
+            },
+            test_dir,
+        )
+        .unwrap();
+        let read: Defaults = crate::from_fs(test_dir).unwrap();
+        assert_eq!(read.a, "changed");
+        assert_eq!(read.b, "default");
+
+        std::fs::remove_dir_all(test_dir).unwrap();
+    }
+
+    #[test]
+    fn test_char_leaves() {
+        #[derive(Serialize)]
+        struct Chars {
+            ascii: char,
+            accented: char,
+            emoji: char,
+        }
+
+        let test_dir = "./.test-ser-char-leaves";
+        let _ = fs::remove_dir_all(test_dir);
+        let chars = Chars {
+            ascii: 'a',
+            accented: 'é',
+            emoji: '🦀',
+        };
+        to_fs(&chars, test_dir).unwrap();
+        for (name, c) in [("ascii", 'a'), ("accented", 'é'), ("emoji", '🦀')] {
+            let bytes = fs::read(format!("{}/{}", test_dir, name)).unwrap();
+            assert_eq!(bytes, c.to_string().as_bytes(), "{}", name);
+        }
+        fs::remove_dir_all(test_dir).unwrap();
+    }
+
+    #[test]
+    fn test_write_leaf() {
+        #[derive(Serialize)]
+        struct Test {
+            int: u32,
+        }
+
+        let test_dir = "./.test-ser-write-leaf";
+        let _ = std::fs::remove_dir_all(test_dir);
+        let mut ser = Serializer::new(test_dir)
+            .unwrap()
+            .on_existing_root(OnExistingRoot::Fail);
+        Test { int: 1 }.serialize(&mut ser).unwrap();
+        ser.write_leaf("README", "generated").unwrap();
+        ser.write_leaf("blobs/precomputed", [0u8, 1, 2]).unwrap();
+
+        assert_eq!(
+            read_tree(test_dir),
+            [
+                (PathBuf::from("README"), b"generated".to_vec()),
+                (PathBuf::from("blobs/precomputed"), vec![0, 1, 2]),
+                (PathBuf::from("int"), b"1".to_vec()),
+            ]
+            .into()
+        );
+        assert_eq!(ser.stats().files, 3);
+
+        for invalid in ["", "../escape", "/abs", "a/../b"] {
+            let err = ser.write_leaf(invalid, "data").unwrap_err();
+            assert!(
+                matches!(err, SerError::InvalidRelativePath(_)),
+                "{}",
+                invalid
+            );
+        }
+
+        std::fs::remove_dir_all(test_dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unix_modes() {
+        use std::os::unix::fs::PermissionsExt;
+
+        #[derive(Serialize)]
+        struct Secret {
+            token: &'static str,
+            nested: BTreeMap<&'static str, u32>,
+        }
+
+        let test_dir = "./.test-ser-unix-modes";
+        let _ = std::fs::remove_dir_all(test_dir);
+        let mut ser = Serializer::new(test_dir)
+            .unwrap()
+            .file_mode(0o600)
+            .dir_mode(0o700);
+        Secret {
+            token: "hunter2",
+            nested: [("a", 1)].into(),
+        }
+        .serialize(&mut ser)
+        .unwrap();
+
+        let mode = |path: &str| {
+            std::fs::metadata(format!("{}/{}", test_dir, path))
+                .unwrap()
+                .permissions()
+                .mode()
+                & 0o777
+        };
+        assert_eq!(mode("token"), 0o600);
+        assert_eq!(mode("nested/a"), 0o600);
+        assert_eq!(mode("nested"), 0o700);
+        assert_eq!(mode(""), 0o700);
+
+        std::fs::remove_dir_all(test_dir).unwrap();
+    }
+
+    #[test]
+    fn test_sibling_leaves_create_dir_once() {
+        #[derive(Serialize)]
+        struct Wide {
+            fields: BTreeMap<String, u32>,
+            nested: BTreeMap<String, u32>,
+        }
+
+        let test_dir = "./.test-ser-create-dir-once";
+        let _ = fs::remove_dir_all(test_dir);
+        let wide = Wide {
+            fields: (0..50).map(|i| (format!("field{}", i), i)).collect(),
+            nested: [("a".into(), 1), ("b".into(), 2)].into(),
+        };
+        let mut serializer = Serializer::new(test_dir).unwrap();
+        serializer.track();
+        wide.serialize(&mut serializer).unwrap();
+        let (_, tracker) = serializer.finish_tracked().unwrap();
+        // once for `fields` and once for `nested`, not once per leaf. Then the root, for the
+        // version marker
+        let root = Path::new(test_dir);
+        assert_eq!(
+            tracker.unwrap().created_dirs,
+            [root.join("fields"), root.join("nested"), root.to_owned()]
+        );
+        check_and_reset(test_dir, vec![("fields/field49", "49"), ("nested/b", "2")]);
+    }
+
+    #[test]
+    fn test_branch() {
+        #[derive(Serialize)]
+        struct Config {
+            name: &'static str,
+            id: u8,
+        }
+
+        let test_dir = "./.test-ser-branch";
+        let _ = fs::remove_dir_all(test_dir);
+        fs::create_dir_all(format!("{}/stale", test_dir)).unwrap();
+        let mut serializer = Serializer::new(test_dir)
+            .unwrap()
+            .on_existing_root(OnExistingRoot::Clear)
+            .typed_leaves(true);
+        let mut branch = serializer.branch("nested/left").unwrap();
+        // the root is cleared before the branch writes, never after
+        assert!(!Path::new(test_dir).join("stale").exists());
+        assert!(matches!(
+            serializer.branch("../outside"),
+            Err(SerError::InvalidRelativePath(_))
+        ));
+
+        Config { name: "b", id: 2 }.serialize(&mut branch).unwrap();
+        Config { name: "a", id: 1 }
+            .serialize(&mut serializer)
+            .unwrap();
+        assert_eq!(branch.stats.files, 2);
+        serializer.join(branch);
+        let stats = serializer.finish().unwrap();
+        // the four leaves and the version marker
+        assert_eq!(stats.files, 5);
+        assert_eq!(stats.max_depth, 3);
+
+        // options carry over to the branch
+        check_and_reset(
+            test_dir,
+            vec![
+                ("name", "sa"),
+                ("id", "u1"),
+                ("nested/left/name", "sb"),
+                ("nested/left/id", "u2"),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_join_held_back_writes() {
+        #[derive(Serialize)]
+        struct Asset {
+            name: &'static str,
+            #[serde(with = "serde_bytes")]
+            data: Vec<u8>,
+        }
+
+        let test_dir = "./.test-ser-join";
+        let _ = fs::remove_dir_all(test_dir);
+        let asset = || Asset {
+            name: "logo",
+            data: vec![0x89, b'P', 0],
+        };
+
+        // shared subtrees are only written by `finish`
+        let mut serializer = Serializer::new(test_dir).unwrap().share_subtrees(true);
+        serializer.track();
+        let mut branch = serializer.branch("left").unwrap();
+        asset().serialize(&mut branch).unwrap();
+        serializer.join(branch);
+        asset().serialize(&mut serializer).unwrap();
+        let (_, tracker) = serializer.finish_tracked().unwrap();
+        let added = tracker.unwrap().added;
+        assert!(added.contains(&Path::new(test_dir).join("left/name")));
+        check_and_reset(test_dir, vec![("name", "logo"), ("left/name", "logo")]);
+
+        // so are the binary leaves listed for git
+        let mut serializer = Serializer::new(test_dir).unwrap().git_helpers(true);
+        let mut branch = serializer.branch("left").unwrap();
+        asset().serialize(&mut branch).unwrap();
+        serializer.join(branch);
+        serializer.finish().unwrap();
+        let attributes = fs::read_to_string(Path::new(test_dir).join(GIT_ATTRIBUTES)).unwrap();
+        assert!(attributes.lines().any(|line| line == "/left/data binary"));
+        fs::remove_dir_all(test_dir).unwrap();
+    }
+
+    #[test]
+    fn test_finish() {
+        #[derive(Serialize)]
+        struct Config {
+            name: &'static str,
+            ports: Vec<u16>,
+        }
+
+        let test_dir = "./.test-ser-finish";
+        let _ = fs::remove_dir_all(test_dir);
+        let config = Config {
+            name: "app",
+            ports: vec![80, 443],
+        };
+        let mut serializer = Serializer::new(test_dir).unwrap();
+        config.serialize(&mut serializer).unwrap();
+        assert!(fs::metadata(format!("{}/{}", test_dir, VERSION_MARKER)).is_err());
+        let stats = serializer.finish().unwrap();
+        assert_eq!(
+            fs::read_to_string(format!("{}/{}", test_dir, VERSION_MARKER)).unwrap(),
+            FormatVersion::CURRENT.as_str()
+        );
+        // the marker is counted like any other leaf
+        assert_eq!(stats.files, 4);
+        assert_eq!(stats.directories, 1);
+        assert_eq!(stats.max_depth, 2);
+
+        // rewriting the same value only touches the leaves that changed
+        let mut serializer = Serializer::new(test_dir).unwrap();
+        serializer.track();
+        Config {
+            ports: vec![80, 8080],
+            ..config
+        }
+        .serialize(&mut serializer)
+        .unwrap();
+        let (_, tracker) = serializer.finish_tracked().unwrap();
+        let tracker = tracker.unwrap();
+        assert_eq!(tracker.added, Vec::<PathBuf>::new());
+        assert_eq!(
+            tracker.updated,
+            vec![Path::new(test_dir).join("ports").join("1")]
+        );
+
+        fs::remove_dir_all(test_dir).unwrap();
+    }
+
+    #[test]
+    fn test_durable() {
+        #[derive(Serialize, serde::Deserialize, PartialEq, Debug)]
+        struct Checkpoint {
+            step: u64,
+            weights: Vec<f64>,
+            meta: BTreeMap<String, String>,
+        }
+
+        let test_dir = "./.test-ser-durable";
+        let _ = std::fs::remove_dir_all(test_dir);
+        let value = Checkpoint {
+            step: 7,
+            weights: vec![0.5, 1.5],
+            meta: [("name".into(), "run".into())].into(),
+        };
+        let mut ser = Serializer::new(test_dir).unwrap().durable(true);
+        value.serialize(&mut ser).unwrap();
+        ser.finish().unwrap();
+        assert_eq!(crate::from_fs::<Checkpoint>(test_dir).unwrap(), value);
+
+        // Overwriting existing leaves takes the same path
+        let mut ser = Serializer::new(test_dir).unwrap().durable(true);
+        value.serialize(&mut ser).unwrap();
+
+        std::fs::remove_dir_all(test_dir).unwrap();
+    }
+
+    #[test]
+    fn test_max_path_len() {
+        #[derive(Serialize)]
+        struct Node {
+            short: u8,
+            deep: Option<Box<Node>>,
+        }
+
+        let mut value = Node {
+            short: 0,
+            deep: None,
+        };
+        for i in 1..10 {
+            value = Node {
+                short: i,
+                deep: Some(Box::new(value)),
+            };
+        }
+
+        let test_dir = "./.test-ser-max-path-len";
+        let _ = std::fs::remove_dir_all(test_dir);
+        // "./.test-ser-max-path-len/deep/deep/deep/short" is 45 bytes
+        let mut ser = Serializer::new(test_dir).unwrap().max_path_len(44);
+        let err = value.serialize(&mut ser).unwrap_err();
+        assert!(matches!(err, SerError::PathTooLong(ref p) if p.ends_with("deep/deep/deep/short")));
+        // The branch that hit the limit was never created
+        assert!(!Path::new(test_dir).join("deep/deep/deep").exists());
+
+        let mut ser = Serializer::new(test_dir).unwrap().max_path_len(1000);
+        value.serialize(&mut ser).unwrap();
+
+        std::fs::remove_dir_all(test_dir).unwrap();
+    }
+
+    #[test]
+    fn test_version_marker() {
+        #[derive(Serialize)]
+        enum E {
+            Tuple(u32, u32),
+        }
+
+        let test_dir = "./.test-ser-version-marker";
+        let _ = std::fs::remove_dir_all(test_dir);
+
+        to_fs(&E::Tuple(1, 2), test_dir).unwrap();
+        check_and_reset(
+            test_dir,
+            vec![
+                ("Tuple/0", "1"),
+                ("Tuple/1", "2"),
+                (VERSION_MARKER, FormatVersion::CURRENT.as_str()),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_stats() {
+        #[derive(Serialize)]
+        struct Inner {
+            a: u8,
+            b: Vec<&'static str>,
+            empty: Vec<u8>,
+        }
+
+        #[derive(Serialize)]
+        struct Test {
+            int: u32,
+            inner: Inner,
+        }
+
+        let test_dir = "./.test-ser-stats";
+        let _ = std::fs::remove_dir_all(test_dir);
+
+        let test = Test {
+            int: 100,
+            inner: Inner {
+                a: 7,
+                b: vec!["xy", "z"],
+                empty: vec![],
+            },
+        };
+
+        // int, inner/a, inner/b/0, inner/b/1 and the version marker
+        let stats = to_fs_stats(&test, test_dir).unwrap();
+        assert_eq!(
+            stats,
+            SerStats {
+                files: 5,
+                directories: 3,
+                total_bytes: 3 + 1 + 2 + 1 + 1,
+                max_depth: 3,
+            }
+        );
+        check_and_reset(test_dir, vec![("int", "100"), ("inner/b/1", "z")]);
+    }
+
+    #[test]
+    fn test_to_fs_path() {
+        #[derive(Serialize)]
+        struct Test {
+            a: u32,
+        }
+
+        let test_dir = "./.test-ser-path";
+        let _ = std::fs::remove_dir_all(test_dir);
+
+        let root = to_fs_path(&Test { a: 1 }, test_dir).unwrap();
+        assert!(root.is_absolute());
+        assert_eq!(root, fs::canonicalize(test_dir).unwrap());
+        assert_eq!(std::fs::read_to_string(root.join("a")).unwrap(), "1");
+        check_and_reset(test_dir, vec![("a", "1")]);
+    }
+
+    #[test]
+    fn test_root_scalars() {
+        let test_dir = "./.test-ser-root-scalars";
+        let _ = std::fs::remove_dir_all(test_dir);
+
+        let res = to_fs(&true, test_dir);
+        assert!(matches!(
+            res,
+            Err(SerError::NotSupportedAtRootLevel("bools"))
+        ));
+        let res = to_fs(&5u8, test_dir);
+        assert!(matches!(res, Err(SerError::NotSupportedAtRootLevel(_))));
+        assert!(!Path::new(test_dir).exists());
+
+        // Bools inside of a structure are fine
+        to_fs(&vec![true, false], test_dir).unwrap();
+        check_and_reset(test_dir, vec![("0", "true"), ("1", "false")]);
+    }
 }
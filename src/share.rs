@@ -0,0 +1,214 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
+use crate::ser::{content_hash, Write};
+use crate::vfs::FileSystem;
+
+/// Name of the file at the root listing the subtrees shared by
+/// [`Serializer::share_subtrees`](crate::Serializer::share_subtrees)
+pub const SHARED_MANIFEST: &str = ".serde_fs_shared";
+
+/// Subtrees with fewer leaves than this are written out, sharing them would save next to nothing
+const MIN_SHARED_LEAVES: usize = 2;
+
+/// Finds the directories among `writes` whose whole subtree repeats an earlier one, in path
+/// order. Returns the writes left once those subtrees are replaced by empty directories, and the
+/// manifest mapping each replaced directory to the one it repeats, both relative to `root`
+pub(crate) fn share_subtrees(
+    root: &Path,
+    writes: Vec<Write>,
+) -> (Vec<Write>, BTreeMap<String, String>) {
+    // sorted by path, so every subtree is a contiguous range
+    let entries: BTreeMap<PathBuf, Option<Vec<u8>>> = writes
+        .into_iter()
+        .map(|write| match write {
+            Write::Dir(path) => (relative(root, &path), None),
+            Write::File(path, data) => (relative(root, &path), Some(data)),
+        })
+        .collect();
+    let mut dirs = BTreeSet::new();
+    for (path, data) in &entries {
+        let dir = if data.is_some() {
+            path.parent()
+        } else {
+            Some(path.as_path())
+        };
+        dirs.extend(
+            dir.into_iter()
+                .flat_map(Path::ancestors)
+                .filter(|d| !d.as_os_str().is_empty())
+                .map(Path::to_owned),
+        );
+    }
+
+    let mut seen: HashMap<u64, Vec<PathBuf>> = HashMap::new();
+    let mut shared: BTreeMap<PathBuf, PathBuf> = BTreeMap::new();
+    for dir in dirs {
+        if shared
+            .keys()
+            .next_back()
+            .is_some_and(|last| dir.starts_with(last))
+        {
+            continue;
+        }
+        let encoded = encode_subtree(&entries, &dir);
+        let leaves = subtree(&entries, &dir).filter(|(_, d)| d.is_some()).count();
+        if leaves < MIN_SHARED_LEAVES {
+            continue;
+        }
+        let candidates = seen.entry(content_hash(&encoded)).or_default();
+        // compare the contents too, equal hashes alone could be a collision
+        match candidates
+            .iter()
+            .find(|original| encode_subtree(&entries, original) == encoded)
+        {
+            Some(original) => {
+                shared.insert(dir, original.clone());
+            }
+            None => candidates.push(dir),
+        }
+    }
+
+    let mut kept = Vec::new();
+    for (path, data) in entries {
+        if shared.keys().any(|dup| path.starts_with(dup)) {
+            continue;
+        }
+        kept.push(match data {
+            Some(data) => Write::File(root.join(path), data),
+            None => Write::Dir(root.join(path)),
+        });
+    }
+    // an empty directory stands in for each shared subtree, so listings still show it
+    kept.extend(shared.keys().map(|dup| Write::Dir(root.join(dup))));
+    let manifest = shared
+        .into_iter()
+        .map(|(dup, original)| (manifest_path(&dup), manifest_path(&original)))
+        .collect();
+    (kept, manifest)
+}
+
+/// Reads the [`SHARED_MANIFEST`] at `root`, returning each shared directory with the one to read
+/// instead. Trees without a manifest, or with one that cannot be parsed, share nothing
+pub(crate) fn load_manifest<F: FileSystem>(fs: &F, root: &Path) -> Vec<(PathBuf, PathBuf)> {
+    let manifest: BTreeMap<String, String> = match fs.read(&root.join(SHARED_MANIFEST)) {
+        Ok(data) => serde_json::from_slice(&data).unwrap_or_default(),
+        Err(_) => BTreeMap::new(),
+    };
+    manifest
+        .into_iter()
+        .map(|(dup, original)| (root.join(dup), root.join(original)))
+        .collect()
+}
+
+fn relative(root: &Path, path: &Path) -> PathBuf {
+    path.strip_prefix(root).unwrap_or(path).to_owned()
+}
+
+/// Joins the components of `path` with `/`, so manifests are the same on every platform
+fn manifest_path(path: &Path) -> String {
+    let components: Vec<_> = path.iter().map(|c| c.to_string_lossy()).collect();
+    components.join("/")
+}
+
+/// Returns the entries strictly below `dir`
+fn subtree<'a>(
+    entries: &'a BTreeMap<PathBuf, Option<Vec<u8>>>,
+    dir: &'a Path,
+) -> impl Iterator<Item = (&'a PathBuf, &'a Option<Vec<u8>>)> {
+    entries
+        .range(dir.to_owned()..)
+        .skip_while(move |(path, _)| path.as_path() == dir)
+        .take_while(move |(path, _)| path.starts_with(dir))
+}
+
+/// Encodes the subtree below `dir` relative to it, so that equal subtrees encode the same
+fn encode_subtree(entries: &BTreeMap<PathBuf, Option<Vec<u8>>>, dir: &Path) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    for (path, data) in subtree(entries, dir) {
+        encoded.extend_from_slice(manifest_path(path.strip_prefix(dir).unwrap()).as_bytes());
+        encoded.push(0);
+        match data {
+            Some(data) => {
+                encoded.push(b'f');
+                encoded.extend_from_slice(&(data.len() as u64).to_le_bytes());
+                encoded.extend_from_slice(data);
+            }
+            None => encoded.push(b'd'),
+        }
+    }
+    encoded
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ser::Serializer;
+    use serde::{Deserialize, Serialize};
+
+    #[test]
+    fn test_share_subtrees() {
+        #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+        struct Limits {
+            cpu: u32,
+            memory: String,
+            ports: Vec<u16>,
+        }
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Config {
+            name: String,
+            primary: Limits,
+            replica: Limits,
+            batch: Limits,
+            id: u32,
+        }
+
+        let test_dir = "./.test-share-subtrees";
+        let _ = std::fs::remove_dir_all(test_dir);
+        let limits = Limits {
+            cpu: 2,
+            memory: "1G".into(),
+            ports: vec![80, 443],
+        };
+        let config = Config {
+            name: "web".into(),
+            primary: limits.clone(),
+            replica: limits.clone(),
+            batch: Limits { cpu: 8, ..limits },
+            id: 1,
+        };
+        let mut serializer = Serializer::new(test_dir).unwrap().share_subtrees(true);
+        config.serialize(&mut serializer).unwrap();
+        // nothing is written before the whole value is known
+        assert!(!Path::new(test_dir).exists());
+        serializer.finish().unwrap();
+
+        let dir = |path: &str| Path::new(test_dir).join(path);
+        // `replica` is stored once, as `primary`, `batch` differs in `cpu` only
+        assert_eq!(
+            std::fs::read_to_string(dir("primary/memory")).unwrap(),
+            "1G"
+        );
+        assert_eq!(std::fs::read_dir(dir("replica")).unwrap().count(), 0);
+        // `ports` is the same everywhere, and is stored in `batch`, which comes first
+        assert!(dir("batch/ports/1").exists());
+        assert_eq!(std::fs::read_dir(dir("primary/ports")).unwrap().count(), 0);
+        let manifest: BTreeMap<String, String> =
+            serde_json::from_slice(&std::fs::read(dir(SHARED_MANIFEST)).unwrap()).unwrap();
+        assert_eq!(
+            manifest,
+            [
+                ("primary/ports".into(), "batch/ports".into()),
+                ("replica".into(), "primary".into())
+            ]
+            .into()
+        );
+
+        let read: Config = crate::from_fs(test_dir).unwrap();
+        assert_eq!(read, config);
+        std::fs::remove_dir_all(test_dir).unwrap();
+    }
+}
@@ -0,0 +1,269 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::de::{DeConfig, Deserializer};
+use crate::error::SerError;
+use crate::ser::{Result, SerConfig, Serializer};
+use crate::vfs::StdFs;
+
+type Error = SerError;
+
+/// What [`sync_fs`] changed. All paths are relative to the root
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SyncReport {
+    /// Files that did not exist before
+    pub added: Vec<PathBuf>,
+    /// Files whose contents changed
+    pub updated: Vec<PathBuf>,
+    /// Files and directories that were not part of the value and have been deleted
+    pub removed: Vec<PathBuf>,
+}
+
+/// Serializes `value` into `root`, then deletes everything under `root` that the serialization
+/// did not write, so that the tree on disk exactly matches `value`.
+///
+/// Nothing outside of `root` is ever deleted. Symlinks are removed as links, never followed, and
+/// if `root` contains a symlink that resolves outside of `root` this returns
+/// [`SerError::SymlinkOutsideRoot`] before anything is written.
+pub fn sync_fs<T>(value: &T, root: impl AsRef<Path>) -> Result<SyncReport>
+where
+    T: Serialize,
+{
+    sync_with_config(value, root.as_ref(), SerConfig::default())
+}
+
+/// Rewrites the tree at `root`, written as a `T` with the options in `from`, so that it is
+/// written with the options in `to` instead. This is how trees are moved to a new
+/// [`EnumRepr`](crate::EnumRepr) or any other change of representation.
+///
+/// The tree is read completely before anything is written, so a tree that cannot be read as a
+/// `T` is left untouched. The report lists what changed as [`sync_fs`] would
+pub fn migrate_fs<T>(root: impl AsRef<Path>, from: DeConfig, to: SerConfig) -> Result<SyncReport>
+where
+    T: Serialize + DeserializeOwned,
+{
+    let root = root.as_ref();
+    let mut deserializer = Deserializer::with_config(StdFs, root, from);
+    let value = T::deserialize(&mut deserializer)?;
+    sync_with_config(&value, root, to)
+}
+
+fn sync_with_config<T>(value: &T, root: &Path, config: SerConfig) -> Result<SyncReport>
+where
+    T: Serialize,
+{
+    if fs::symlink_metadata(root).is_ok() {
+        let canonical_root = fs::canonicalize(root)?;
+        check_symlinks(&canonical_root, root)?;
+    }
+
+    let mut serializer = Serializer::with_config(root, config)?;
+    serializer.track();
+    value.serialize(&mut serializer)?;
+    let (_, tracker) = serializer.finish_tracked()?;
+    let tracker = tracker.unwrap();
+
+    let mut removed = Vec::new();
+    remove_unwritten(root, &tracker.written, &mut removed)?;
+
+    let relative = |paths: Vec<PathBuf>| -> Vec<PathBuf> {
+        paths
+            .into_iter()
+            .map(|p| p.strip_prefix(root).map(Path::to_owned).unwrap_or(p))
+            .collect()
+    };
+    Ok(SyncReport {
+        added: relative(tracker.added),
+        updated: relative(tracker.updated),
+        removed: relative(removed),
+    })
+}
+
+/// Fails if any symlink below `dir` points outside of `canonical_root`
+fn check_symlinks(canonical_root: &Path, dir: &Path) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let file_type = fs::symlink_metadata(&path)?.file_type();
+        if file_type.is_symlink() {
+            // Dangling links are fine, removing them only removes the link
+            if let Ok(target) = fs::canonicalize(&path) {
+                if !target.starts_with(canonical_root) {
+                    return Err(Error::SymlinkOutsideRoot(path));
+                }
+            }
+        } else if file_type.is_dir() {
+            check_symlinks(canonical_root, &path)?;
+        }
+    }
+    Ok(())
+}
+
+fn remove_unwritten(
+    dir: &Path,
+    written: &HashSet<PathBuf>,
+    removed: &mut Vec<PathBuf>,
+) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let file_type = fs::symlink_metadata(&path)?.file_type();
+        if written.contains(&path) {
+            if file_type.is_dir() {
+                remove_unwritten(&path, written, removed)?;
+            }
+            continue;
+        }
+        if file_type.is_dir() {
+            fs::remove_dir_all(&path)?;
+        } else {
+            // also removes symlinks without touching their target
+            fs::remove_file(&path)?;
+        }
+        removed.push(path);
+    }
+    Ok(())
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sync() {
+        #[derive(Serialize)]
+        struct Big {
+            a: u32,
+            b: Vec<u32>,
+            c: u32,
+        }
+
+        #[derive(Serialize)]
+        struct Small {
+            a: u32,
+            c: u32,
+        }
+
+        let test_dir = "./.test-sync";
+        let _ = fs::remove_dir_all(test_dir);
+
+        let report = sync_fs(
+            &Big {
+                a: 1,
+                b: vec![2, 3],
+                c: 4,
+            },
+            test_dir,
+        )
+        .unwrap();
+        assert_eq!(report.added.len(), 5);
+        assert!(report.updated.is_empty());
+        assert!(report.removed.is_empty());
+
+        let report = sync_fs(&Small { a: 1, c: 5 }, test_dir).unwrap();
+        assert_eq!(
+            report,
+            SyncReport {
+                added: vec![],
+                updated: vec![PathBuf::from("c")],
+                removed: vec![PathBuf::from("b")],
+            }
+        );
+        assert!(!Path::new(test_dir).join("b").exists());
+        assert_eq!(fs::read_to_string(format!("{}/c", test_dir)).unwrap(), "5");
+
+        fs::remove_dir_all(test_dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_sync_symlink_outside_root() {
+        #[derive(Serialize)]
+        struct Small {
+            a: u32,
+        }
+
+        let test_dir = "./.test-sync-symlink";
+        let outside = "./.test-sync-symlink-outside";
+        let _ = fs::remove_dir_all(test_dir);
+        let _ = fs::remove_dir_all(outside);
+        fs::create_dir_all(test_dir).unwrap();
+        fs::create_dir_all(outside).unwrap();
+        fs::write(format!("{}/keep", outside), "data").unwrap();
+        std::os::unix::fs::symlink(
+            fs::canonicalize(outside).unwrap(),
+            format!("{}/link", test_dir),
+        )
+        .unwrap();
+
+        let res = sync_fs(&Small { a: 1 }, test_dir);
+        assert!(matches!(res, Err(SerError::SymlinkOutsideRoot(_))));
+        assert!(!Path::new(test_dir).join("a").exists());
+        assert!(Path::new(outside).join("keep").exists());
+
+        fs::remove_dir_all(test_dir).unwrap();
+        fs::remove_dir_all(outside).unwrap();
+    }
+
+    #[test]
+    fn test_migrate() {
+        use crate::EnumRepr;
+        use serde::Deserialize;
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        enum Shape {
+            Empty,
+            Circle { radius: u32 },
+        }
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Scene {
+            first: Shape,
+            second: Shape,
+        }
+
+        let test_dir = "./.test-sync-migrate";
+        let _ = fs::remove_dir_all(test_dir);
+        let value = Scene {
+            first: Shape::Empty,
+            second: Shape::Circle { radius: 3 },
+        };
+        sync_fs(&value, test_dir).unwrap();
+        assert!(Path::new(test_dir).join("second/Circle/radius").exists());
+
+        let by_index = SerConfig {
+            enum_repr: EnumRepr::ByIndex,
+            ..SerConfig::default()
+        };
+        let report = migrate_fs::<Scene>(test_dir, DeConfig::default(), by_index).unwrap();
+        assert_eq!(
+            report,
+            SyncReport {
+                added: vec![PathBuf::from("second/1/radius")],
+                updated: vec![PathBuf::from("first")],
+                removed: vec![PathBuf::from("second/Circle")],
+            }
+        );
+        assert_eq!(
+            fs::read_to_string(format!("{}/first", test_dir)).unwrap(),
+            "0"
+        );
+
+        let by_index = DeConfig {
+            enum_repr: EnumRepr::ByIndex,
+            ..DeConfig::default()
+        };
+        let mut de = Deserializer::with_config(StdFs, test_dir, by_index);
+        assert_eq!(Scene::deserialize(&mut de).unwrap(), value);
+
+        // a tree that does not match the old options is left alone
+        let res = migrate_fs::<Scene>(test_dir, DeConfig::default(), SerConfig::default());
+        assert!(matches!(res, Err(SerError::ReadExisting(_))));
+        assert!(Path::new(test_dir).join("second/1/radius").exists());
+
+        fs::remove_dir_all(test_dir).unwrap();
+    }
+}
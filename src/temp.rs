@@ -0,0 +1,123 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::ser::Result;
+
+/// Separates the name of the entry being replaced from the random suffix in temporary entry
+/// names, see [`is_temp_name`]
+const TEMP_INFIX: &str = ".tmp.";
+
+/// Returns true if `name` is a temporary entry left by an interrupted write: a hidden entry named
+/// `.<name>.tmp.<suffix>`, where neither part is empty
+pub(crate) fn is_temp_name(name: &str) -> bool {
+    name.strip_prefix('.')
+        .and_then(|rest| rest.split_once(TEMP_INFIX))
+        .is_some_and(|(name, suffix)| !name.is_empty() && !suffix.is_empty())
+}
+
+/// Removes the temporary entries that interrupted writes left anywhere below `root`, and returns
+/// their paths relative to `root`.
+///
+/// Symlinks are never followed, a temporary symlink is removed as a link
+pub fn cleanup_fs(root: impl AsRef<Path>) -> Result<Vec<PathBuf>> {
+    let root = root.as_ref();
+    let mut removed = Vec::new();
+    remove_temp_entries(root, &mut removed)?;
+    removed.sort();
+    Ok(removed
+        .into_iter()
+        .map(|p| p.strip_prefix(root).map(Path::to_owned).unwrap_or(p))
+        .collect())
+}
+
+fn remove_temp_entries(dir: &Path, removed: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let file_type = fs::symlink_metadata(&path)?.file_type();
+        if !is_temp_name(&entry.file_name().to_string_lossy()) {
+            if file_type.is_dir() {
+                remove_temp_entries(&path, removed)?;
+            }
+            continue;
+        }
+        if file_type.is_dir() {
+            fs::remove_dir_all(&path)?;
+        } else {
+            fs::remove_file(&path)?;
+        }
+        removed.push(path);
+    }
+    Ok(())
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde::{Deserialize, Serialize};
+
+    #[test]
+    fn test_is_temp_name() {
+        assert!(is_temp_name(".config.tmp.1a2b"));
+        assert!(is_temp_name(".0.tmp.x"));
+        assert!(!is_temp_name("config.tmp.1a2b"));
+        assert!(!is_temp_name(".config.tmp."));
+        assert!(!is_temp_name("..tmp.1a2b"));
+        assert!(!is_temp_name(".config"));
+    }
+
+    #[test]
+    fn test_leftover_temp_entries() {
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        enum Mode {
+            Fast,
+            Custom { level: u8 },
+        }
+
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct Config {
+            name: String,
+            limits: std::collections::BTreeMap<String, u32>,
+            mode: Mode,
+            ports: Vec<u16>,
+        }
+
+        let test_dir = "./.test-temp-leftovers";
+        let _ = fs::remove_dir_all(test_dir);
+        let config = Config {
+            name: "app".into(),
+            limits: [("cpu".into(), 4)].into(),
+            mode: Mode::Custom { level: 2 },
+            ports: vec![80],
+        };
+        crate::to_fs(&config, test_dir).unwrap();
+
+        // what interrupted writes of `limits`, `mode` and `ports/1` would leave behind
+        fs::create_dir_all(format!("{}/.limits.tmp.a1/mem", test_dir)).unwrap();
+        fs::create_dir_all(format!("{}/limits/.cpu.tmp.b2", test_dir)).unwrap();
+        fs::create_dir_all(format!("{}/mode/.Custom.tmp.c3", test_dir)).unwrap();
+        fs::write(format!("{}/ports/.1.tmp.d4", test_dir), "443").unwrap();
+
+        let read: Config = crate::from_fs(test_dir).unwrap();
+        assert_eq!(read, config);
+        let mut de = crate::Deserializer::from_fs(test_dir).ignore_temp_entries(false);
+        assert_ne!(Config::deserialize(&mut de).ok(), Some(config));
+
+        assert_eq!(
+            cleanup_fs(test_dir).unwrap(),
+            vec![
+                PathBuf::from(".limits.tmp.a1"),
+                PathBuf::from("limits/.cpu.tmp.b2"),
+                PathBuf::from("mode/.Custom.tmp.c3"),
+                PathBuf::from("ports/.1.tmp.d4"),
+            ]
+        );
+        assert_eq!(cleanup_fs(test_dir).unwrap(), Vec::<PathBuf>::new());
+        assert!(fs::metadata(format!("{}/limits/cpu", test_dir)).is_ok());
+
+        fs::remove_dir_all(test_dir).unwrap();
+    }
+}
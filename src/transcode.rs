@@ -0,0 +1,63 @@
+use std::io::{Read, Write};
+use std::path::Path;
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::de::{self, Deserializer};
+use crate::ser;
+
+/// Writes the tree at `root` to `writer` as JSON, without needing the type it was written from.
+///
+/// Directories become objects, or arrays when their entries are exactly `0..n`. Leaves holding a
+/// canonical bool or number are written as such, every other leaf is a string. Empty directories
+/// are written as empty objects
+pub fn fs_to_json<W: Write>(root: impl AsRef<Path>, writer: W) -> de::Result<()> {
+    let mut deserializer = Deserializer::from_fs(root).infer_types(true);
+    let value = Value::deserialize(&mut deserializer)?;
+    serde_json::to_writer(writer, &value)?;
+    Ok(())
+}
+
+/// Reads a JSON document from `reader` and writes it as a tree at `root`, like
+/// [`to_fs`](crate::to_fs) would for the equivalent Rust value.
+///
+/// `null` is written the same way as `None`, so with the default options it leaves no entry
+pub fn json_to_fs<R: Read>(reader: R, root: impl AsRef<Path>) -> ser::Result<()> {
+    let value: Value = serde_json::from_reader(reader)?;
+    ser::to_fs(&value, root)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_transcode() {
+        let test_dir = "./.test-transcode";
+        let _ = std::fs::remove_dir_all(test_dir);
+        let value = json!({
+            "name": "server",
+            "port": 8080,
+            "ratio": 0.5,
+            "enabled": true,
+            "tags": ["a", "b"],
+            "limits": { "cpu": -2, "memory": "1G" },
+            "empty": {},
+        });
+        json_to_fs(value.to_string().as_bytes(), test_dir).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(format!("{}/limits/memory", test_dir)).unwrap(),
+            "1G"
+        );
+
+        let mut json = Vec::new();
+        fs_to_json(test_dir, &mut json).unwrap();
+        let read: Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(read, value);
+        std::fs::remove_dir_all(test_dir).unwrap();
+    }
+}
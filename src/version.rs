@@ -0,0 +1,55 @@
+use std::path::Path;
+
+use crate::vfs::{FileSystem, StdFs};
+
+/// Name of the file at the root of a tree recording which layout version wrote it
+pub const VERSION_MARKER: &str = ".serde_fs_version";
+
+/// The on-disk layout version of a tree.
+///
+/// The serializer records the version it wrote in [`VERSION_MARKER`] at the root of the tree, so
+/// that newer deserializers can keep reading trees written by older versions of this crate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FormatVersion {
+    /// Trees written before the version marker existed. Char leaves may be padded with NUL bytes
+    V1,
+    /// The current layout
+    V2,
+}
+
+impl FormatVersion {
+    pub const CURRENT: FormatVersion = FormatVersion::V2;
+
+    /// Reads the version marker at `root`.
+    ///
+    /// A missing marker means the tree predates versioning and is treated as [`FormatVersion::V1`].
+    /// An unrecognized marker falls back to [`FormatVersion::CURRENT`].
+    pub fn detect(root: impl AsRef<Path>) -> FormatVersion {
+        FormatVersion::detect_in(&StdFs, root.as_ref())
+    }
+
+    pub(crate) fn detect_in(fs: &impl FileSystem, root: &Path) -> FormatVersion {
+        match fs.read(&root.join(VERSION_MARKER)) {
+            Ok(s) => std::str::from_utf8(&s)
+                .ok()
+                .and_then(|s| FormatVersion::parse(s.trim()))
+                .unwrap_or(FormatVersion::CURRENT),
+            Err(_) => FormatVersion::V1,
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<FormatVersion> {
+        match s {
+            "1" => Some(FormatVersion::V1),
+            "2" => Some(FormatVersion::V2),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FormatVersion::V1 => "1",
+            FormatVersion::V2 => "2",
+        }
+    }
+}
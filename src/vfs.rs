@@ -0,0 +1,135 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// What a path points at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Dir,
+}
+
+/// The file system operations used to read a tree.
+///
+/// [`StdFs`] reads the real file system, [`MemFs`] holds a tree in memory
+pub trait FileSystem {
+    /// Returns what `path` points at, following symlinks
+    fn metadata(&self, path: &Path) -> io::Result<EntryKind>;
+
+    /// Reads the whole file at `path`
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+
+    /// Lists the names of the entries in the directory at `path`
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<OsString>>;
+
+    /// Returns when the file at `path` was last modified. File systems without modification
+    /// times return an [`io::ErrorKind::Unsupported`] error, which is the default
+    fn modified(&self, _path: &Path) -> io::Result<SystemTime> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+/// The real file system, through [`std::fs`]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StdFs;
+
+impl FileSystem for StdFs {
+    fn metadata(&self, path: &Path) -> io::Result<EntryKind> {
+        let metadata = fs::metadata(path)?;
+        Ok(if metadata.is_dir() {
+            EntryKind::Dir
+        } else {
+            EntryKind::File
+        })
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(path)
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<OsString>> {
+        fs::read_dir(path)?
+            .map(|entry| entry.map(|e| e.file_name()))
+            .collect()
+    }
+
+    fn modified(&self, path: &Path) -> io::Result<SystemTime> {
+        fs::metadata(path)?.modified()
+    }
+}
+
+/// An in-memory tree rooted at the empty path
+#[derive(Debug, Clone)]
+pub struct MemFs {
+    files: BTreeMap<PathBuf, Vec<u8>>,
+    /// Maps each directory to the names of its entries
+    dirs: BTreeMap<PathBuf, BTreeSet<OsString>>,
+}
+
+impl Default for MemFs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemFs {
+    /// Creates a tree containing only the empty root directory
+    pub fn new() -> Self {
+        Self {
+            files: BTreeMap::new(),
+            dirs: [(PathBuf::new(), BTreeSet::new())].into(),
+        }
+    }
+
+    /// Writes a file at `path`, creating its parent directories
+    pub fn insert(&mut self, path: impl Into<PathBuf>, data: impl Into<Vec<u8>>) {
+        let path = path.into();
+        if let Some(parent) = path.parent() {
+            self.create_dir_all(parent);
+            let name = path.file_name().expect("file path has no name").to_owned();
+            self.dirs.get_mut(parent).unwrap().insert(name);
+        }
+        self.files.insert(path, data.into());
+    }
+
+    /// Creates the directory at `path` and all of its parents
+    pub fn create_dir_all(&mut self, path: impl AsRef<Path>) {
+        let mut path = path.as_ref();
+        while let Some(parent) = path.parent() {
+            let name = path.file_name().expect("dir path has no name").to_owned();
+            self.dirs.entry(path.to_owned()).or_default();
+            if !self.dirs.entry(parent.to_owned()).or_default().insert(name) {
+                return;
+            }
+            path = parent;
+        }
+    }
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, path.display().to_string())
+}
+
+impl FileSystem for MemFs {
+    fn metadata(&self, path: &Path) -> io::Result<EntryKind> {
+        if self.files.contains_key(path) {
+            Ok(EntryKind::File)
+        } else if self.dirs.contains_key(path) {
+            Ok(EntryKind::Dir)
+        } else {
+            Err(not_found(path))
+        }
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.files.get(path).cloned().ok_or_else(|| not_found(path))
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<OsString>> {
+        let entries = self.dirs.get(path).ok_or_else(|| not_found(path))?;
+        Ok(entries.iter().cloned().collect())
+    }
+}
@@ -0,0 +1,149 @@
+//! Checks that every scalar type reads back exactly what was written, for the edge cases of each
+//! type: its extremes, zero, negatives and, for floats, the special values.
+//!
+//! Each value is written as the only leaf of a struct and read back through the same type, so a
+//! mismatch between how a type is formatted and how it is parsed shows up as the failing value.
+
+use std::fmt::Debug;
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize)]
+struct Leaf<T> {
+    value: T,
+}
+
+/// Round-trips each of `values`, comparing them with `same`, and reports every value that did not
+/// come back
+fn check<T>(ty: &str, values: &[T], same: fn(&T, &T) -> bool)
+where
+    T: Serialize + DeserializeOwned + Debug + Clone,
+{
+    let test_dir = format!("/tmp/.test-conformance-{}", ty);
+    let mut failures = Vec::new();
+    for value in values {
+        let _ = std::fs::remove_dir_all(&test_dir);
+        let leaf = Leaf {
+            value: value.clone(),
+        };
+        let result = serde_fs::to_fs(&leaf, &test_dir)
+            .map_err(|err| err.to_string())
+            .and_then(|()| serde_fs::from_fs::<Leaf<T>>(&test_dir).map_err(|err| err.to_string()));
+        match result {
+            Ok(read) if same(&read.value, value) => {}
+            Ok(read) => failures.push(format!("{:?} read back as {:?}", value, read.value)),
+            Err(err) => failures.push(format!("{:?} failed: {}", value, err)),
+        }
+    }
+    let _ = std::fs::remove_dir_all(&test_dir);
+    assert!(failures.is_empty(), "{}: {:#?}", ty, failures);
+}
+
+macro_rules! check_ints {
+    ($($ty:ident)*) => {
+        $(
+            check(
+                stringify!($ty),
+                &[$ty::MIN, $ty::MIN + 1, 0, 1, $ty::MAX / 2, $ty::MAX - 1, $ty::MAX],
+                $ty::eq,
+            );
+        )*
+    };
+}
+
+macro_rules! check_floats {
+    ($($ty:ident)*) => {
+        $(
+            check(
+                stringify!($ty),
+                &[
+                    0.0,
+                    -0.0,
+                    1.0,
+                    -1.5,
+                    0.1,
+                    1.0 / 3.0,
+                    $ty::MIN,
+                    $ty::MAX,
+                    $ty::MIN_POSITIVE,
+                    $ty::EPSILON,
+                    // the smallest subnormal
+                    $ty::from_bits(1),
+                    1e20,
+                    -1e-20,
+                    $ty::INFINITY,
+                    $ty::NEG_INFINITY,
+                    $ty::NAN,
+                ],
+                // bit equality keeps -0.0 apart from 0.0. NaN payloads are not kept by default,
+                // see `FloatPolicy`
+                |a, b| a.to_bits() == b.to_bits() || (a.is_nan() && b.is_nan()),
+            );
+        )*
+    };
+}
+
+#[test]
+fn integers() {
+    check_ints!(i8 i16 i32 i64 u8 u16 u32 u64);
+}
+
+#[test]
+fn floats() {
+    check_floats!(f32 f64);
+}
+
+#[test]
+fn bools() {
+    check("bool", &[true, false], bool::eq);
+}
+
+#[test]
+fn chars() {
+    check(
+        "char",
+        &[
+            'a',
+            'Z',
+            '0',
+            ' ',
+            '\t',
+            '\n',
+            '\0',
+            '\\',
+            '/',
+            'é',
+            'ß',
+            '✓',
+            '😀',
+            '\u{7f}',
+            '\u{feff}',
+            '\u{10ffff}',
+        ],
+        char::eq,
+    );
+}
+
+#[test]
+fn strings() {
+    check(
+        "string",
+        &[
+            "".to_owned(),
+            " ".to_owned(),
+            "plain".to_owned(),
+            " padded ".to_owned(),
+            "trailing newline\n".to_owned(),
+            "\r\n".to_owned(),
+            "multi\nline\ntext".to_owned(),
+            "nul\0inside".to_owned(),
+            "unicode ✓ 😀".to_owned(),
+            "42".to_owned(),
+            "true".to_owned(),
+            "null".to_owned(),
+            "\u{feff}".to_owned(),
+            "x".repeat(10_000),
+        ],
+        String::eq,
+    );
+}
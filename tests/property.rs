@@ -0,0 +1,218 @@
+//! Round-trips randomly generated values of a recursive type, and shrinks any value that fails to
+//! a minimal one before reporting it.
+//!
+//! The generated shapes stay within what the format can represent. Known gaps, like `None` inside
+//! a sequence (which ends the sequence early), are deliberately not generated.
+
+use std::collections::BTreeMap;
+
+use rand::{distributions::Alphanumeric, rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+/// Number of random values checked per run
+const CASES: u64 = 200;
+/// How deep generated values nest
+const MAX_DEPTH: usize = 4;
+/// Upper bound on shrinking steps, so a pathological failure still gets reported
+const MAX_SHRINK_STEPS: usize = 1_000;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+enum Node {
+    Bool(bool),
+    Int(i64),
+    Uint(u64),
+    Float(f64),
+    Char(char),
+    Text(String),
+    Bytes(#[serde(with = "serde_bytes")] Vec<u8>),
+    List(Vec<Node>),
+    Map(BTreeMap<String, Node>),
+    Record {
+        name: String,
+        maybe: Option<Box<Node>>,
+        children: Vec<Node>,
+    },
+}
+
+impl Node {
+    fn random(rng: &mut StdRng, depth: usize) -> Self {
+        let kinds = if depth >= MAX_DEPTH { 7 } else { 10 };
+        match rng.gen_range(0..kinds) {
+            0 => Node::Bool(rng.gen()),
+            1 => Node::Int(rng.gen()),
+            2 => Node::Uint(rng.gen()),
+            3 => Node::Float(random_float(rng)),
+            4 => Node::Char(rng.gen()),
+            5 => Node::Text(
+                (0..rng.gen_range(0..16))
+                    .map(|_| rng.gen::<char>())
+                    .collect(),
+            ),
+            6 => Node::Bytes((0..rng.gen_range(0..16)).map(|_| rng.gen()).collect()),
+            7 => Node::List(random_children(rng, depth)),
+            8 => Node::Map(
+                (0..rng.gen_range(0..4))
+                    .map(|_| (random_key(rng), Node::random(rng, depth + 1)))
+                    .collect(),
+            ),
+            9 => Node::Record {
+                name: random_key(rng),
+                maybe: match rng.gen() {
+                    true => Some(Box::new(Node::random(rng, depth + 1))),
+                    false => None,
+                },
+                children: random_children(rng, depth),
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    /// Returns values that are simpler than `self`, simplest first
+    fn shrink(&self) -> Vec<Node> {
+        let mut out = Vec::new();
+        match self {
+            Node::Bool(true) => out.push(Node::Bool(false)),
+            Node::Int(v) if *v != 0 => out.extend([Node::Int(0), Node::Int(v / 2)]),
+            Node::Uint(v) if *v != 0 => out.extend([Node::Uint(0), Node::Uint(v / 2)]),
+            Node::Float(v) if *v != 0.0 => out.extend([Node::Float(0.0), Node::Float(v.trunc())]),
+            Node::Char(c) if *c != 'a' => out.push(Node::Char('a')),
+            Node::Text(s) if !s.is_empty() => {
+                out.push(Node::Text(String::new()));
+                out.push(Node::Text(s.chars().skip(1).collect()));
+            }
+            Node::Bytes(b) if !b.is_empty() => {
+                out.push(Node::Bytes(Vec::new()));
+                out.push(Node::Bytes(b[1..].to_vec()));
+            }
+            Node::List(children) => {
+                out.extend(children.iter().cloned());
+                out.extend(shrink_vec(children).into_iter().map(Node::List));
+            }
+            Node::Map(map) => {
+                out.extend(map.values().cloned());
+                for key in map.keys() {
+                    let mut smaller = map.clone();
+                    smaller.remove(key);
+                    out.push(Node::Map(smaller));
+                }
+                for (key, value) in map {
+                    for shrunk in value.shrink() {
+                        let mut smaller = map.clone();
+                        smaller.insert(key.clone(), shrunk);
+                        out.push(Node::Map(smaller));
+                    }
+                }
+            }
+            Node::Record {
+                name,
+                maybe,
+                children,
+            } => {
+                out.extend(maybe.iter().map(|m| (**m).clone()));
+                out.extend(children.iter().cloned());
+                let record = |maybe: Option<Box<Node>>, children: Vec<Node>| Node::Record {
+                    name: name.clone(),
+                    maybe,
+                    children,
+                };
+                if let Some(m) = maybe {
+                    out.push(record(None, children.clone()));
+                    for shrunk in m.shrink() {
+                        out.push(record(Some(Box::new(shrunk)), children.clone()));
+                    }
+                }
+                for smaller in shrink_vec(children) {
+                    out.push(record(maybe.clone(), smaller));
+                }
+            }
+            _ => {}
+        }
+        out
+    }
+}
+
+/// Any float except NaN, which never compares equal to itself
+fn random_float(rng: &mut StdRng) -> f64 {
+    loop {
+        let v = f64::from_bits(rng.gen());
+        if !v.is_nan() {
+            return v;
+        }
+    }
+}
+
+/// Keys start with a letter that is not `j`, so they never opt into the embedded JSON format
+fn random_key(rng: &mut StdRng) -> String {
+    let len = rng.gen_range(0..8);
+    std::iter::once('k')
+        .chain((0..len).map(|_| rng.sample(Alphanumeric) as char))
+        .collect()
+}
+
+fn random_children(rng: &mut StdRng, depth: usize) -> Vec<Node> {
+    (0..rng.gen_range(0..4))
+        .map(|_| Node::random(rng, depth + 1))
+        .collect()
+}
+
+/// Returns `children` with one element removed, and with one element shrunk
+fn shrink_vec(children: &[Node]) -> Vec<Vec<Node>> {
+    let mut out = Vec::new();
+    for i in 0..children.len() {
+        let mut smaller = children.to_vec();
+        smaller.remove(i);
+        out.push(smaller);
+    }
+    for (i, child) in children.iter().enumerate() {
+        for shrunk in child.shrink() {
+            let mut smaller = children.to_vec();
+            smaller[i] = shrunk;
+            out.push(smaller);
+        }
+    }
+    out
+}
+
+/// Serializes `value` into `test_dir` and reads it back, describing any difference
+fn check_roundtrip(test_dir: &str, value: &Node) -> Result<(), String> {
+    let _ = std::fs::remove_dir_all(test_dir);
+    serde_fs::to_fs(value, test_dir).map_err(|e| format!("serialize: {}", e))?;
+    let actual: Node = serde_fs::from_fs(test_dir).map_err(|e| format!("deserialize: {}", e))?;
+    if &actual != value {
+        return Err(format!("read back {:?}", actual));
+    }
+    Ok(())
+}
+
+/// Greedily replaces `value` with the first simpler value that still fails
+fn minimize(test_dir: &str, mut value: Node, mut error: String) -> (Node, String) {
+    'steps: for _ in 0..MAX_SHRINK_STEPS {
+        for candidate in value.shrink() {
+            if let Err(e) = check_roundtrip(test_dir, &candidate) {
+                value = candidate;
+                error = e;
+                continue 'steps;
+            }
+        }
+        break;
+    }
+    (value, error)
+}
+
+#[test]
+fn roundtrip_random_values() {
+    let test_dir = "/tmp/.test-property";
+    for seed in 0..CASES {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let value = Node::random(&mut rng, 0);
+        if let Err(error) = check_roundtrip(test_dir, &value) {
+            let (minimal, error) = minimize(test_dir, value, error);
+            let _ = std::fs::remove_dir_all(test_dir);
+            panic!(
+                "seed {} failed to round-trip\nminimal value: {:?}\n{}",
+                seed, minimal, error
+            );
+        }
+    }
+    let _ = std::fs::remove_dir_all(test_dir);
+}
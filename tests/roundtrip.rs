@@ -0,0 +1,1708 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_fs::{
+    Align, BytesRepr, DeConfig, DeError, Deserializer, EnumRepr, FloatPolicy, OptionRepr,
+    SerConfig, Serializer, UnitRepr,
+};
+
+/// Serializes `value` to `test_dir`, reads it back and checks that nothing was lost
+fn roundtrip<T>(test_dir: &str, value: &T)
+where
+    T: Serialize + DeserializeOwned + PartialEq + Debug,
+{
+    let _ = std::fs::remove_dir_all(test_dir);
+    serde_fs::to_fs(value, test_dir).unwrap();
+    let actual: T = serde_fs::from_fs(test_dir).unwrap();
+    pretty_assertions::assert_eq!(value, &actual);
+    std::fs::remove_dir_all(test_dir).unwrap();
+}
+
+#[test]
+fn net_addrs() {
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Net {
+        v4: IpAddr,
+        v6: IpAddr,
+        socket: SocketAddr,
+        socket_v6: SocketAddr,
+    }
+
+    let test_dir = "/tmp/.test-roundtrip-net";
+    let value = Net {
+        v4: IpAddr::V4(Ipv4Addr::new(192, 168, 1, 10)),
+        v6: IpAddr::V6(Ipv6Addr::LOCALHOST),
+        socket: "127.0.0.1:8080".parse().unwrap(),
+        socket_v6: "[::1]:443".parse().unwrap(),
+    };
+    roundtrip(test_dir, &value);
+
+    // Addresses are plain text leaves
+    serde_fs::to_fs(&value, test_dir).unwrap();
+    assert_eq!(
+        std::fs::read_to_string(format!("{}/socket", test_dir)).unwrap(),
+        "127.0.0.1:8080"
+    );
+    std::fs::remove_dir_all(test_dir).unwrap();
+}
+
+#[test]
+fn empty_collections() {
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Empty {
+        vec: Vec<u32>,
+        map: BTreeMap<String, u32>,
+        nested: Vec<Vec<u32>>,
+    }
+
+    let test_dir = "/tmp/.test-roundtrip-empty-collections";
+    roundtrip(
+        test_dir,
+        &Empty {
+            vec: vec![],
+            map: BTreeMap::new(),
+            nested: vec![vec![], vec![1]],
+        },
+    );
+
+    // Empty collections are directories, so they are distinct from absent fields
+    serde_fs::to_fs(
+        &Empty {
+            vec: vec![],
+            map: BTreeMap::new(),
+            nested: vec![],
+        },
+        test_dir,
+    )
+    .unwrap();
+    for dir in ["vec", "map", "nested"] {
+        let path = format!("{}/{}", test_dir, dir);
+        assert!(std::fs::metadata(&path).unwrap().is_dir(), "{}", path);
+        assert_eq!(std::fs::read_dir(&path).unwrap().count(), 0);
+    }
+    std::fs::remove_dir_all(test_dir).unwrap();
+}
+
+#[test]
+fn cow() {
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Cows {
+        s: Cow<'static, str>,
+        bytes: Cow<'static, [u8]>,
+    }
+
+    let test_dir = "/tmp/.test-roundtrip-cow";
+    let value = Cows {
+        s: Cow::Borrowed("borrowed on write"),
+        bytes: Cow::Borrowed(&[1, 2, 3]),
+    };
+    roundtrip(test_dir, &value);
+
+    // Nothing can be borrowed from disk, so both come back owned
+    serde_fs::to_fs(&value, test_dir).unwrap();
+    let actual: Cows = serde_fs::from_fs(test_dir).unwrap();
+    assert!(matches!(actual.s, Cow::Owned(_)));
+    assert!(matches!(actual.bytes, Cow::Owned(_)));
+    std::fs::remove_dir_all(test_dir).unwrap();
+}
+
+#[test]
+fn flatten_map() {
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Flat {
+        name: String,
+        #[serde(flatten)]
+        extra: BTreeMap<String, String>,
+    }
+
+    roundtrip(
+        "/tmp/.test-roundtrip-flatten-map",
+        &Flat {
+            name: "app".into(),
+            extra: [("a".into(), "1".into()), ("b".into(), "2".into())].into(),
+        },
+    );
+}
+
+#[test]
+fn integer_bounds() {
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Bounds {
+        i8s: (i8, i8, i8),
+        i16s: (i16, i16, i16),
+        i32s: (i32, i32, i32),
+        i64s: (i64, i64, i64),
+        u8s: (u8, u8),
+        u16s: (u16, u16),
+        u32s: (u32, u32),
+        u64s: (u64, u64),
+        keys: BTreeMap<i64, u64>,
+    }
+
+    let test_dir = "/tmp/.test-roundtrip-integer-bounds";
+    roundtrip(
+        test_dir,
+        &Bounds {
+            i8s: (i8::MIN, -1, i8::MAX),
+            i16s: (i16::MIN, -1, i16::MAX),
+            i32s: (i32::MIN, -1, i32::MAX),
+            i64s: (i64::MIN, -1, i64::MAX),
+            u8s: (0, u8::MAX),
+            u16s: (0, u16::MAX),
+            u32s: (0, u32::MAX),
+            u64s: (0, u64::MAX),
+            keys: [(i64::MIN, u64::MAX), (i64::MAX, 0)].into(),
+        },
+    );
+}
+
+#[test]
+fn floats() {
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Floats {
+        f32s: Vec<f32>,
+        f64s: Vec<f64>,
+    }
+
+    // None of these are integers, so they only survive if formatting and parsing are exact
+    roundtrip(
+        "/tmp/.test-roundtrip-floats",
+        &Floats {
+            f32s: vec![
+                0.1,
+                f32::MAX,
+                f32::MIN_POSITIVE,
+                1e-30,
+                -3.4028235e38,
+                1.0 / 3.0,
+            ],
+            f64s: vec![0.1, f64::MAX, f64::MIN_POSITIVE, 1e-300, 1.0 / 3.0],
+        },
+    );
+}
+
+#[test]
+fn option_reprs() {
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Options {
+        some: Option<String>,
+        none: Option<String>,
+    }
+
+    let test_dir = "/tmp/.test-roundtrip-option-reprs";
+    let value = Options {
+        some: Some("value".into()),
+        none: None,
+    };
+    let reprs = [
+        (OptionRepr::MissingFile, None),
+        (OptionRepr::EmptyFile, Some("")),
+        (OptionRepr::SentinelFile("null".into()), Some("null")),
+    ];
+    for (repr, none_file) in reprs {
+        let _ = std::fs::remove_dir_all(test_dir);
+        let mut ser = Serializer::new(test_dir).unwrap().option_repr(repr.clone());
+        value.serialize(&mut ser).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(format!("{}/none", test_dir)).ok(),
+            none_file.map(String::from),
+            "{:?}",
+            repr
+        );
+
+        let mut de = Deserializer::from_fs(test_dir).option_repr(repr);
+        assert_eq!(Options::deserialize(&mut de).unwrap(), value);
+    }
+    std::fs::remove_dir_all(test_dir).unwrap();
+}
+
+#[test]
+fn unit_repr() {
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Marker;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Flags {
+        unit: (),
+        marker: Marker,
+        some_unit: Option<()>,
+        none_unit: Option<()>,
+        none: Option<String>,
+    }
+
+    let test_dir = "/tmp/.test-roundtrip-unit-repr";
+    let _ = std::fs::remove_dir_all(test_dir);
+    let value = Flags {
+        unit: (),
+        marker: Marker,
+        some_unit: Some(()),
+        none_unit: None,
+        none: None,
+    };
+    // A unit is otherwise not written at all, which reads back as `None`
+    let repr = UnitRepr::SentinelFile("unit".into());
+    let mut ser = Serializer::new(test_dir)
+        .unwrap()
+        .option_repr(OptionRepr::EmptyFile)
+        .unit_repr(repr.clone());
+    value.serialize(&mut ser).unwrap();
+    let read = |name: &str| std::fs::read_to_string(format!("{}/{}", test_dir, name)).unwrap();
+    assert_eq!(read("unit"), "unit");
+    assert_eq!(read("marker"), "unit");
+    assert_eq!(read("some_unit"), "unit");
+    assert_eq!(read("none_unit"), "");
+    assert_eq!(read("none"), "");
+
+    let mut de = Deserializer::from_fs(test_dir)
+        .option_repr(OptionRepr::EmptyFile)
+        .unit_repr(repr.clone());
+    pretty_assertions::assert_eq!(Flags::deserialize(&mut de).unwrap(), value);
+
+    std::fs::create_dir_all(test_dir).unwrap();
+    std::fs::write(format!("{}/unit", test_dir), "other").unwrap();
+    let mut de = Deserializer::from_fs(format!("{}/unit", test_dir)).unit_repr(repr);
+    assert!(<()>::deserialize(&mut de).is_err());
+    std::fs::remove_dir_all(test_dir).unwrap();
+}
+
+#[test]
+fn hex_bytes() {
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Key {
+        #[serde(with = "serde_bytes")]
+        hash: [u8; 32],
+        #[serde(with = "serde_bytes")]
+        salt: Vec<u8>,
+    }
+
+    let test_dir = "/tmp/.test-roundtrip-hex-bytes";
+    let _ = std::fs::remove_dir_all(test_dir);
+    let mut hash = [0; 32];
+    for (i, byte) in hash.iter_mut().enumerate() {
+        *byte = (i * 8) as u8;
+    }
+    let value = Key {
+        hash,
+        salt: vec![0xde, 0xad, 0xbe, 0xef],
+    };
+    let mut ser = Serializer::new(test_dir)
+        .unwrap()
+        .bytes_repr(BytesRepr::Hex);
+    value.serialize(&mut ser).unwrap();
+    let hash_file = std::fs::read_to_string(format!("{}/hash", test_dir)).unwrap();
+    assert_eq!(hash_file.len(), 64);
+    assert!(hash_file.starts_with("0008101820"));
+    assert_eq!(
+        std::fs::read_to_string(format!("{}/salt", test_dir)).unwrap(),
+        "deadbeef"
+    );
+
+    let mut de = Deserializer::from_fs(test_dir).bytes_repr(BytesRepr::Hex);
+    assert_eq!(Key::deserialize(&mut de).unwrap(), value);
+
+    for invalid in ["dea", "deadbeeg"] {
+        std::fs::write(format!("{}/salt", test_dir), invalid).unwrap();
+        let mut de = Deserializer::from_fs(test_dir).bytes_repr(BytesRepr::Hex);
+        assert!(matches!(
+            Key::deserialize(&mut de),
+            Err(DeError::InvalidHex(ref s, _)) if s == invalid
+        ));
+    }
+    std::fs::remove_dir_all(test_dir).unwrap();
+}
+
+#[test]
+fn typed_leaves() {
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Level {
+        Low,
+        High(u8),
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Record {
+        text: String,
+        number: u32,
+        negative: i16,
+        ratio: f64,
+        flag: bool,
+        initial: char,
+        level: Level,
+        other_level: Level,
+        empty: Option<String>,
+        missing: Option<String>,
+    }
+
+    let test_dir = "/tmp/.test-roundtrip-typed-leaves";
+    let _ = std::fs::remove_dir_all(test_dir);
+    let value = Record {
+        text: "42".into(),
+        number: 42,
+        negative: -7,
+        ratio: 0.5,
+        flag: true,
+        initial: 'x',
+        level: Level::Low,
+        other_level: Level::High(3),
+        empty: Some(String::new()),
+        missing: None,
+    };
+    let mut ser = Serializer::new(test_dir)
+        .unwrap()
+        .option_repr(OptionRepr::EmptyFile)
+        .typed_leaves(true);
+    value.serialize(&mut ser).unwrap();
+    let read = |name: &str| std::fs::read(format!("{}/{}", test_dir, name)).unwrap();
+    assert_eq!(read("text"), b"s42");
+    assert_eq!(read("number"), b"u42");
+    assert_eq!(read("negative"), b"i-7");
+    // `Some("")` no longer looks like an empty `None`
+    assert_eq!(read("empty"), b"s");
+    assert_eq!(read("missing"), b"");
+
+    let de = || {
+        Deserializer::from_fs(test_dir)
+            .option_repr(OptionRepr::EmptyFile)
+            .typed_leaves(true)
+    };
+    pretty_assertions::assert_eq!(Record::deserialize(&mut de()).unwrap(), value);
+
+    // Without a type hint, the prefix tells the string `"42"` from the number `42`
+    let dynamic = serde_json::Value::deserialize(&mut de()).unwrap();
+    assert_eq!(dynamic["text"], serde_json::json!("42"));
+    assert_eq!(dynamic["number"], serde_json::json!(42));
+    assert_eq!(dynamic["negative"], serde_json::json!(-7));
+    assert_eq!(dynamic["ratio"], serde_json::json!(0.5));
+    assert_eq!(dynamic["flag"], serde_json::json!(true));
+    assert_eq!(dynamic["level"], serde_json::json!("Low"));
+    assert_eq!(dynamic["other_level"], serde_json::json!({ "High": 3 }));
+    std::fs::remove_dir_all(test_dir).unwrap();
+}
+
+#[test]
+fn enum_keys() {
+    #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+    enum Color {
+        Red,
+        Green,
+        Blue,
+    }
+
+    let test_dir = "/tmp/.test-roundtrip-enum-keys";
+    let value: BTreeMap<Color, String> = [
+        (Color::Red, "stop".into()),
+        (Color::Green, "go".into()),
+        (Color::Blue, "calm".into()),
+    ]
+    .into();
+    roundtrip(test_dir, &value);
+
+    // Unit variants are keyed by name
+    serde_fs::to_fs(&value, test_dir).unwrap();
+    assert_eq!(
+        std::fs::read_to_string(format!("{}/Green", test_dir)).unwrap(),
+        "go"
+    );
+    std::fs::remove_dir_all(test_dir).unwrap();
+
+    #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+    enum Shape {
+        Circle(u32),
+    }
+    let err = serde_fs::to_fs(&BTreeMap::from([(Shape::Circle(1), 1)]), test_dir).unwrap_err();
+    assert!(matches!(
+        err,
+        serde_fs::SerError::UnsupportedKey("newtype variants")
+    ));
+    let _ = std::fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn paths() {
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Paths {
+        relative: std::path::PathBuf,
+        absolute: std::path::PathBuf,
+    }
+
+    let test_dir = "/tmp/.test-roundtrip-paths";
+    let value = Paths {
+        relative: "config/app.toml".into(),
+        absolute: "/etc/app".into(),
+    };
+    roundtrip(test_dir, &value);
+
+    // A path is a single leaf holding its string, not a directory per component
+    serde_fs::to_fs(&value, test_dir).unwrap();
+    assert_eq!(
+        std::fs::read_to_string(format!("{}/relative", test_dir)).unwrap(),
+        "config/app.toml"
+    );
+    std::fs::remove_dir_all(test_dir).unwrap();
+
+    // serde refuses to serialize paths that are not UTF-8, which surfaces as a plain error
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        let invalid = Paths {
+            relative: std::ffi::OsStr::from_bytes(b"bad\xff").into(),
+            absolute: "/".into(),
+        };
+        let err = serde_fs::to_fs(&invalid, test_dir).unwrap_err();
+        assert!(matches!(err, serde_fs::SerError::Serde(_)), "{:?}", err);
+        let _ = std::fs::remove_dir_all(test_dir);
+    }
+}
+
+#[test]
+fn ordered_maps() {
+    /// Keeps entries in insertion order, like `IndexMap`
+    #[derive(Debug, PartialEq)]
+    struct InsertionOrdered(Vec<(String, u32)>);
+
+    impl Serialize for InsertionOrdered {
+        fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.collect_map(self.0.iter().map(|(k, v)| (k, v)))
+        }
+    }
+
+    impl<'de> Deserialize<'de> for InsertionOrdered {
+        fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            struct Visitor;
+            impl<'de> serde::de::Visitor<'de> for Visitor {
+                type Value = InsertionOrdered;
+
+                fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                    f.write_str("a map")
+                }
+
+                fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+                where
+                    A: serde::de::MapAccess<'de>,
+                {
+                    let mut entries = Vec::new();
+                    while let Some(entry) = map.next_entry()? {
+                        entries.push(entry);
+                    }
+                    Ok(InsertionOrdered(entries))
+                }
+            }
+            deserializer.deserialize_map(Visitor)
+        }
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Config {
+        name: String,
+        steps: InsertionOrdered,
+    }
+
+    let test_dir = "/tmp/.test-roundtrip-ordered-maps";
+    let _ = std::fs::remove_dir_all(test_dir);
+    let value = Config {
+        name: "pipeline".into(),
+        steps: InsertionOrdered(
+            ["zeta", "alpha", "mid", "10", "2", "json"]
+                .iter()
+                .enumerate()
+                .map(|(i, k)| (k.to_string(), i as u32))
+                .collect(),
+        ),
+    };
+    let mut ser = Serializer::new(test_dir).unwrap().ordered_maps(true);
+    value.serialize(&mut ser).unwrap();
+    assert_eq!(
+        std::fs::read_to_string(format!("{}/steps/1.alpha", test_dir)).unwrap(),
+        "1"
+    );
+
+    let mut de = Deserializer::from_fs(test_dir).ordered_maps(true);
+    pretty_assertions::assert_eq!(Config::deserialize(&mut de).unwrap(), value);
+    std::fs::remove_dir_all(test_dir).unwrap();
+
+    // sorted maps are numbered in sorted order, whatever order the map yields its entries in
+    let keys = ["c", "e", "d", "b", "a"];
+    let unordered: std::collections::HashMap<String, u32> =
+        keys.iter().map(|k| (k.to_string(), 1)).collect();
+    let mut ser = Serializer::new(test_dir)
+        .unwrap()
+        .sort_maps(true)
+        .ordered_maps(true);
+    unordered.serialize(&mut ser).unwrap();
+    let mut de = Deserializer::from_fs(test_dir).ordered_maps(true);
+    let read = InsertionOrdered::deserialize(&mut de).unwrap();
+    let read: Vec<_> = read.0.iter().map(|(k, _)| k.as_str()).collect();
+    assert_eq!(read, ["a", "b", "c", "d", "e"]);
+    std::fs::remove_dir_all(test_dir).unwrap();
+
+    let nested: std::collections::HashMap<String, Vec<u32>> =
+        keys.iter().map(|k| (k.to_string(), vec![7])).collect();
+    let mut ser = Serializer::new(test_dir)
+        .unwrap()
+        .sort_maps(true)
+        .ordered_maps(true);
+    nested.serialize(&mut ser).unwrap();
+    assert_eq!(
+        std::fs::read_to_string(format!("{}/2.c/0", test_dir)).unwrap(),
+        "7"
+    );
+    std::fs::remove_dir_all(test_dir).unwrap();
+}
+
+#[test]
+fn as_file() {
+    use serde_fs::{AsFile, Json};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Inner {
+        id: u32,
+        tags: Vec<String>,
+        nested: BTreeMap<String, u8>,
+        missing: Option<u8>,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Outer {
+        plain: Inner,
+        packed: AsFile<Json, Inner>,
+        packed_list: Vec<AsFile<Json, Inner>>,
+    }
+
+    let inner = || Inner {
+        id: 1,
+        tags: vec!["a".into(), "b".into()],
+        nested: [("x".into(), 1), ("y".into(), 2)].into(),
+        missing: None,
+    };
+    let value = Outer {
+        plain: inner(),
+        packed: AsFile::new(inner()),
+        packed_list: vec![inner().into()],
+    };
+    let test_dir = "/tmp/.test-roundtrip-as-file";
+    roundtrip(test_dir, &value);
+
+    serde_fs::to_fs(&value, test_dir).unwrap();
+    let packed = std::fs::read_to_string(format!("{}/packed", test_dir)).unwrap();
+    assert_eq!(
+        packed,
+        r#"{"id":1,"tags":["a","b"],"nested":{"x":1,"y":2},"missing":null}"#
+    );
+    assert!(std::fs::metadata(format!("{}/packed_list/0", test_dir))
+        .unwrap()
+        .is_file());
+    assert!(std::fs::metadata(format!("{}/plain", test_dir))
+        .unwrap()
+        .is_dir());
+    std::fs::remove_dir_all(test_dir).unwrap();
+}
+
+#[test]
+fn internally_tagged_enums() {
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Endpoint {
+        host: String,
+        path: String,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    #[serde(tag = "type")]
+    enum Source {
+        Local { path: String, tags: Vec<String> },
+        Remote(Endpoint),
+        Stdin,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    #[serde(tag = "kind")]
+    enum Limit {
+        Rate {
+            per_second: u32,
+            burst: f64,
+            strict: bool,
+        },
+        Off,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Config {
+        sources: Vec<Source>,
+        limit: Limit,
+    }
+
+    let sources = || {
+        vec![
+            Source::Local {
+                path: "/var/log".into(),
+                tags: vec!["a".into(), "b".into()],
+            },
+            Source::Remote(Endpoint {
+                host: "example.com".into(),
+                path: "/logs".into(),
+            }),
+            Source::Stdin,
+        ]
+    };
+    let test_dir = "/tmp/.test-roundtrip-tagged";
+    roundtrip(
+        test_dir,
+        &Config {
+            sources: sources(),
+            limit: Limit::Off,
+        },
+    );
+
+    let _ = std::fs::remove_dir_all(test_dir);
+    let value = Config {
+        sources: sources(),
+        limit: Limit::Rate {
+            per_second: 100,
+            burst: 1.5,
+            strict: true,
+        },
+    };
+    serde_fs::to_fs(&value, test_dir).unwrap();
+    let source_type = std::fs::read_to_string(format!("{}/sources/0/type", test_dir)).unwrap();
+    assert_eq!(source_type, "Local");
+    let per_second = std::fs::read_to_string(format!("{}/limit/per_second", test_dir)).unwrap();
+    assert_eq!(per_second, "100");
+
+    let mut de = Deserializer::from_fs(test_dir).infer_types(true);
+    pretty_assertions::assert_eq!(Config::deserialize(&mut de).unwrap(), value);
+    std::fs::remove_dir_all(test_dir).unwrap();
+}
+
+#[test]
+fn adjacently_tagged_enums() {
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    #[serde(tag = "t", content = "c")]
+    enum Shape {
+        Circle(f64),
+        Rect { width: u32, height: u32 },
+        Points(Vec<(i8, i8)>),
+        Empty,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Drawing {
+        shapes: Vec<Shape>,
+        background: Shape,
+    }
+
+    let test_dir = "/tmp/.test-roundtrip-adjacently-tagged";
+    let value = Drawing {
+        shapes: vec![
+            Shape::Circle(2.5),
+            Shape::Rect {
+                width: 3,
+                height: 4,
+            },
+            Shape::Points(vec![(0, 1), (-2, 3)]),
+            Shape::Empty,
+        ],
+        background: Shape::Rect {
+            width: 10,
+            height: 20,
+        },
+    };
+    roundtrip(test_dir, &value);
+
+    serde_fs::to_fs(&value, test_dir).unwrap();
+    let read = |path: &str| std::fs::read_to_string(format!("{}/{}", test_dir, path)).unwrap();
+    assert_eq!(read("shapes/0/t"), "Circle");
+    assert_eq!(read("shapes/0/c"), "2.5");
+    assert_eq!(read("background/t"), "Rect");
+    assert_eq!(read("background/c/width"), "10");
+    std::fs::remove_dir_all(test_dir).unwrap();
+}
+
+#[test]
+fn untagged_enums() {
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    #[serde(untagged)]
+    enum Dependency {
+        Version(String),
+        Detailed { version: String, path: String },
+        Features(Vec<String>),
+    }
+
+    let test_dir = "/tmp/.test-roundtrip-untagged";
+    let value: BTreeMap<String, Dependency> = [
+        ("serde".into(), Dependency::Version("1.0".into())),
+        (
+            "local".into(),
+            Dependency::Detailed {
+                version: "0.1".into(),
+                path: "../local".into(),
+            },
+        ),
+        (
+            "tokio".into(),
+            Dependency::Features(vec!["rt".into(), "macros".into()]),
+        ),
+    ]
+    .into();
+    roundtrip(test_dir, &value);
+
+    // A shape that matches no variant is an error
+    let _ = std::fs::remove_dir_all(test_dir);
+    std::fs::create_dir_all(format!("{}/broken/nested", test_dir)).unwrap();
+    std::fs::write(format!("{}/broken/nested/x", test_dir), "1").unwrap();
+    let mut de = Deserializer::from_fs(format!("{}/broken", test_dir));
+    assert!(Dependency::deserialize(&mut de).is_err());
+    std::fs::write(format!("{}/ok", test_dir), "2.0").unwrap();
+    let mut de = Deserializer::from_fs(format!("{}/ok", test_dir));
+    assert_eq!(
+        Dependency::deserialize(&mut de).unwrap(),
+        Dependency::Version("2.0".into())
+    );
+    std::fs::remove_dir_all(test_dir).unwrap();
+}
+
+#[test]
+fn tuple_map_values() {
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Point(i32, i32);
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Layout {
+        sizes: BTreeMap<String, (u8, u8)>,
+        points: BTreeMap<String, Point>,
+        nested: BTreeMap<String, BTreeMap<String, (String, bool)>>,
+        // a field after the maps, to catch a path left pushed by the tuples
+        after: u8,
+    }
+
+    let test_dir = "/tmp/.test-roundtrip-tuple-map-values";
+    let value = Layout {
+        sizes: [("small".into(), (1, 2)), ("large".into(), (200, 255))].into(),
+        points: [
+            ("origin".into(), Point(0, 0)),
+            ("corner".into(), Point(-3, 4)),
+        ]
+        .into(),
+        nested: [(
+            "outer".into(),
+            [("inner".into(), ("x".into(), true))].into(),
+        )]
+        .into(),
+        after: 9,
+    };
+    roundtrip(test_dir, &value);
+
+    serde_fs::to_fs(&value, test_dir).unwrap();
+    let read = |path: &str| std::fs::read_to_string(format!("{}/{}", test_dir, path)).unwrap();
+    assert_eq!(read("sizes/large/1"), "255");
+    assert_eq!(read("points/corner/0"), "-3");
+    assert_eq!(read("nested/outer/inner/0"), "x");
+    std::fs::remove_dir_all(test_dir).unwrap();
+}
+
+#[test]
+fn results() {
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Outcomes {
+        ok: Result<String, u32>,
+        err: Result<String, u32>,
+        nested: Result<Vec<u8>, BTreeMap<String, String>>,
+        list: Vec<Result<u8, String>>,
+    }
+
+    let test_dir = "/tmp/.test-roundtrip-results";
+    let value = Outcomes {
+        ok: Ok("done".into()),
+        err: Err(404),
+        nested: Err([("reason".into(), "timeout".into())].into()),
+        list: vec![Ok(1), Err("bad".into())],
+    };
+    roundtrip(test_dir, &value);
+
+    serde_fs::to_fs(&value, test_dir).unwrap();
+    let read = |path: &str| std::fs::read_to_string(format!("{}/{}", test_dir, path)).unwrap();
+    assert_eq!(read("ok/Ok"), "done");
+    assert_eq!(read("err/Err"), "404");
+    assert_eq!(read("nested/Err/reason"), "timeout");
+    assert_eq!(read("list/1/Err"), "bad");
+    std::fs::remove_dir_all(test_dir).unwrap();
+}
+
+#[test]
+fn padded_int_keys() {
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Padded {
+        signed: BTreeMap<i32, String>,
+        unsigned: BTreeMap<u64, u8>,
+    }
+
+    let test_dir = "/tmp/.test-roundtrip-padded-int-keys";
+    let _ = std::fs::remove_dir_all(test_dir);
+    let value = Padded {
+        signed: [
+            (-1234567, "very low".into()),
+            (-7, "low".into()),
+            (0, "zero".into()),
+            (42, "high".into()),
+        ]
+        .into(),
+        unsigned: [(0, 0), (9, 9), (u64::MAX, 1)].into(),
+    };
+    let mut ser = Serializer::new(test_dir).unwrap().pad_int_keys(4);
+    value.serialize(&mut ser).unwrap();
+    let mut names: Vec<_> = std::fs::read_dir(format!("{}/signed", test_dir))
+        .unwrap()
+        .map(|e| e.unwrap().file_name().into_string().unwrap())
+        .collect();
+    names.sort();
+    assert_eq!(names, ["-0007", "-1234567", "0000", "0042"]);
+
+    let mut de = Deserializer::from_fs(test_dir).padded_int_keys(true);
+    pretty_assertions::assert_eq!(Padded::deserialize(&mut de).unwrap(), value);
+    // without the option, padded keys are not canonical integers
+    let mut de = Deserializer::from_fs(test_dir);
+    assert!(Padded::deserialize(&mut de).is_err());
+    std::fs::remove_dir_all(test_dir).unwrap();
+}
+
+#[test]
+fn json_keys() {
+    #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+    struct Cell {
+        row: u8,
+        col: u8,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Grid {
+        by_pair: BTreeMap<(u8, u8), String>,
+        by_cell: BTreeMap<Cell, bool>,
+        by_path: BTreeMap<Vec<String>, u8>,
+        plain: BTreeMap<u8, u8>,
+    }
+
+    let test_dir = "/tmp/.test-roundtrip-json-keys";
+    let _ = std::fs::remove_dir_all(test_dir);
+    let value = Grid {
+        by_pair: [((0, 1), "a".into()), ((2, 3), "b".into())].into(),
+        by_cell: [(Cell { row: 1, col: 2 }, true)].into(),
+        by_path: [(vec!["usr/bin".into(), "100%".into()], 1)].into(),
+        plain: [(1, 2)].into(),
+    };
+    assert!(matches!(
+        serde_fs::to_fs(&value, test_dir),
+        Err(serde_fs::SerError::UnsupportedKey(_))
+    ));
+
+    let _ = std::fs::remove_dir_all(test_dir);
+    let mut ser = Serializer::new(test_dir).unwrap().json_keys(true);
+    value.serialize(&mut ser).unwrap();
+    let exists = |path: &str| std::fs::metadata(format!("{}/{}", test_dir, path)).is_ok();
+    assert!(exists("by_pair/[2,3]"));
+    assert!(exists(r#"by_cell/{"row":1,"col":2}"#));
+    assert!(exists(r#"by_path/["usr%2Fbin","100%25"]"#));
+    assert!(exists("plain/1"));
+
+    let read: Grid = serde_fs::from_fs(test_dir).unwrap();
+    pretty_assertions::assert_eq!(read, value);
+    std::fs::remove_dir_all(test_dir).unwrap();
+}
+
+#[test]
+fn git_helpers() {
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Asset {
+        name: String,
+        #[serde(with = "serde_bytes")]
+        data: Vec<u8>,
+        #[serde(flatten)]
+        extra: BTreeMap<String, String>,
+    }
+
+    let test_dir = "/tmp/.test-roundtrip-git-helpers";
+    let _ = std::fs::remove_dir_all(test_dir);
+    let value = Asset {
+        name: "logo".into(),
+        data: vec![0x89, b'P', b'N', b'G', 0],
+        extra: BTreeMap::new(),
+    };
+    let mut ser = Serializer::new(test_dir).unwrap().git_helpers(true);
+    value.serialize(&mut ser).unwrap();
+    ser.finish().unwrap();
+
+    let attributes = std::fs::read_to_string(format!("{}/.gitattributes", test_dir)).unwrap();
+    assert!(attributes.lines().any(|line| line == "/data binary"));
+    assert!(!attributes.contains("/name"));
+    let ignore = std::fs::read_to_string(format!("{}/.gitignore", test_dir)).unwrap();
+    assert!(ignore.lines().any(|line| line == ".*.tmp.*"));
+
+    // the flattened map would pick the helper files up if they were not skipped
+    let read: Asset = serde_fs::from_fs(test_dir).unwrap();
+    pretty_assertions::assert_eq!(read, value);
+    std::fs::remove_dir_all(test_dir).unwrap();
+
+    // ordered map entries are renamed after they are written, their binary leaves with them
+    let mut assets = std::collections::HashMap::new();
+    assets.insert("k".to_owned(), serde_bytes::ByteBuf::from(vec![0, 1]));
+    assets.insert("b".to_owned(), serde_bytes::ByteBuf::from(vec![2]));
+    let nested: BTreeMap<String, Asset> = [("n".to_owned(), value)].into();
+    let mut ser = Serializer::new(test_dir)
+        .unwrap()
+        .sort_maps(true)
+        .ordered_maps(true)
+        .git_helpers(true);
+    (&assets, &nested).serialize(&mut ser).unwrap();
+    ser.finish().unwrap();
+    let attributes = std::fs::read_to_string(format!("{}/.gitattributes", test_dir)).unwrap();
+    let mut binary: Vec<_> = attributes.lines().skip(1).collect();
+    binary.sort();
+    assert_eq!(
+        binary,
+        ["/0/0.b binary", "/0/1.k binary", "/1/0.n/0.data binary"]
+    );
+    assert!(std::path::Path::new(test_dir).join("0/1.k").exists());
+    // the flattened struct is written as a map too
+    assert!(std::path::Path::new(test_dir).join("1/0.n/0.data").exists());
+
+    let mut de = Deserializer::from_fs(test_dir).ordered_maps(true);
+    let read = <(
+        BTreeMap<String, serde_bytes::ByteBuf>,
+        BTreeMap<String, Asset>,
+    )>::deserialize(&mut de)
+    .unwrap();
+    assert_eq!(
+        read.0
+            .into_iter()
+            .collect::<std::collections::HashMap<_, _>>(),
+        assets
+    );
+    pretty_assertions::assert_eq!(read.1, nested);
+    std::fs::remove_dir_all(test_dir).unwrap();
+}
+
+#[test]
+fn optional_vecs() {
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Data {
+        part1: Option<Vec<u32>>,
+        part2: Option<Vec<u32>>,
+        part3: Option<Vec<u32>>,
+    }
+
+    let test_dir = "/tmp/.test-roundtrip-optional-vecs";
+    let value = Data {
+        part1: None,
+        part2: Some(vec![]),
+        part3: Some(vec![1, 2]),
+    };
+    let reprs = [
+        OptionRepr::MissingFile,
+        OptionRepr::EmptyFile,
+        OptionRepr::SentinelFile("null".into()),
+    ];
+    for repr in reprs {
+        let _ = std::fs::remove_dir_all(test_dir);
+        let mut ser = Serializer::new(test_dir).unwrap().option_repr(repr.clone());
+        value.serialize(&mut ser).unwrap();
+        let metadata = |path: &str| std::fs::metadata(format!("{}/{}", test_dir, path));
+        // `Some(vec![])` is an empty directory, never confused with the missing or file `None`
+        assert_eq!(
+            metadata("part1").is_ok(),
+            repr != OptionRepr::MissingFile,
+            "{:?}",
+            repr
+        );
+        assert!(metadata("part2").unwrap().is_dir());
+        assert_eq!(
+            std::fs::read_dir(format!("{}/part2", test_dir))
+                .unwrap()
+                .count(),
+            0
+        );
+        assert!(metadata("part3/1").unwrap().is_file());
+
+        let mut de = Deserializer::from_fs(test_dir).option_repr(repr);
+        assert_eq!(Data::deserialize(&mut de).unwrap(), value);
+    }
+    std::fs::remove_dir_all(test_dir).unwrap();
+}
+
+#[test]
+fn padded_numbers() {
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Row {
+        id: u32,
+        delta: i16,
+        ratio: f64,
+        wide: u64,
+        name: String,
+    }
+
+    let test_dir = "/tmp/.test-roundtrip-padded-numbers";
+    let value = Row {
+        id: 42,
+        delta: -7,
+        ratio: 0.25,
+        wide: 12345678,
+        name: "  kept  ".into(),
+    };
+    let aligned = [
+        (Align::Right, "    42", "    -7"),
+        (Align::Left, "42    ", "-7    "),
+    ];
+    for (align, id, delta) in aligned {
+        let _ = std::fs::remove_dir_all(test_dir);
+        let mut ser = Serializer::new(test_dir)
+            .unwrap()
+            .pad_numbers(6, ' ', align);
+        value.serialize(&mut ser).unwrap();
+        let read = |path: &str| std::fs::read_to_string(format!("{}/{}", test_dir, path)).unwrap();
+        assert_eq!(read("id"), id);
+        assert_eq!(read("delta"), delta);
+        // wider numbers and strings are left alone
+        assert_eq!(read("wide"), "12345678");
+        assert_eq!(read("name"), "  kept  ");
+
+        let mut de = Deserializer::from_fs(test_dir).padded_numbers(' ');
+        assert_eq!(Row::deserialize(&mut de).unwrap(), value);
+        // without the option the padding is not a number
+        let mut de = Deserializer::from_fs(test_dir);
+        assert!(matches!(
+            Row::deserialize(&mut de),
+            Err(DeError::ParseError(_))
+        ));
+    }
+    std::fs::remove_dir_all(test_dir).unwrap();
+}
+
+#[test]
+fn enums_by_index() {
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Shape {
+        Empty,
+        Circle(u32),
+        Line(i32, i32),
+        Rect { w: u32, h: u32 },
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Shapes {
+        unit: Shape,
+        newtype: Shape,
+        tuple: Shape,
+        strukt: Shape,
+    }
+
+    let test_dir = "/tmp/.test-roundtrip-enums-by-index";
+    let _ = std::fs::remove_dir_all(test_dir);
+    let value = Shapes {
+        unit: Shape::Empty,
+        newtype: Shape::Circle(3),
+        tuple: Shape::Line(-1, 1),
+        strukt: Shape::Rect { w: 4, h: 5 },
+    };
+    let mut ser = Serializer::new(test_dir)
+        .unwrap()
+        .enum_repr(EnumRepr::ByIndex);
+    value.serialize(&mut ser).unwrap();
+    let read = |path: &str| std::fs::read_to_string(format!("{}/{}", test_dir, path)).unwrap();
+    assert_eq!(read("unit"), "0");
+    assert_eq!(read("newtype/1"), "3");
+    assert_eq!(read("tuple/2/0"), "-1");
+    assert_eq!(read("strukt/3/h"), "5");
+
+    let mut de = Deserializer::from_fs(test_dir).enum_repr(EnumRepr::ByIndex);
+    pretty_assertions::assert_eq!(Shapes::deserialize(&mut de).unwrap(), value);
+
+    // indices past the last variant are not silently mapped
+    std::fs::write(format!("{}/unit", test_dir), "4").unwrap();
+    let mut de = Deserializer::from_fs(test_dir).enum_repr(EnumRepr::ByIndex);
+    assert!(matches!(
+        Shapes::deserialize(&mut de),
+        Err(DeError::UnknownVariantIndex(ref index, _)) if index == "4"
+    ));
+    std::fs::remove_dir_all(test_dir).unwrap();
+}
+
+#[test]
+fn seq_base() {
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Lines {
+        lines: Vec<String>,
+        empty: Vec<u8>,
+        pair: (u8, u8),
+    }
+
+    let test_dir = "/tmp/.test-roundtrip-seq-base";
+    let _ = std::fs::remove_dir_all(test_dir);
+    let value = Lines {
+        lines: vec!["a".into(), "b".into()],
+        empty: vec![],
+        pair: (1, 2),
+    };
+    let mut ser = Serializer::new(test_dir).unwrap().seq_base(1);
+    value.serialize(&mut ser).unwrap();
+    let exists = |path: &str| std::fs::metadata(format!("{}/{}", test_dir, path)).is_ok();
+    assert!(exists("lines/1") && exists("lines/2") && !exists("lines/0"));
+    assert!(exists("empty"));
+    let mut de = Deserializer::from_fs(test_dir).seq_base(1);
+    pretty_assertions::assert_eq!(Lines::deserialize(&mut de).unwrap(), value);
+
+    // a tree built by hand, like a script numbering files from 1 would
+    std::fs::remove_dir_all(test_dir).unwrap();
+    std::fs::create_dir_all(test_dir).unwrap();
+    for (i, line) in ["first", "second", "third"].iter().enumerate() {
+        std::fs::write(format!("{}/{}", test_dir, i + 1), line).unwrap();
+    }
+    let mut de = Deserializer::from_fs(test_dir).seq_base(1);
+    assert_eq!(
+        Vec::<String>::deserialize(&mut de).unwrap(),
+        ["first", "second", "third"]
+    );
+    let mut de = Deserializer::from_fs(test_dir).seq_base(1);
+    assert_eq!(
+        serde_json::Value::deserialize(&mut de).unwrap(),
+        serde_json::json!(["first", "second", "third"])
+    );
+    // read from 0 the same tree has no elements
+    let read: Vec<String> = serde_fs::from_fs(test_dir).unwrap();
+    assert!(read.is_empty());
+    std::fs::remove_dir_all(test_dir).unwrap();
+}
+
+#[test]
+fn pairs_and_maps() {
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Scores {
+        pairs: Vec<(String, u32)>,
+        map: BTreeMap<String, u32>,
+    }
+
+    let test_dir = "/tmp/.test-roundtrip-pairs-and-maps";
+    let _ = std::fs::remove_dir_all(test_dir);
+    let value = Scores {
+        // out of order and repeated keys, which only a sequence keeps
+        pairs: vec![("b".into(), 2), ("a".into(), 1), ("b".into(), 3)],
+        map: [("a".into(), 1), ("b".into(), 2)].into(),
+    };
+    serde_fs::to_fs(&value, test_dir).unwrap();
+    let read = |path: &str| std::fs::read_to_string(format!("{}/{}", test_dir, path)).unwrap();
+    assert_eq!(read("pairs/0/0"), "b");
+    assert_eq!(read("pairs/0/1"), "2");
+    assert_eq!(read("pairs/2/0"), "b");
+    assert_eq!(read("pairs/2/1"), "3");
+    assert!(std::fs::metadata(format!("{}/pairs/b", test_dir)).is_err());
+    assert_eq!(read("map/a"), "1");
+    assert_eq!(read("map/b"), "2");
+
+    let actual: Scores = serde_fs::from_fs(test_dir).unwrap();
+    pretty_assertions::assert_eq!(actual, value);
+    // read without a type, the pairs stay a sequence of 2 element sequences
+    let mut de = Deserializer::from_fs(format!("{}/pairs", test_dir));
+    assert_eq!(
+        serde_json::Value::deserialize(&mut de).unwrap(),
+        serde_json::json!([["b", "2"], ["a", "1"], ["b", "3"]])
+    );
+    std::fs::remove_dir_all(test_dir).unwrap();
+}
+
+#[test]
+fn index_files() {
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Source {
+        Git { url: String, rev: String },
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Package {
+        name: String,
+        deps: BTreeMap<String, Source>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        license: Option<String>,
+    }
+
+    let test_dir = "/tmp/.test-roundtrip-index-files";
+    let _ = std::fs::remove_dir_all(test_dir);
+    let value = Package {
+        name: "app".into(),
+        deps: [(
+            "serde".into(),
+            Source::Git {
+                url: "https://example.com/serde".into(),
+                rev: "abc".into(),
+            },
+        )]
+        .into(),
+        license: None,
+    };
+    let mut ser = Serializer::new(test_dir).unwrap().write_indexes(true);
+    value.serialize(&mut ser).unwrap();
+    let read = |path: &str| std::fs::read_to_string(format!("{}/{}", test_dir, path)).unwrap();
+    // skipped fields are not listed
+    assert_eq!(read("_index"), "# struct Package\nname\ndeps\n");
+    assert_eq!(read("deps/_index"), "# map\nserde\n");
+    assert_eq!(
+        read("deps/serde/Git/_index"),
+        "# struct Source::Git\nurl\nrev\n"
+    );
+
+    let actual: Package = serde_fs::from_fs(test_dir).unwrap();
+    pretty_assertions::assert_eq!(actual, value);
+
+    let clash: BTreeMap<String, u8> = [("_index".into(), 1)].into();
+    let _ = std::fs::remove_dir_all(test_dir);
+    let mut ser = Serializer::new(test_dir).unwrap().write_indexes(true);
+    assert!(matches!(
+        clash.serialize(&mut ser),
+        Err(serde_fs::SerError::InvalidKey(ref key)) if key == "_index"
+    ));
+    let _ = std::fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn config_structs() {
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Settings {
+        name: Option<String>,
+        ports: Vec<u16>,
+    }
+
+    let test_dir = "/tmp/.test-roundtrip-config-structs";
+    let _ = std::fs::remove_dir_all(test_dir);
+    let value = Settings {
+        name: None,
+        ports: vec![80, 443],
+    };
+    let ser_config = SerConfig {
+        option_repr: OptionRepr::SentinelFile("-".into()),
+        seq_base: 1,
+        ..SerConfig::default()
+    };
+    let mut ser = Serializer::with_config(test_dir, ser_config.clone()).unwrap();
+    assert_eq!(ser.config(), &ser_config);
+    assert_eq!(ser.root(), std::path::Path::new(test_dir));
+    value.serialize(&mut ser).unwrap();
+    let read = |path: &str| std::fs::read_to_string(format!("{}/{}", test_dir, path)).unwrap();
+    assert_eq!(read("name"), "-");
+    assert_eq!(read("ports/1"), "80");
+
+    let de_config = DeConfig {
+        option_repr: ser_config.option_repr,
+        seq_base: 1,
+        ..DeConfig::default()
+    };
+    let mut de = Deserializer::with_config(serde_fs::StdFs, test_dir, de_config.clone());
+    assert_eq!(de.config(), &de_config);
+    let actual = Settings::deserialize(&mut de).unwrap();
+    pretty_assertions::assert_eq!(actual, value);
+    let _ = std::fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn non_finite_floats() {
+    #[derive(Serialize, Deserialize, Debug)]
+    struct Samples {
+        nan: f64,
+        payload: f64,
+        inf: f32,
+        neg_inf: f64,
+        finite: f32,
+    }
+
+    let test_dir = "/tmp/.test-roundtrip-non-finite-floats";
+    let _ = std::fs::remove_dir_all(test_dir);
+    let value = Samples {
+        nan: f64::NAN,
+        payload: f64::from_bits(0x7ff8_0000_0000_0001),
+        inf: f32::INFINITY,
+        neg_inf: f64::NEG_INFINITY,
+        finite: 1.5,
+    };
+    let mut ser = Serializer::new(test_dir)
+        .unwrap()
+        .float_policy(FloatPolicy::EncodeBits);
+    value.serialize(&mut ser).unwrap();
+    let read = |path: &str| std::fs::read_to_string(format!("{}/{}", test_dir, path)).unwrap();
+    assert_eq!(read("inf"), "0x7f800000");
+    assert_eq!(read("payload"), "0x7ff8000000000001");
+    assert_eq!(read("finite"), "1.5");
+
+    let actual: Samples = serde_fs::from_fs(test_dir).unwrap();
+    assert_eq!(actual.nan.to_bits(), value.nan.to_bits());
+    assert_eq!(actual.payload.to_bits(), value.payload.to_bits());
+    assert_eq!(actual.inf.to_bits(), value.inf.to_bits());
+    assert_eq!(actual.neg_inf.to_bits(), value.neg_inf.to_bits());
+    assert_eq!(actual.finite, value.finite);
+
+    let _ = std::fs::remove_dir_all(test_dir);
+    let mut ser = Serializer::new(test_dir)
+        .unwrap()
+        .float_policy(FloatPolicy::Reject);
+    assert!(matches!(
+        value.serialize(&mut ser),
+        Err(serde_fs::SerError::NonFiniteFloat(v)) if v.is_nan()
+    ));
+    let _ = std::fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn skip_none_entries() {
+    let test_dir = "/tmp/.test-roundtrip-skip-none-entries";
+    let _ = std::fs::remove_dir_all(test_dir);
+    let value: BTreeMap<String, Option<u32>> = [
+        ("a".to_owned(), Some(1)),
+        ("b".to_owned(), None),
+        ("c".to_owned(), Some(3)),
+        ("d".to_owned(), None),
+    ]
+    .into();
+    let mut ser = Serializer::new(test_dir)
+        .unwrap()
+        .option_repr(OptionRepr::SentinelFile("null".into()))
+        .skip_none_entries(true);
+    value.serialize(&mut ser).unwrap();
+    let dir = std::path::Path::new(test_dir);
+    assert!(dir.join("a").exists());
+    assert!(!dir.join("b").exists());
+    assert!(!dir.join("d").exists());
+
+    let actual: BTreeMap<String, Option<u32>> = serde_fs::from_fs(test_dir).unwrap();
+    let expected: BTreeMap<String, Option<u32>> =
+        value.into_iter().filter(|(_, v)| v.is_some()).collect();
+    pretty_assertions::assert_eq!(actual, expected);
+
+    // only the map entries themselves are skipped
+    let _ = std::fs::remove_dir_all(test_dir);
+    let nested: BTreeMap<String, Vec<Option<u32>>> = [("e".to_owned(), vec![None])].into();
+    let mut ser = Serializer::new(test_dir)
+        .unwrap()
+        .option_repr(OptionRepr::SentinelFile("null".into()))
+        .skip_none_entries(true);
+    nested.serialize(&mut ser).unwrap();
+    assert_eq!(
+        std::fs::read_to_string(dir.join("e").join("0")).unwrap(),
+        "null"
+    );
+    let _ = std::fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn nested_maps() {
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    enum Entry {
+        Raw { json_meta: BTreeMap<String, u8> },
+    }
+
+    let bytes: BTreeMap<u32, BTreeMap<String, Vec<u8>>> = [
+        (
+            1,
+            [
+                ("json".to_owned(), vec![1, 2]),
+                ("json_x".to_owned(), vec![]),
+            ]
+            .into(),
+        ),
+        (2, [("plain".to_owned(), vec![3])].into()),
+        (3, BTreeMap::new()),
+    ]
+    .into();
+    roundtrip("/tmp/.test-roundtrip-nested-maps-bytes", &bytes);
+
+    let strings: BTreeMap<String, BTreeMap<i64, BTreeMap<bool, String>>> = [
+        (
+            "json".to_owned(),
+            [(-1, [(true, "{}".to_owned())].into())].into(),
+        ),
+        (
+            "b".to_owned(),
+            [(7, [(false, "json".to_owned())].into())].into(),
+        ),
+    ]
+    .into();
+    roundtrip("/tmp/.test-roundtrip-nested-maps-strings", &strings);
+
+    // struct variant fields named `json*` still hold JSON, unlike map keys
+    let entries: BTreeMap<String, Entry> = [(
+        "json".to_owned(),
+        Entry::Raw {
+            json_meta: [("k".to_owned(), 1)].into(),
+        },
+    )]
+    .into();
+    let test_dir = "/tmp/.test-roundtrip-nested-maps-variant";
+    let _ = std::fs::remove_dir_all(test_dir);
+    serde_fs::to_fs(&entries, test_dir).unwrap();
+    assert_eq!(
+        std::fs::read_to_string(format!("{}/json/Raw/json_meta", test_dir)).unwrap(),
+        r#"{"k":1}"#
+    );
+    let actual: BTreeMap<String, Entry> = serde_fs::from_fs(test_dir).unwrap();
+    pretty_assertions::assert_eq!(actual, entries);
+    let _ = std::fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn collapse_below_depth() {
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Level1 {
+        name: String,
+        level2: BTreeMap<String, Level2>,
+    }
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Level2 {
+        id: u32,
+        level3: Level3,
+    }
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Level3 {
+        tags: Vec<String>,
+        level4: Option<Level4>,
+    }
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    enum Level4 {
+        Leaf(f64),
+    }
+
+    let test_dir = "/tmp/.test-roundtrip-collapse-below-depth";
+    let _ = std::fs::remove_dir_all(test_dir);
+    let value = Level1 {
+        name: "top".into(),
+        level2: [(
+            "a".into(),
+            Level2 {
+                id: 7,
+                level3: Level3 {
+                    tags: vec!["x".into(), "y".into()],
+                    level4: Some(Level4::Leaf(0.5)),
+                },
+            },
+        )]
+        .into(),
+    };
+    let mut ser = Serializer::new(test_dir).unwrap().collapse_below_depth(2);
+    value.serialize(&mut ser).unwrap();
+    let read = |path: &str| std::fs::read_to_string(format!("{}/{}", test_dir, path)).unwrap();
+    assert_eq!(read("name"), "top");
+    assert_eq!(read("level2/a/id"), "7");
+    assert_eq!(
+        read("level2/a/level3"),
+        r#"{"tags":["x","y"],"level4":{"Leaf":0.5}}"#
+    );
+
+    let mut de = Deserializer::from_fs(test_dir).collapse_below_depth(2);
+    let actual = Level1::deserialize(&mut de).unwrap();
+    pretty_assertions::assert_eq!(actual, value);
+    let _ = std::fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn empty_containers_in_options() {
+    #[derive(Serialize, Deserialize, Debug, PartialEq, Default)]
+    struct SkipAll {
+        #[serde(skip)]
+        cache: u32,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        extra: Vec<u8>,
+    }
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct AllNone {
+        a: Option<u8>,
+    }
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Holder {
+        map: Option<BTreeMap<String, u8>>,
+        skipped: Option<SkipAll>,
+        nones: Option<AllNone>,
+        missing: Option<SkipAll>,
+    }
+
+    let test_dir = "/tmp/.test-roundtrip-empty-containers-in-options";
+    let value = Holder {
+        map: Some(BTreeMap::new()),
+        skipped: Some(SkipAll::default()),
+        nones: Some(AllNone { a: None }),
+        missing: None,
+    };
+    let _ = std::fs::remove_dir_all(test_dir);
+    serde_fs::to_fs(&value, test_dir).unwrap();
+    let dir = std::path::Path::new(test_dir);
+    assert!(dir.join("map").is_dir());
+    assert!(dir.join("skipped").is_dir());
+    assert!(dir.join("nones").is_dir());
+    assert!(!dir.join("missing").exists());
+    roundtrip(test_dir, &value);
+}
+
+#[test]
+fn delimited_leaves() {
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Post {
+        #[serde(with = "serde_fs::delimited")]
+        tags: Vec<String>,
+        #[serde(with = "serde_fs::delimited::With::<','>")]
+        ids: Vec<i32>,
+        #[serde(with = "serde_fs::delimited")]
+        empty: Vec<String>,
+    }
+
+    let test_dir = "/tmp/.test-roundtrip-delimited-leaves";
+    let value = Post {
+        tags: vec![
+            "rust".into(),
+            "two\nlines".into(),
+            "back\\slash".into(),
+            "".into(),
+        ],
+        ids: vec![-1, 20, 300],
+        empty: vec![],
+    };
+    let _ = std::fs::remove_dir_all(test_dir);
+    serde_fs::to_fs(&value, test_dir).unwrap();
+    let read = |path: &str| std::fs::read_to_string(format!("{}/{}", test_dir, path)).unwrap();
+    assert_eq!(read("tags"), "rust\ntwo\\\nlines\nback\\\\slash\n\n");
+    assert_eq!(read("ids"), "-1,20,300,");
+    roundtrip(test_dir, &value);
+
+    // the last delimiter is optional in hand written leaves
+    std::fs::create_dir_all(test_dir).unwrap();
+    std::fs::write(format!("{}/tags", test_dir), "a\nb").unwrap();
+    std::fs::write(format!("{}/ids", test_dir), "1,2").unwrap();
+    std::fs::write(format!("{}/empty", test_dir), "").unwrap();
+    let actual: Post = serde_fs::from_fs(test_dir).unwrap();
+    assert_eq!(actual.tags, ["a", "b"]);
+    assert_eq!(actual.ids, [1, 2]);
+    let _ = std::fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn case_collisions() {
+    #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord)]
+    enum Color {
+        Red,
+        DarkBlue,
+    }
+
+    let test_dir = "/tmp/.test-roundtrip-case-collisions";
+    let clash: BTreeMap<String, u8> = [("Key".to_owned(), 1), ("key".to_owned(), 2)].into();
+    for ser in [
+        Serializer::new(test_dir)
+            .unwrap()
+            .detect_case_collisions(true),
+        Serializer::new(test_dir).unwrap().lowercase_keys(true),
+    ] {
+        let _ = std::fs::remove_dir_all(test_dir);
+        let mut ser = ser;
+        assert!(matches!(
+            clash.serialize(&mut ser),
+            Err(serde_fs::SerError::CaseCollision { ref a, ref b }) if a == "Key" && b == "key"
+        ));
+        // the colliding entry is never written
+        let key = std::fs::read_to_string(std::path::Path::new(test_dir).join("key")).ok();
+        assert_ne!(key.as_deref(), Some("2"));
+    }
+
+    let _ = std::fs::remove_dir_all(test_dir);
+    let colors: BTreeMap<Color, u8> = [(Color::Red, 1), (Color::DarkBlue, 2)].into();
+    let mut ser = Serializer::new(test_dir).unwrap().lowercase_keys(true);
+    colors.serialize(&mut ser).unwrap();
+    assert!(std::path::Path::new(test_dir).join("darkblue").exists());
+    let mut de = Deserializer::from_fs(test_dir).lowercase_keys(true);
+    let actual = BTreeMap::<Color, u8>::deserialize(&mut de).unwrap();
+    pretty_assertions::assert_eq!(actual, colors);
+    let _ = std::fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn serialize_iter() {
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Reading {
+        id: u32,
+        value: f64,
+    }
+
+    let test_dir = "/tmp/.test-roundtrip-serialize-iter";
+    let _ = std::fs::remove_dir_all(test_dir);
+    let readings = (0..10_000).map(|id| Reading {
+        id,
+        value: f64::from(id) / 4.0,
+    });
+    serde_fs::serialize_iter_to_fs(readings, test_dir).unwrap();
+    let read = |path: &str| std::fs::read_to_string(format!("{}/{}", test_dir, path)).unwrap();
+    assert_eq!(read("0/id"), "0");
+    assert_eq!(read("9999/id"), "9999");
+    assert_eq!(read("9999/value"), "2499.75");
+    assert!(!std::path::Path::new(test_dir).join("10000").exists());
+
+    let actual: Vec<Reading> = serde_fs::from_fs(test_dir).unwrap();
+    assert_eq!(actual.len(), 10_000);
+    assert_eq!(
+        actual[1234],
+        Reading {
+            id: 1234,
+            value: 308.5
+        }
+    );
+    let _ = std::fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn transparent_newtypes() {
+    #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+    #[serde(transparent)]
+    struct Name(String);
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Point {
+        x: i32,
+        y: i32,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    #[serde(transparent)]
+    struct Origin(Point);
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    #[serde(transparent)]
+    struct Labels(BTreeMap<Name, u32>);
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    #[serde(transparent)]
+    struct Wrapped {
+        labels: Labels,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Scene {
+        name: Name,
+        origin: Origin,
+        labels: Labels,
+        nested: Wrapped,
+        origins: Vec<Origin>,
+    }
+
+    let labels = || Labels([(Name("a".into()), 1), (Name("b".into()), 2)].into());
+    let value = Scene {
+        name: Name("scene".into()),
+        origin: Origin(Point { x: 1, y: -2 }),
+        labels: labels(),
+        nested: Wrapped { labels: labels() },
+        origins: vec![Origin(Point { x: 3, y: 4 })],
+    };
+
+    // a transparent wrapper leaves no trace in the tree
+    let test_dir = "/tmp/.test-roundtrip-transparent";
+    let _ = std::fs::remove_dir_all(test_dir);
+    serde_fs::to_fs(&value, test_dir).unwrap();
+    let read = |path: &str| std::fs::read_to_string(format!("{}/{}", test_dir, path)).unwrap();
+    assert_eq!(read("name"), "scene");
+    assert_eq!(read("origin/y"), "-2");
+    assert_eq!(read("labels/b"), "2");
+    assert_eq!(read("nested/a"), "1");
+    assert_eq!(read("origins/0/x"), "3");
+    std::fs::remove_dir_all(test_dir).unwrap();
+
+    roundtrip(test_dir, &value);
+    roundtrip(test_dir, &Origin(Point { x: 5, y: 6 }));
+    roundtrip(test_dir, &labels());
+}